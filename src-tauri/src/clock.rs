@@ -0,0 +1,39 @@
+// the current time, for the timestamps on entries and history.
+// everything asks `now()` instead of the system clock, so tests can pin it with `set` and not depend on when they run.
+
+use std::{
+    cell::Cell,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+thread_local! {
+    // per thread, so tests running in parallel don't see each other's clock
+    static FIXED: Cell<Option<i64>> = const { Cell::new(None) };
+}
+
+/// The current time, in seconds since the Unix epoch.
+pub fn now() -> i64 {
+    FIXED.with(Cell::get).unwrap_or_else(|| {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|duration| duration.as_secs() as i64)
+            .unwrap_or(0)
+    })
+}
+
+/// Pins the clock of the current thread to `now`, or lets it run again if `None`.
+#[cfg(test)]
+pub fn set(now: Option<i64>) {
+    FIXED.with(|fixed| fixed.set(now));
+}
+
+#[cfg(test)]
+mod tests {
+    #[test]
+    fn set() {
+        super::set(Some(1_700_000_000));
+        assert_eq!(super::now(), 1_700_000_000);
+        super::set(None);
+        assert!(super::now() > 1_700_000_000);
+    }
+}
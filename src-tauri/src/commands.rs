@@ -0,0 +1,586 @@
+// these are the functions the frontend can `invoke()`.
+// each one is a thin wrapper around `db_ops`, the actual logic lives there.
+
+use std::time::{Duration, Instant};
+
+use tauri::{AppHandle, Manager, State};
+
+use crate::{
+    crypto,
+    db_ops::{self, crud_operations, MASTER_KEYWORD},
+    error::BackendError,
+    export, history,
+    import::{
+        self,
+        authenticator::{self, AuthenticatorFormat},
+        bitwarden,
+        generic_csv::{self, CsvColumn, CsvPreset, CsvPreview},
+        keepass, ImportReport, NameCollision,
+    },
+    kdbx::KdbxSettings,
+    organize,
+    otp::{self, OtpCode},
+    password::{
+        CustomField, Folder, HistoryEntry, ListOptions, PasswordField, PasswordInfo, PasswordPage,
+        SearchOptions, SearchResult, Tag,
+    },
+    state::{AppState, LockReason, ServiceAccess, Session, SessionConfig, VAULT_LOCKED_EVENT},
+};
+
+/// Runs `operation` with the key of the unlocked session, failing with `BackendError::Locked` if the vault is locked.
+/// If the session expired since the watcher last checked, the vault is locked (and the frontend told) first.
+fn with_key<F, TResult>(app_handle: &AppHandle, operation: F) -> Result<TResult, BackendError>
+where
+    F: FnOnce(&[u8; 32]) -> Result<TResult, BackendError>,
+{
+    let app_state: State<AppState> = app_handle.state();
+    if let Some(reason) = app_state.expire(Instant::now()) {
+        let _ = app_handle.emit_all(VAULT_LOCKED_EVENT, reason);
+    }
+    app_state.with_key(operation)
+}
+
+/// The master record must only ever be touched through `register_master`/`unlock`.
+fn check_name(name: &str) -> Result<(), BackendError> {
+    if name == MASTER_KEYWORD {
+        return Err(BackendError::ReservedName(name.to_string()));
+    }
+    Ok(())
+}
+
+/// Returns `true` if a master password has been registered.
+#[tauri::command]
+pub fn check_master_exists(app_handle: AppHandle) -> Result<bool, BackendError> {
+    Ok(app_handle.db(db_ops::check_master_exists)?)
+}
+
+/// Registers a new master password along with its recovery note.
+/// Fails if a master password already exists or if the new one is too weak.
+#[tauri::command]
+pub fn register_master(
+    app_handle: AppHandle,
+    master: String,
+    recovery_note: String,
+) -> Result<(), BackendError> {
+    app_handle.db_mut(|connection| db_ops::register_master(connection, &master, &recovery_note))
+}
+
+/// Authenticates the master password and, if it is correct, unlocks the vault.
+/// This is the only command that takes the master password; everything else uses the unlocked session.
+/// Returns `false` (and leaves the vault locked) if the password is wrong.
+#[tauri::command]
+pub fn unlock(app_handle: AppHandle, master: String) -> Result<bool, BackendError> {
+    // the connection is released before we touch the session, `with_key` takes them the other way around
+    let session = app_handle.db(|connection| Session::open(connection, &master))?;
+    let app_state: State<AppState> = app_handle.state();
+    Ok(match session {
+        Some(session) => {
+            app_state.unlock(session);
+            // the vault is fully upgraded by now, so the copies from before the upgrade only keep what it got rid of.
+            // one that can't be deleted is tried again at the next unlock
+            if let Some(app_dir) = app_handle.path_resolver().app_data_dir() {
+                let _ = db_ops::tauri::remove_backups(&app_dir);
+            }
+            true
+        }
+        None => false,
+    })
+}
+
+/// Locks the vault now, wiping the session key.
+#[tauri::command]
+pub fn lock(app_handle: AppHandle) {
+    let app_state: State<AppState> = app_handle.state();
+    if app_state.lock() {
+        let _ = app_handle.emit_all(VAULT_LOCKED_EVENT, LockReason::Manual);
+    }
+}
+
+/// Sets how long the vault may stay unlocked: `idle_timeout_secs` without being used,
+/// and `max_age_secs` in total. Applies to the current session too.
+/// Fails with `InvalidSessionTimeouts` if either is under 30 seconds or `max_age_secs` is less than `idle_timeout_secs`.
+#[tauri::command]
+pub fn set_session_timeouts(
+    app_handle: AppHandle,
+    idle_timeout_secs: u64,
+    max_age_secs: u64,
+) -> Result<(), BackendError> {
+    let config = SessionConfig::new(
+        Duration::from_secs(idle_timeout_secs),
+        Duration::from_secs(max_age_secs),
+    )?;
+    let app_state: State<AppState> = app_handle.state();
+    *app_state.session_config.lock().unwrap() = config;
+    Ok(())
+}
+
+/// Changes the master password, re-encrypting the whole vault. The vault stays unlocked under the new password.
+#[tauri::command]
+pub fn change_master_password(
+    app_handle: AppHandle,
+    old: String,
+    new: String,
+) -> Result<(), BackendError> {
+    let session = app_handle.db_mut(|connection| {
+        db_ops::change_master_password(connection, &old, &new)?;
+        Session::open(connection, &new)
+    })?;
+    // the old session key can't decrypt anything anymore
+    let app_state: State<AppState> = app_handle.state();
+    match session {
+        Some(session) => app_state.unlock(session),
+        None => {
+            app_state.lock();
+        }
+    }
+    Ok(())
+}
+
+/// Lists the names of every password in the vault.
+#[tauri::command]
+pub fn list_entries(app_handle: AppHandle) -> Result<Vec<String>, BackendError> {
+    with_key(&app_handle, |key| {
+        app_handle.db(|connection| crud_operations::get_password_names(connection, key))
+    })
+}
+
+/// Lists passwords for the sidebar: names and which fields are set, filtered, sorted and paginated. Nothing secret is decrypted.
+#[tauri::command]
+pub fn list_password_infos(
+    app_handle: AppHandle,
+    options: ListOptions,
+) -> Result<PasswordPage, BackendError> {
+    with_key(&app_handle, |key| {
+        app_handle.db(|connection| crud_operations::list_password_infos(connection, key, &options))
+    })
+}
+
+/// Fuzzy-searches the decrypted names, emails, usernames and notes of every password (and passwords, if asked),
+/// returning the best matches first along with what to highlight.
+#[tauri::command]
+pub fn search_entries(
+    app_handle: AppHandle,
+    query: String,
+    options: SearchOptions,
+) -> Result<Vec<SearchResult>, BackendError> {
+    with_key(&app_handle, |key| {
+        app_handle.db(|connection| {
+            crud_operations::search_password_infos(connection, key, &query, &options)
+        })
+    })
+}
+
+/// Reads and decrypts a password, recording that it was accessed. Returns `None` if no password has the given name.
+#[tauri::command]
+pub fn read_entry(
+    app_handle: AppHandle,
+    name: String,
+) -> Result<Option<PasswordInfo>, BackendError> {
+    check_name(&name)?;
+    with_key(&app_handle, |key| {
+        app_handle.db(|connection| crud_operations::read_password_info(connection, &name, key))
+    })
+}
+
+/// Records that a password was used just now, e.g. when it's copied to the clipboard.
+#[tauri::command]
+pub fn mark_entry_used(app_handle: AppHandle, name: String) -> Result<usize, BackendError> {
+    check_name(&name)?;
+    with_key(&app_handle, |key| {
+        Ok(app_handle.db(|connection| crud_operations::mark_used(connection, &name, key))?)
+    })
+}
+
+/// Generates the current two-factor code of a password, or `None` if it has no OTP field.
+/// Set the field with `upsert_field` and an `otpauth://` URI.
+#[tauri::command]
+pub fn otp_code(app_handle: AppHandle, name: String) -> Result<Option<OtpCode>, BackendError> {
+    check_name(&name)?;
+    with_key(&app_handle, |key| {
+        app_handle.db(|connection| otp::next_code(connection, key, &name))
+    })
+}
+
+/// Encrypts and inserts (or updates) one field of a password, creating the password if it doesn't exist.
+#[tauri::command]
+pub fn upsert_field(
+    app_handle: AppHandle,
+    name: String,
+    field: PasswordField,
+    data: String,
+) -> Result<usize, BackendError> {
+    check_name(&name)?;
+    with_key(&app_handle, |key| {
+        app_handle
+            .db(|connection| crud_operations::insert_data(connection, &name, key, field, &data))
+    })
+}
+
+/// Adds a custom field to a password, creating the password if it doesn't exist. Returns the new field's id.
+#[tauri::command]
+pub fn add_custom_field(
+    app_handle: AppHandle,
+    name: String,
+    field: CustomField,
+) -> Result<i32, BackendError> {
+    check_name(&name)?;
+    with_key(&app_handle, |key| {
+        app_handle
+            .db(|connection| crud_operations::insert_custom_field(connection, &name, key, &field))
+    })
+}
+
+/// Changes a custom field of a password (its label, type and value).
+#[tauri::command]
+pub fn update_custom_field(
+    app_handle: AppHandle,
+    name: String,
+    field: CustomField,
+) -> Result<usize, BackendError> {
+    check_name(&name)?;
+    with_key(&app_handle, |key| {
+        app_handle
+            .db(|connection| crud_operations::update_custom_field(connection, &name, key, &field))
+    })
+}
+
+/// Deletes a custom field of a password.
+#[tauri::command]
+pub fn delete_custom_field(
+    app_handle: AppHandle,
+    name: String,
+    field_id: i32,
+) -> Result<usize, BackendError> {
+    check_name(&name)?;
+    with_key(&app_handle, |key| {
+        Ok(app_handle.db(|connection| {
+            crud_operations::delete_custom_field(connection, &name, key, field_id)
+        })?)
+    })
+}
+
+/// Lists the previous values of a password's fields (or of one field), newest first.
+#[tauri::command]
+pub fn list_history(
+    app_handle: AppHandle,
+    name: String,
+    field: Option<PasswordField>,
+) -> Result<Vec<HistoryEntry>, BackendError> {
+    check_name(&name)?;
+    with_key(&app_handle, |key| {
+        app_handle.db(|connection| history::list_history(connection, key, &name, field))
+    })
+}
+
+/// Puts a previous value back into its field. The value it replaces goes into the history.
+#[tauri::command]
+pub fn restore_history(
+    app_handle: AppHandle,
+    name: String,
+    history_id: i32,
+) -> Result<usize, BackendError> {
+    check_name(&name)?;
+    with_key(&app_handle, |key| {
+        app_handle.db(|connection| history::restore_history(connection, key, &name, history_id))
+    })
+}
+
+/// Returns how many previous values of each field are kept.
+#[tauri::command]
+pub fn get_history_retention(app_handle: AppHandle) -> Result<usize, BackendError> {
+    with_key(&app_handle, |_| app_handle.db(history::retention))
+}
+
+/// Sets how many previous values of each field are kept, deleting any older ones. Returns how many were deleted.
+#[tauri::command]
+pub fn set_history_retention(app_handle: AppHandle, count: usize) -> Result<usize, BackendError> {
+    with_key(&app_handle, |_| {
+        app_handle.db(|connection| history::set_retention(connection, count))
+    })
+}
+
+/// Renames a password, re-encrypting it under the new name.
+#[tauri::command]
+pub fn rename_entry(
+    app_handle: AppHandle,
+    old_name: String,
+    new_name: String,
+) -> Result<usize, BackendError> {
+    with_key(&app_handle, |key| {
+        app_handle.db(|connection| {
+            crud_operations::rename_password_info(connection, &old_name, &new_name, key)
+        })
+    })
+}
+
+/// Moves a password to the trash.
+#[tauri::command]
+pub fn delete_entry(app_handle: AppHandle, name: String) -> Result<usize, BackendError> {
+    check_name(&name)?;
+    with_key(&app_handle, |key| {
+        app_handle.db(|connection| crud_operations::delete_password_info(connection, &name, key))
+    })
+}
+
+/// Takes a password back out of the trash, returning the name it was restored under.
+#[tauri::command]
+pub fn restore_entry(app_handle: AppHandle, id: i32) -> Result<Option<String>, BackendError> {
+    with_key(&app_handle, |key| {
+        app_handle.db(|connection| crud_operations::restore_password_info(connection, id, key))
+    })
+}
+
+/// Deletes a password in the trash for good.
+#[tauri::command]
+pub fn delete_entry_permanently(app_handle: AppHandle, id: i32) -> Result<usize, BackendError> {
+    with_key(&app_handle, |_| {
+        Ok(app_handle.db(|connection| crud_operations::delete_permanently(connection, id))?)
+    })
+}
+
+/// Deletes every password that has been in the trash for longer than the retention period. Returns how many were deleted.
+#[tauri::command]
+pub fn purge_trash(app_handle: AppHandle) -> Result<usize, BackendError> {
+    with_key(&app_handle, |_| app_handle.db(db_ops::purge_trash))
+}
+
+/// Returns how many days passwords stay in the trash.
+#[tauri::command]
+pub fn get_trash_retention(app_handle: AppHandle) -> Result<u32, BackendError> {
+    with_key(&app_handle, |_| app_handle.db(db_ops::trash_retention))
+}
+
+/// Sets how many days passwords stay in the trash.
+#[tauri::command]
+pub fn set_trash_retention(app_handle: AppHandle, days: u32) -> Result<(), BackendError> {
+    with_key(&app_handle, |_| {
+        app_handle.db(|connection| db_ops::set_trash_retention(connection, days))?;
+        Ok(())
+    })
+}
+
+/// Creates a folder, at the top level or inside `parent_id`. Returns the new folder's id.
+#[tauri::command]
+pub fn create_folder(
+    app_handle: AppHandle,
+    name: String,
+    parent_id: Option<i32>,
+) -> Result<i32, BackendError> {
+    with_key(&app_handle, |key| {
+        app_handle.db(|connection| organize::create_folder(connection, key, &name, parent_id))
+    })
+}
+
+/// Renames a folder.
+#[tauri::command]
+pub fn rename_folder(
+    app_handle: AppHandle,
+    folder_id: i32,
+    name: String,
+) -> Result<usize, BackendError> {
+    with_key(&app_handle, |key| {
+        app_handle.db(|connection| organize::rename_folder(connection, key, folder_id, &name))
+    })
+}
+
+/// Moves a folder into another one, or to the top level if `parent_id` is `None`.
+#[tauri::command]
+pub fn move_folder(
+    app_handle: AppHandle,
+    folder_id: i32,
+    parent_id: Option<i32>,
+) -> Result<usize, BackendError> {
+    with_key(&app_handle, |key| {
+        app_handle.db(|connection| organize::move_folder(connection, key, folder_id, parent_id))
+    })
+}
+
+/// Deletes a folder. Whatever was in it moves up into its parent.
+#[tauri::command]
+pub fn delete_folder(app_handle: AppHandle, folder_id: i32) -> Result<usize, BackendError> {
+    with_key(&app_handle, |_| {
+        app_handle.db(|connection| organize::delete_folder(connection, folder_id))
+    })
+}
+
+/// Lists every folder, with how many passwords are in each.
+#[tauri::command]
+pub fn list_folders(app_handle: AppHandle) -> Result<Vec<Folder>, BackendError> {
+    with_key(&app_handle, |key| {
+        app_handle.db(|connection| organize::list_folders(connection, key))
+    })
+}
+
+/// Moves a password into a folder, or out of any folder if `folder_id` is `None`.
+#[tauri::command]
+pub fn move_entry(
+    app_handle: AppHandle,
+    name: String,
+    folder_id: Option<i32>,
+) -> Result<usize, BackendError> {
+    check_name(&name)?;
+    with_key(&app_handle, |key| {
+        app_handle.db(|connection| organize::move_password(connection, key, &name, folder_id))
+    })
+}
+
+/// Tags a password, creating the tag if it doesn't exist yet.
+#[tauri::command]
+pub fn tag_entry(app_handle: AppHandle, name: String, tag: String) -> Result<usize, BackendError> {
+    check_name(&name)?;
+    with_key(&app_handle, |key| {
+        app_handle.db(|connection| organize::tag_password(connection, key, &name, &tag))
+    })
+}
+
+/// Removes a tag from a password.
+#[tauri::command]
+pub fn untag_entry(
+    app_handle: AppHandle,
+    name: String,
+    tag: String,
+) -> Result<usize, BackendError> {
+    check_name(&name)?;
+    with_key(&app_handle, |key| {
+        Ok(app_handle.db(|connection| organize::untag_password(connection, key, &name, &tag))?)
+    })
+}
+
+/// Renames a tag.
+#[tauri::command]
+pub fn rename_tag(app_handle: AppHandle, tag_id: i32, name: String) -> Result<usize, BackendError> {
+    with_key(&app_handle, |key| {
+        app_handle.db(|connection| organize::rename_tag(connection, key, tag_id, &name))
+    })
+}
+
+/// Deletes a tag, removing it from every password.
+#[tauri::command]
+pub fn delete_tag(app_handle: AppHandle, tag_id: i32) -> Result<usize, BackendError> {
+    with_key(&app_handle, |_| {
+        Ok(app_handle.db(|connection| organize::delete_tag(connection, tag_id))?)
+    })
+}
+
+/// Lists every tag, with how many passwords have each.
+#[tauri::command]
+pub fn list_tags(app_handle: AppHandle) -> Result<Vec<Tag>, BackendError> {
+    with_key(&app_handle, |key| {
+        app_handle.db(|connection| organize::list_tags(connection, key))
+    })
+}
+
+/// Generates a random password of the given length.
+#[tauri::command]
+pub fn generate_password(length: usize) -> String {
+    crypto::generate_password(length)
+}
+
+/// Imports the accounts of an authenticator app's export, each as a password with an OTP field.
+/// `password` is only needed for encrypted Aegis vaults. Names that are taken get a suffix unless `collision` says otherwise.
+#[tauri::command]
+pub fn import_authenticator(
+    app_handle: AppHandle,
+    format: AuthenticatorFormat,
+    data: String,
+    password: Option<String>,
+    collision: Option<NameCollision>,
+) -> Result<ImportReport, BackendError> {
+    with_key(&app_handle, |key| {
+        let export = authenticator::parse(format, &data, password.as_deref())?;
+        app_handle.db(|connection| {
+            import::add_entries(connection, key, export, collision.unwrap_or_default())
+        })
+    })
+}
+
+/// Imports a Bitwarden export, JSON or CSV (`csv` decides which).
+/// `password` is only needed for password protected JSON exports. Names that are taken get a suffix unless `collision` says otherwise.
+#[tauri::command]
+pub fn import_bitwarden(
+    app_handle: AppHandle,
+    data: String,
+    csv: bool,
+    password: Option<String>,
+    collision: Option<NameCollision>,
+) -> Result<ImportReport, BackendError> {
+    with_key(&app_handle, |key| {
+        let export = match csv {
+            true => bitwarden::parse_csv(&data)?,
+            false => bitwarden::parse_json(&data, password.as_deref())?,
+        };
+        app_handle.db(|connection| {
+            import::add_entries(connection, key, export, collision.unwrap_or_default())
+        })
+    })
+}
+
+/// Imports a KeePass or KeePassXC database (`.kdbx`, version 4). Names that are taken get a suffix unless `collision` says otherwise.
+#[tauri::command]
+pub fn import_keepass(
+    app_handle: AppHandle,
+    data: Vec<u8>,
+    password: String,
+    collision: Option<NameCollision>,
+) -> Result<ImportReport, BackendError> {
+    with_key(&app_handle, |key| {
+        let export = keepass::parse(&data, &password)?;
+        app_handle.db(|connection| {
+            import::add_entries(connection, key, export, collision.unwrap_or_default())
+        })
+    })
+}
+
+/// Reads a CSV file into a preview of what importing it would do, with each row's errors. Nothing is imported.
+/// `mapping` says what each column holds; if it's `None`, it's detected from the headers.
+#[tauri::command]
+pub fn preview_csv(
+    app_handle: AppHandle,
+    data: String,
+    mapping: Option<Vec<CsvColumn>>,
+) -> Result<CsvPreview, BackendError> {
+    with_key(&app_handle, |_| generic_csv::preview(&data, mapping))
+}
+
+/// Imports the rows of a CSV file that have no errors (see `preview_csv`), all at once.
+/// Names that are taken get a suffix unless `collision` says otherwise.
+#[tauri::command]
+pub fn import_csv(
+    app_handle: AppHandle,
+    data: String,
+    mapping: Option<Vec<CsvColumn>>,
+    collision: Option<NameCollision>,
+) -> Result<ImportReport, BackendError> {
+    with_key(&app_handle, |key| {
+        let export = generic_csv::preview(&data, mapping)?.into_export();
+        app_handle.db(|connection| {
+            import::add_entries(connection, key, export, collision.unwrap_or_default())
+        })
+    })
+}
+
+/// Exports the vault as CSV, in the format `preset`'s app imports, or passman's own if `None`.
+#[tauri::command]
+pub fn export_csv(
+    app_handle: AppHandle,
+    preset: Option<CsvPreset>,
+) -> Result<String, BackendError> {
+    with_key(&app_handle, |key| {
+        app_handle.db(|connection| export::to_csv(connection, key, preset))
+    })
+}
+
+/// Exports the vault as a KeePass database (`.kdbx`) protected by `password`, returning the file's contents.
+/// Uses KeePassXC's default encryption unless `settings` says otherwise.
+#[tauri::command]
+pub fn export_kdbx(
+    app_handle: AppHandle,
+    password: String,
+    settings: Option<KdbxSettings>,
+) -> Result<Vec<u8>, BackendError> {
+    with_key(&app_handle, |key| {
+        app_handle.db(|connection| {
+            export::to_kdbx(connection, key, &password, &settings.unwrap_or_default())
+        })
+    })
+}
@@ -1,15 +1,26 @@
 use aes_gcm::{
-    aead::{generic_array::GenericArray, Aead, OsRng},
+    aead::{generic_array::GenericArray, Aead, OsRng, Payload},
     aes::Aes256,
-    Aes256Gcm, AesGcm, Key, KeyInit,
+    AeadCore, Aes256Gcm, AesGcm, Key, KeyInit,
 };
 
+use core::fmt;
+use std::{fmt::Display, str::FromStr};
+
+use argon2::{
+    password_hash::{self, PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
+    Argon2,
+};
+use hmac::{Hmac, Mac};
 use pbkdf2::pbkdf2_hmac;
 use rand::Rng;
+use rusqlite::types::{FromSql, FromSqlError, FromSqlResult, ToSql, ToSqlOutput, ValueRef};
 use sha2::{Digest, Sha256};
+use subtle::ConstantTimeEq;
 use typenum::consts::{U12, U32};
+use zeroize::{Zeroize, Zeroizing};
 
-use crate::error::BackendError;
+use crate::{error::BackendError, password::PasswordField};
 
 /// Hashes `text` using `Sha256`.
 ///
@@ -38,32 +49,523 @@ pub fn derive_key(master_password: impl AsRef<[u8]>, password: impl AsRef<[u8]>)
     );
     derived_key
 }
-/// Decrypts a `Password` field. May fail with a `GetPasswordError`.
+/// Hashes the master password (or the recovery note) into a PHC string, e.g. `$argon2id$v=19$m=19456,t=2,p=1$...`,
+/// using Argon2id with a random salt. This is what gets stored in the master record.
 ///
 /// # Arguments
-/// - `data` - the password field to decrypt.
-/// - `nonce` - a raw nonce to use for decryption.
+///
+/// - `password` - the password to hash.
+pub fn hash_master(password: impl AsRef<[u8]>) -> Result<String, BackendError> {
+    let salt = SaltString::generate(&mut OsRng);
+    Ok(Argon2::default()
+        .hash_password(password.as_ref(), &salt)?
+        .to_string())
+}
+
+/// The result of checking a password against a stored master verifier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verification {
+    /// The password is wrong.
+    Invalid,
+    /// The password is right.
+    Valid,
+    /// The password is right, but the verifier is a legacy unsalted SHA-256 hash and should be replaced
+    /// with the output of `hash_master`.
+    ValidLegacy,
+}
+
+/// Checks `password` against a verifier produced by `hash_master`, or against a legacy hex-encoded SHA-256 hash.
+/// Both comparisons are constant-time.
+///
+/// # Arguments
+///
+/// - `password` - the password to check.
+/// - `verifier` - the stored verifier.
+pub fn verify_master(
+    password: impl AsRef<[u8]>,
+    verifier: &str,
+) -> Result<Verification, BackendError> {
+    // PHC strings always start with `$`, hex never does
+    if verifier.starts_with('$') {
+        let parsed = PasswordHash::new(verifier)?;
+        return match Argon2::default().verify_password(password.as_ref(), &parsed) {
+            Ok(()) => Ok(Verification::Valid),
+            Err(password_hash::Error::Password) => Ok(Verification::Invalid),
+            Err(e) => Err(e.into()),
+        };
+    }
+    let expected = hex::decode(verifier)?;
+    if bool::from(hash(password.as_ref()).ct_eq(expected.as_slice())) {
+        Ok(Verification::ValidLegacy)
+    } else {
+        Ok(Verification::Invalid)
+    }
+}
+
+/// The key derivation functions a vault may use to derive its master key.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum KdfAlgorithm {
+    /// PBKDF2-HMAC-SHA256.
+    Pbkdf2Sha256,
+    /// Argon2id, used by every new vault.
+    Argon2id,
+}
+
+impl Display for KdfAlgorithm {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let str = match self {
+            KdfAlgorithm::Pbkdf2Sha256 => "pbkdf2-sha256",
+            KdfAlgorithm::Argon2id => "argon2id",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+impl FromStr for KdfAlgorithm {
+    type Err = BackendError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "pbkdf2-sha256" => Ok(KdfAlgorithm::Pbkdf2Sha256),
+            "argon2id" => Ok(KdfAlgorithm::Argon2id),
+            _ => Err(BackendError::UnsupportedKdf(s.to_string())),
+        }
+    }
+}
+
+/// Parameters for deriving the master key. These are stored in the vault (see `db_ops::read_kdf_params`),
+/// so they can be raised for new vaults without breaking existing ones.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KdfParams {
+    pub algorithm: KdfAlgorithm,
+    /// Memory cost in KiB. Ignored by PBKDF2.
+    pub memory_kib: u32,
+    /// Iterations (PBKDF2) or passes over memory (Argon2).
+    pub iterations: u32,
+    /// Degree of parallelism. Ignored by PBKDF2.
+    pub parallelism: u32,
+    pub salt: Vec<u8>,
+}
+
+impl KdfParams {
+    /// Argon2id with 64 MiB of memory, 3 passes and 4 lanes, and a fresh random salt.
+    pub fn argon2id() -> Self {
+        KdfParams {
+            algorithm: KdfAlgorithm::Argon2id,
+            memory_kib: 64 * 1024,
+            iterations: 3,
+            parallelism: 4,
+            salt: OsRng.gen::<[u8; 16]>().to_vec(),
+        }
+    }
+}
+
+impl Default for KdfParams {
+    fn default() -> Self {
+        Self::argon2id()
+    }
+}
+
+/// Derives the master key from the master password. This is the key material held by an unlocked session,
+/// so that the master password itself doesn't need to be kept around (or sent from the frontend) after unlocking.
+/// The expensive part of key derivation happens here, once per unlock.
+/// The result is only used as the key-encryption key of the vault key, see `wrap_vault_key`.
+///
+/// # Arguments
+///
+/// - `master_password` - the master password.
+/// - `params` - the vault's KDF parameters.
+pub fn derive_master_key(
+    master_password: impl AsRef<[u8]>,
+    params: &KdfParams,
+) -> Result<[u8; 32], BackendError> {
+    let mut derived_key = [0u8; 32];
+    match params.algorithm {
+        KdfAlgorithm::Pbkdf2Sha256 => pbkdf2_hmac::<Sha256>(
+            master_password.as_ref(),
+            &params.salt,
+            params.iterations,
+            &mut derived_key,
+        ),
+        KdfAlgorithm::Argon2id => {
+            let argon2_params = argon2::Params::new(
+                params.memory_kib,
+                params.iterations,
+                params.parallelism,
+                Some(derived_key.len()),
+            )?;
+            argon2::Argon2::new(
+                argon2::Algorithm::Argon2id,
+                argon2::Version::V0x13,
+                argon2_params,
+            )
+            .hash_password_into(
+                master_password.as_ref(),
+                &params.salt,
+                &mut derived_key,
+            )?;
+        }
+    }
+    Ok(derived_key)
+}
+/// The version of the associated data layout built by `associated_data`. Bump it if the layout ever changes.
+pub const FIELD_FORMAT_VERSION: u8 = 1;
+
+/// Builds the associated data a field is encrypted with, binding the ciphertext to its entry and column.
+/// A ciphertext copied into another column, or into another entry, fails to authenticate.
+///
+/// # Arguments
+///
+/// - `password_name` - the name of the entry the field belongs to.
+/// - `field` - the column the field is stored in.
+pub fn associated_data(password_name: &str, field: &PasswordField) -> Vec<u8> {
+    let column = field.to_string();
+    let mut aad = b"passman-field".to_vec();
+    aad.push(FIELD_FORMAT_VERSION);
+    // length-prefixed, so no two (name, column) pairs can produce the same bytes
+    aad.extend_from_slice(&(password_name.len() as u32).to_be_bytes());
+    aad.extend_from_slice(password_name.as_bytes());
+    aad.extend_from_slice(column.as_bytes());
+    aad
+}
+
+/// Builds the associated data a custom field's label or value is encrypted with, binding it to its entry and custom field.
+/// The value is also bound to the field's type, so e.g. a hidden field can't be turned into a text field (and shown)
+/// without being encrypted again.
+///
+/// # Arguments
+///
+/// - `password_name` - the name of the entry the custom field belongs to.
+/// - `field_id` - the id of the custom field.
+/// - `part` - `label` for the label, or the field's type for the value.
+pub fn custom_field_associated_data(password_name: &str, field_id: i32, part: &str) -> Vec<u8> {
+    let mut aad = b"passman-custom-field".to_vec();
+    aad.push(FIELD_FORMAT_VERSION);
+    aad.extend_from_slice(&(password_name.len() as u32).to_be_bytes());
+    aad.extend_from_slice(password_name.as_bytes());
+    aad.extend_from_slice(&field_id.to_be_bytes());
+    aad.extend_from_slice(part.as_bytes());
+    aad
+}
+
+/// The current version of the binary field envelope, see `EncryptedField::Envelope`.
+pub const ENVELOPE_VERSION: u8 = 1;
+/// Algorithm id for AES-256-GCM inside an envelope.
+pub const ALGORITHM_AES_256_GCM: u8 = 1;
+
+/// An encrypted field as it is stored in the database.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EncryptedField {
+    /// The original format: hex of `nonce || ciphertext`, stored as TEXT.
+    /// Depending on when it was written it may or may not have associated data.
+    LegacyHex(String),
+    /// A versioned binary envelope, stored as a BLOB: `version || algorithm || nonce || ciphertext`.
+    /// The two header bytes are authenticated along with the field's associated data.
+    Envelope(Vec<u8>),
+}
+
+impl FromSql for EncryptedField {
+    fn column_result(value: ValueRef<'_>) -> FromSqlResult<Self> {
+        match value {
+            ValueRef::Text(text) => Ok(EncryptedField::LegacyHex(
+                String::from_utf8(text.to_vec()).map_err(|e| FromSqlError::Other(Box::new(e)))?,
+            )),
+            ValueRef::Blob(blob) => Ok(EncryptedField::Envelope(blob.to_vec())),
+            _ => Err(FromSqlError::InvalidType),
+        }
+    }
+}
+
+impl ToSql for EncryptedField {
+    fn to_sql(&self) -> rusqlite::Result<ToSqlOutput<'_>> {
+        match self {
+            EncryptedField::LegacyHex(text) => text.to_sql(),
+            EncryptedField::Envelope(blob) => blob.to_sql(),
+        }
+    }
+}
+
+/// Encrypts a `Password` field into the current envelope format.
+///
+/// # Arguments
+/// - `data` - the plaintext to encrypt.
+/// - `aad` - the associated data to bind the field to, see `associated_data`.
+/// - `cipher` - an AES 256 GCM cipher to use for encryption.
+///
+pub fn encrypt_password_field(
+    data: &str,
+    aad: &[u8],
+    cipher: &AesGcm<Aes256, U12>,
+) -> EncryptedField {
+    let header = [ENVELOPE_VERSION, ALGORITHM_AES_256_GCM];
+    let nonce = Aes256Gcm::generate_nonce(OsRng);
+    let payload = Payload {
+        msg: data.as_bytes(),
+        aad: &[&header[..], aad].concat(),
+    };
+
+    let mut envelope = header.to_vec();
+    envelope.extend_from_slice(&nonce);
+    // encrypting into a Vec can't fail
+    envelope.append(&mut cipher.encrypt(&nonce, payload).unwrap());
+    EncryptedField::Envelope(envelope)
+}
+
+/// Decrypts a `Password` field, dispatching on its format and envelope version.
+///
+/// # Arguments
+/// - `field` - the password field to decrypt.
+/// - `aad` - the associated data the field was encrypted with, see `associated_data`.
+/// - `allow_legacy` - whether a `LegacyHex` field may also be decrypted without associated data.
 /// - `cipher` - an AES 256 GCM cipher to use for decryption.
 ///
 pub fn decrypt_password_field(
+    field: &EncryptedField,
+    aad: &[u8],
+    allow_legacy: bool,
+    cipher: &AesGcm<Aes256, U12>,
+) -> Result<String, BackendError> {
+    match field {
+        EncryptedField::LegacyHex(data) => {
+            let decoded_data = hex::decode(data)?;
+            let nonce = decoded_data
+                .get(..12)
+                .ok_or(BackendError::NoMatchingNonce)?;
+            let ciphertext = &decoded_data[12..];
+            match decrypt_aes_gcm(ciphertext, nonce, aad, cipher) {
+                // fields written before associated data existed, until the vault is migrated
+                Err(BackendError::AesError) if allow_legacy => {
+                    decrypt_aes_gcm(ciphertext, nonce, [], cipher)
+                }
+                result => result,
+            }
+        }
+        EncryptedField::Envelope(envelope) => match envelope.first() {
+            Some(&ENVELOPE_VERSION) => {
+                let header = envelope.get(..2).ok_or(BackendError::NoMatchingNonce)?;
+                if header[1] != ALGORITHM_AES_256_GCM {
+                    return Err(BackendError::UnsupportedFormat(header[0], header[1]));
+                }
+                let nonce = envelope.get(2..14).ok_or(BackendError::NoMatchingNonce)?;
+                decrypt_aes_gcm(&envelope[14..], nonce, [header, aad].concat(), cipher)
+            }
+            Some(&version) => Err(BackendError::UnsupportedFormat(version, 0)),
+            None => Err(BackendError::NoMatchingNonce),
+        },
+    }
+}
+
+/// Decrypts raw AES-256-GCM ciphertext. May fail with a `GetPasswordError`.
+///
+/// # Arguments
+/// - `data` - the ciphertext to decrypt.
+/// - `nonce` - a raw nonce to use for decryption.
+/// - `aad` - the associated data the ciphertext was encrypted with, if any.
+/// - `cipher` - an AES 256 GCM cipher to use for decryption.
+///
+pub fn decrypt_aes_gcm(
     data: impl AsRef<[u8]>,
     nonce: impl AsRef<[u8]>,
+    aad: impl AsRef<[u8]>,
     cipher: &AesGcm<Aes256, U12>,
 ) -> Result<String, BackendError> {
+    let payload = Payload {
+        msg: data.as_ref(),
+        aad: aad.as_ref(),
+    };
     let decrypted = cipher
-        .decrypt(GenericArray::from_slice(nonce.as_ref()), data.as_ref())
+        .decrypt(GenericArray::from_slice(nonce.as_ref()), payload)
         .map_err(|_| BackendError::AesError)?;
     Ok(String::from_utf8(decrypted)?)
 }
-pub fn gen_cipher(
+/// The cipher for the fields of one password, keyed with a subkey of the vault key for its name (see `derive_subkey`).
+/// The vault key is random, so there is nothing for a slow KDF to stretch.
+///
+/// # Arguments
+///
+/// - `key` - the vault key.
+/// - `password_name` - the name of the password.
+pub fn gen_cipher(key: impl AsRef<[u8]>, password_name: impl AsRef<[u8]>) -> AesGcm<Aes256, U12> {
+    let label = [b"passman entry key\0".as_slice(), password_name.as_ref()].concat();
+    let derived = Zeroizing::new(derive_subkey(key, &label));
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(derived.as_ref()))
+}
+
+/// The cipher fields were encrypted with before the envelope format (`EncryptedField::LegacyHex`), when every
+/// password's key was stretched with PBKDF2 from the master password and its name. Only used to read such fields.
+///
+/// # Arguments
+///
+/// - `master` - the key the vault was encrypted under, see `db_ops::unlock_vault_key`.
+/// - `password_name` - the name of the password.
+pub fn legacy_gen_cipher(
     master: impl AsRef<[u8]>,
     password_name: impl AsRef<[u8]>,
 ) -> AesGcm<Aes256, U12> {
-    let derived = derive_key(master, password_name);
-    let key = Key::<Aes256Gcm>::from_slice(&derived);
+    let derived = Zeroizing::new(derive_key(master, password_name));
+    let key = Key::<Aes256Gcm>::from_slice(derived.as_ref());
     Aes256Gcm::new(key)
 }
 
+/// Derives a subkey of the vault key for one purpose (HMAC-SHA256 of `label`), so no key is ever used for two things.
+///
+/// # Arguments
+///
+/// - `key` - the vault key.
+/// - `label` - what the subkey is for.
+pub fn derive_subkey(key: impl AsRef<[u8]>, label: &[u8]) -> [u8; 32] {
+    // HMAC takes keys of any length
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key.as_ref()).unwrap();
+    mac.update(label);
+    mac.finalize().into_bytes().into()
+}
+
+/// Normalizes an entry name before it is indexed: surrounding whitespace is ignored and case is folded,
+/// so `GitHub` and ` github` are the same entry.
+pub fn normalize_name(name: &str) -> String {
+    name.trim().to_lowercase()
+}
+
+/// Computes the blind index of an entry name, a keyed hash of its normalized form.
+/// Names are stored encrypted, so this is what the unique constraint and exact lookups use instead;
+/// without the vault key it reveals nothing but which rows share a name (and no two do).
+///
+/// # Arguments
+///
+/// - `key` - the vault key.
+/// - `password_name` - the name to index.
+pub fn name_index(key: impl AsRef<[u8]>, password_name: &str) -> Vec<u8> {
+    blind_index(key, b"passman name index", password_name)
+}
+
+/// Computes the blind index of a tag, like `name_index` but under its own subkey,
+/// so a tag can't be linked to an entry with the same name.
+///
+/// # Arguments
+///
+/// - `key` - the vault key.
+/// - `tag` - the tag to index.
+pub fn tag_index(key: impl AsRef<[u8]>, tag: &str) -> Vec<u8> {
+    blind_index(key, b"passman tag index", tag)
+}
+
+/// The placeholder blind index of a trashed entry, which frees up its name for a new entry.
+/// It isn't an HMAC, so it can never clash with a real index (those are always 32 bytes).
+///
+/// # Arguments
+///
+/// - `password_id` - the id of the trashed entry.
+pub fn trash_index(password_id: i32) -> Vec<u8> {
+    [b"trash".as_ref(), &password_id.to_be_bytes()].concat()
+}
+
+fn blind_index(key: impl AsRef<[u8]>, label: &[u8], name: &str) -> Vec<u8> {
+    let subkey = Zeroizing::new(derive_subkey(key, label));
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(subkey.as_ref()).unwrap();
+    mac.update(normalize_name(name).as_bytes());
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// Builds the cipher entry names are encrypted with. Unlike fields, names can't be encrypted under
+/// a key derived from the name itself, so they share one key derived from the vault key.
+///
+/// # Arguments
+///
+/// - `key` - the vault key.
+pub fn name_cipher(key: impl AsRef<[u8]>) -> AesGcm<Aes256, U12> {
+    let subkey = Zeroizing::new(derive_subkey(key, b"passman name key"));
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(subkey.as_ref()))
+}
+
+/// Builds the associated data an entry name is encrypted with, binding it to its blind index,
+/// so an encrypted name can't be moved to another row.
+///
+/// # Arguments
+///
+/// - `index` - the name's blind index, see `name_index`.
+pub fn name_associated_data(index: &[u8]) -> Vec<u8> {
+    [b"passman-name".as_ref(), index].concat()
+}
+
+/// Builds the cipher previous field values are encrypted with, see `history`. It's derived from the vault key rather than
+/// the entry's name, so renaming an entry doesn't mean encrypting its whole history again.
+///
+/// # Arguments
+///
+/// - `key` - the vault key.
+pub fn history_cipher(key: impl AsRef<[u8]>) -> AesGcm<Aes256, U12> {
+    let subkey = Zeroizing::new(derive_subkey(key, b"passman history key"));
+    Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(subkey.as_ref()))
+}
+
+/// Builds the associated data a previous field value is encrypted with, binding it to its entry, column and history row.
+///
+/// # Arguments
+///
+/// - `password_id` - the id of the entry the value belonged to.
+/// - `field` - the column the value was in.
+/// - `history_id` - the id of the history row.
+pub fn history_associated_data(
+    password_id: i32,
+    field: &PasswordField,
+    history_id: i32,
+) -> Vec<u8> {
+    let mut aad = b"passman-history".to_vec();
+    aad.push(FIELD_FORMAT_VERSION);
+    aad.extend_from_slice(&password_id.to_be_bytes());
+    aad.extend_from_slice(&history_id.to_be_bytes());
+    aad.extend_from_slice(field.to_string().as_bytes());
+    aad
+}
+
+/// Associated data for the wrapped vault key, so the blob can't be mistaken for (or swapped with) an entry field.
+const VAULT_KEY_AAD: &[u8] = b"passman vault key";
+
+/// Generates a random 256-bit vault key. Every per-entry key is derived from it.
+pub fn generate_vault_key() -> [u8; 32] {
+    OsRng.gen()
+}
+
+/// Encrypts the vault key under a key-encryption key (the master key, see `derive_master_key`).
+/// Returns `nonce || ciphertext`.
+///
+/// # Arguments
+///
+/// - `kek` - the key-encryption key.
+/// - `vault_key` - the vault key to wrap.
+pub fn wrap_vault_key(kek: &[u8; 32], vault_key: &[u8; 32]) -> Vec<u8> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(kek));
+    let nonce = Aes256Gcm::generate_nonce(OsRng);
+    let payload = Payload {
+        msg: vault_key,
+        aad: VAULT_KEY_AAD,
+    };
+    let mut wrapped = nonce.to_vec();
+    // encrypting into a Vec can't fail
+    wrapped.append(&mut cipher.encrypt(&nonce, payload).unwrap());
+    wrapped
+}
+
+/// Decrypts a vault key wrapped by `wrap_vault_key`. Fails with `BackendError::AesError` if `kek` is wrong.
+///
+/// # Arguments
+///
+/// - `kek` - the key-encryption key.
+/// - `wrapped` - `nonce || ciphertext`, as returned by `wrap_vault_key`.
+pub fn unwrap_vault_key(kek: &[u8; 32], wrapped: &[u8]) -> Result<[u8; 32], BackendError> {
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(kek));
+    let nonce = wrapped.get(..12).ok_or(BackendError::NoMatchingNonce)?;
+    let payload = Payload {
+        msg: &wrapped[12..],
+        aad: VAULT_KEY_AAD,
+    };
+    let mut decrypted = cipher
+        .decrypt(GenericArray::from_slice(nonce), payload)
+        .map_err(|_| BackendError::AesError)?;
+    let key = <[u8; 32]>::try_from(decrypted.as_slice()).map_err(|_| BackendError::AesError);
+    decrypted.zeroize();
+    key
+}
+
 /// generates a password given a length using randomness from the OS
 pub fn generate_password(length: usize) -> String {
     let characters: Vec<char> = "abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ1234567890!@#$%^&*()~`-=_+[]{}\\|;':\",.<>/?".chars().collect();
@@ -74,6 +576,36 @@ pub fn generate_password(length: usize) -> String {
         .collect()
 }
 
+/// Estimates the entropy of a password in bits, as `length * log2(pool)`,
+/// where the pool is the combined size of every character class the password uses.
+/// This is a rough upper bound - it knows nothing about dictionary words or repeated characters.
+pub fn estimate_entropy(password: &str) -> f64 {
+    let mut pool = 0;
+    if password.chars().any(|c| c.is_ascii_lowercase()) {
+        pool += 26;
+    }
+    if password.chars().any(|c| c.is_ascii_uppercase()) {
+        pool += 26;
+    }
+    if password.chars().any(|c| c.is_ascii_digit()) {
+        pool += 10;
+    }
+    if password
+        .chars()
+        .any(|c| c.is_ascii_punctuation() || c == ' ')
+    {
+        pool += 33;
+    }
+    // anything else (accents, emoji, ...) gets a conservative pool of its own
+    if !password.is_ascii() {
+        pool += 100;
+    }
+    if pool == 0 {
+        return 0.0;
+    }
+    password.chars().count() as f64 * (pool as f64).log2()
+}
+
 #[cfg(test)]
 mod tests {
     use aes_gcm::{aead::Aead, aead::OsRng, AeadCore, Aes256Gcm, Key, KeyInit};
@@ -94,6 +626,134 @@ mod tests {
         assert_eq!(res.to_vec(), expected);
     }
 
+    #[test]
+    fn entry_ciphers() {
+        let encrypt = |cipher: &aes_gcm::AesGcm<aes_gcm::aes::Aes256, typenum::U12>| {
+            cipher.encrypt(&[0; 12].into(), b"data".as_ref()).unwrap()
+        };
+        let current = encrypt(&super::gen_cipher("key", "GitHub"));
+        // an HMAC subkey of the vault key, apart from the vault's other subkeys
+        assert_eq!(
+            current,
+            encrypt(&Aes256Gcm::new(
+                &super::derive_subkey("key", b"passman entry key\0GitHub").into()
+            ))
+        );
+        assert_ne!(current, encrypt(&super::gen_cipher("key", "github")));
+        assert_ne!(current, encrypt(&super::legacy_gen_cipher("key", "GitHub")));
+        assert_eq!(
+            encrypt(&super::legacy_gen_cipher("key", "GitHub")),
+            encrypt(&Aes256Gcm::new(&super::derive_key("key", "GitHub").into()))
+        );
+    }
+
+    #[test]
+    fn verify_master() {
+        use super::Verification;
+        let verifier = super::hash_master("mymasterpassword").unwrap();
+        assert!(verifier.starts_with("$argon2id$"));
+        // salted, so hashing the same password twice gives different verifiers
+        assert_ne!(verifier, super::hash_master("mymasterpassword").unwrap());
+
+        assert_eq!(
+            super::verify_master("mymasterpassword", &verifier).unwrap(),
+            Verification::Valid
+        );
+        assert_eq!(
+            super::verify_master("random_guess", &verifier).unwrap(),
+            Verification::Invalid
+        );
+
+        let legacy = hex::encode(super::hash(b"mymasterpassword"));
+        assert_eq!(
+            super::verify_master("mymasterpassword", &legacy).unwrap(),
+            Verification::ValidLegacy
+        );
+        assert_eq!(
+            super::verify_master("random_guess", &legacy).unwrap(),
+            Verification::Invalid
+        );
+    }
+
+    #[test]
+    fn derive_master_key() {
+        use super::{KdfAlgorithm, KdfParams};
+
+        // small parameters so the test runs quickly
+        let params = KdfParams {
+            algorithm: KdfAlgorithm::Argon2id,
+            memory_kib: 1024,
+            iterations: 1,
+            parallelism: 1,
+            salt: b"somesalt".to_vec(),
+        };
+        let key = super::derive_master_key("mymasterpassword", &params).unwrap();
+        assert_eq!(
+            key,
+            super::derive_master_key("mymasterpassword", &params).unwrap()
+        );
+        // the salt matters
+        let other_salt = KdfParams {
+            salt: b"othersalt".to_vec(),
+            ..params.clone()
+        };
+        assert_ne!(
+            key,
+            super::derive_master_key("mymasterpassword", &other_salt).unwrap()
+        );
+        // and invalid parameters are an error, not a panic
+        let invalid = KdfParams {
+            memory_kib: 1,
+            ..params
+        };
+        assert!(super::derive_master_key("mymasterpassword", &invalid).is_err());
+    }
+
+    #[test]
+    fn wrap_vault_key() {
+        let kek = [1u8; 32];
+        let vault_key = super::generate_vault_key();
+        let wrapped = super::wrap_vault_key(&kek, &vault_key);
+        // nonce + key + tag
+        assert_eq!(wrapped.len(), 12 + 32 + 16);
+        assert_eq!(super::unwrap_vault_key(&kek, &wrapped).unwrap(), vault_key);
+
+        // the wrong kek, or a tampered blob, must not unwrap
+        assert!(super::unwrap_vault_key(&[2u8; 32], &wrapped).is_err());
+        let mut tampered = wrapped.clone();
+        tampered[20] ^= 1;
+        assert!(super::unwrap_vault_key(&kek, &tampered).is_err());
+        assert!(super::unwrap_vault_key(&kek, &wrapped[..8]).is_err());
+    }
+
+    #[test]
+    fn name_index() {
+        let key = [1u8; 32];
+        let index = super::name_index(key, "GitHub");
+        assert_eq!(index.len(), 32);
+        // normalized...
+        assert_eq!(super::name_index(key, " github "), index);
+        assert_ne!(super::name_index(key, "gitlab"), index);
+        // ...and keyed
+        assert_ne!(super::name_index([2u8; 32], "GitHub"), index);
+        assert_ne!(super::tag_index(key, "GitHub"), index);
+        // subkeys for different purposes are unrelated
+        assert_ne!(
+            super::derive_subkey(key, b"passman name index"),
+            super::derive_subkey(key, b"passman name key")
+        );
+    }
+
+    #[test]
+    fn estimate_entropy() {
+        assert_eq!(super::estimate_entropy(""), 0.0);
+        // 4 digits: 4 * log2(10)
+        assert!((super::estimate_entropy("1234") - 13.29).abs() < 0.01);
+        // mixing classes grows the pool
+        assert!(super::estimate_entropy("abcd1234") > super::estimate_entropy("abcdefgh"));
+        assert!(super::estimate_entropy("correct horse battery staple") > 100.0);
+    }
+
     #[test]
     fn decrypt() {
         let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
@@ -106,8 +766,81 @@ mod tests {
         let ciphertext = cipher.encrypt(&nonce, b"data".as_ref()).unwrap();
 
         // here's the function we're testing
-        let result = super::decrypt_password_field(ciphertext, nonce, &cipher).unwrap();
+        let result = super::decrypt_aes_gcm(&ciphertext, nonce, b"", &cipher).unwrap();
+        assert_eq!(result, "data");
 
+        // the same ciphertext, stored the old way
+        let mut legacy = nonce.to_vec();
+        legacy.extend_from_slice(&ciphertext);
+        let field = super::EncryptedField::LegacyHex(hex::encode(legacy));
+        let result = super::decrypt_password_field(&field, b"", false, &cipher).unwrap();
         assert_eq!(result, "data");
     }
+
+    #[test]
+    fn envelope() {
+        use super::EncryptedField;
+        use crate::password::PasswordField;
+
+        let cipher = super::gen_cipher("key", "test_name");
+        let aad = super::associated_data("test_name", &PasswordField::Password);
+        let field = super::encrypt_password_field("data", &aad, &cipher);
+        let envelope = match &field {
+            EncryptedField::Envelope(envelope) => envelope.clone(),
+            _ => panic!("expected an envelope"),
+        };
+        // version, algorithm, nonce, ciphertext + tag
+        assert_eq!(
+            envelope[..2],
+            [super::ENVELOPE_VERSION, super::ALGORITHM_AES_256_GCM]
+        );
+        assert_eq!(envelope.len(), 2 + 12 + 4 + 16);
+
+        let result = super::decrypt_password_field(&field, &aad, false, &cipher).unwrap();
+        assert_eq!(result, "data");
+
+        // moved to another column, or to another entry, it no longer decrypts
+        let notes = super::associated_data("test_name", &PasswordField::Notes);
+        assert!(super::decrypt_password_field(&field, &notes, true, &cipher).is_err());
+        let other = super::associated_data("other_name", &PasswordField::Password);
+        assert!(super::decrypt_password_field(&field, &other, true, &cipher).is_err());
+
+        // the header is authenticated too
+        let mut tampered = envelope.clone();
+        tampered[1] = 2;
+        assert!(matches!(
+            super::decrypt_password_field(
+                &EncryptedField::Envelope(tampered),
+                &aad,
+                false,
+                &cipher
+            ),
+            Err(crate::error::BackendError::UnsupportedFormat(1, 2))
+        ));
+        let mut future = envelope;
+        future[0] = 2;
+        assert!(matches!(
+            super::decrypt_password_field(&EncryptedField::Envelope(future), &aad, false, &cipher),
+            Err(crate::error::BackendError::UnsupportedFormat(2, _))
+        ));
+    }
+
+    #[test]
+    fn legacy_associated_data() {
+        use super::EncryptedField;
+        use crate::password::PasswordField;
+
+        let cipher = super::gen_cipher("key", "test_name");
+        let aad = super::associated_data("test_name", &PasswordField::Password);
+        let nonce = Aes256Gcm::generate_nonce(&mut OsRng);
+        let without_aad = cipher.encrypt(&nonce, b"data".as_ref()).unwrap();
+        let field = EncryptedField::LegacyHex(hex::encode([nonce.to_vec(), without_aad].concat()));
+
+        // a hex field without associated data only decrypts while legacy fields are allowed
+        assert_eq!(
+            super::decrypt_password_field(&field, &aad, true, &cipher).unwrap(),
+            "data"
+        );
+        assert!(super::decrypt_password_field(&field, &aad, false, &cipher).is_err());
+    }
 }
@@ -2,16 +2,23 @@ pub const MASTER_KEYWORD: &str = ".master";
 
 pub mod crud_operations {
 
-    use aes_gcm::{
-        aead::{generic_array::GenericArray, Aead, OsRng},
-        AeadCore, Aes256Gcm,
-    };
+    use std::collections::HashMap;
+
+    use aes_gcm::{aes::Aes256, AesGcm};
     use rusqlite::{Connection, OptionalExtension};
+    use typenum::U12;
 
     use {
         crate::crypto::*,
         crate::error::*,
-        crate::password::{PasswordField, PasswordInfo},
+        crate::organize::subtree,
+        crate::password::{
+            CustomField, EncryptedCustomField, EncryptedPasswordInfo, FieldTimestamps,
+            FolderFilter, ListOptions, NameFilter, PasswordField, PasswordInfo, PasswordPage,
+            PasswordSummary, SearchField, SearchHighlight, SearchOptions, SearchResult, SortOrder,
+            TrashFilter,
+        },
+        crate::search::fuzzy_match,
     };
     /// Reads a `Password` from the SQLite database. The password should contain encrypted fields.
     /// This function may fail with `rusqlite::Error`. Otherwise it will return an `Option<Password>`, being none if no password is found with the given search term.
@@ -19,25 +26,146 @@ pub mod crud_operations {
     /// # Arguments
     ///
     /// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
-    /// - `search_term` - a string slice that holds the name of the password to search for.
+    /// - `search_term` - a string slice that holds the name of the password to search for. Case and surrounding whitespace are ignored.
+    /// - `key` - the vault key of the unlocked session, which the name's blind index is keyed with (see `crypto::name_index`).
     ///
     pub fn get_password_info(
         connection: &Connection,
         search_term: &str,
-    ) -> Result<Option<PasswordInfo>, rusqlite::Error> {
-        let mut stmt = connection.prepare("select * from PasswordInfo where name = ?")?;
-        stmt.query_row([search_term], |row| {
-            Ok(PasswordInfo {
+        key: &[u8],
+    ) -> Result<Option<EncryptedPasswordInfo>, rusqlite::Error> {
+        let mut stmt = connection.prepare(
+            format!(
+                "select {} from PasswordInfo where name_index = ?",
+                PASSWORD_INFO_COLUMNS
+            )
+            .as_str(),
+        )?;
+        stmt.query_row([name_index(key, search_term)], password_info_from_row)
+            .optional()?
+            .map(|password| with_children(connection, password))
+            .transpose()
+    }
+    /// Reads a `Password` in the trash from the SQLite database. Trashed passwords can't be found by name, see `delete_password_info`.
+    ///
+    /// # Arguments
+    ///
+    /// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+    /// - `password_id` - the id of the password.
+    ///
+    pub fn get_trashed_password_info(
+        connection: &Connection,
+        password_id: i32,
+    ) -> Result<Option<EncryptedPasswordInfo>, rusqlite::Error> {
+        let mut stmt = connection.prepare(
+            format!(
+                "select {} from PasswordInfo where id = ? and trashed_at is not null",
+                PASSWORD_INFO_COLUMNS
+            )
+            .as_str(),
+        )?;
+        stmt.query_row([password_id], password_info_from_row)
+            .optional()?
+            .map(|password| with_children(connection, password))
+            .transpose()
+    }
+    /// Reads every `Password` from the SQLite database except the master record. The passwords contain encrypted fields.
+    ///
+    /// # Arguments
+    ///
+    /// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+    /// - `include_trashed` - whether to read the passwords in the trash too.
+    ///
+    pub fn get_all_password_infos(
+        connection: &Connection,
+        include_trashed: bool,
+    ) -> Result<Vec<EncryptedPasswordInfo>, rusqlite::Error> {
+        // the master record has no blind index
+        let mut stmt = connection.prepare(
+            format!(
+            "select {} from PasswordInfo where name_index is not null and (?1 or trashed_at is null)",
+            PASSWORD_INFO_COLUMNS
+        )
+        .as_str(),
+        )?;
+        let infos = stmt.query_map([include_trashed], password_info_from_row)?;
+        infos
+            .map(|password| with_children(connection, password?))
+            .collect()
+    }
+    // the columns must be selected in this order - `select *` would give us the table's order, which has username before email.
+    // reads used to do that and came back with the two swapped. writes have always gone by column name,
+    // so the data already in a vault is where it should be and needs no migration
+    const PASSWORD_INFO_COLUMNS: &str = "id, name_index, name, email, username, password, notes, folder_id,
+        created_at, email_updated_at, username_updated_at, password_updated_at, notes_updated_at, last_accessed_at, trashed_at,
+        otp, otp_updated_at, custom_fields_updated_at";
+    fn password_info_from_row(
+        row: &rusqlite::Row,
+    ) -> Result<EncryptedPasswordInfo, rusqlite::Error> {
+        Ok(EncryptedPasswordInfo {
+            id: row.get(0)?,
+            name_index: row.get(1)?,
+            name: row.get(2)?,
+            email: row.get(3)?,
+            username: row.get(4)?,
+            password: row.get(5)?,
+            notes: row.get(6)?,
+            otp: row.get(15)?,
+            folder_id: row.get(7)?,
+            created_at: row.get(8)?,
+            updated_at: FieldTimestamps {
+                email: row.get(9)?,
+                username: row.get(10)?,
+                password: row.get(11)?,
+                notes: row.get(12)?,
+                otp: row.get(16)?,
+                custom_fields: row.get(17)?,
+            },
+            last_accessed_at: row.get(13)?,
+            trashed_at: row.get(14)?,
+            // filled in by `with_children`
+            custom_fields: Vec::new(),
+            tag_ids: Vec::new(),
+        })
+    }
+    // custom fields and tags live in their own tables
+    fn with_children(
+        connection: &Connection,
+        mut password: EncryptedPasswordInfo,
+    ) -> Result<EncryptedPasswordInfo, rusqlite::Error> {
+        let mut stmt = connection.prepare(
+            "select id, label, field_type, value from CustomField where password_id = ? order by id",
+        )?;
+        let fields = stmt.query_map([password.id], |row| {
+            Ok(EncryptedCustomField {
                 id: row.get(0)?,
-
-                name: row.get(1)?,
-                email: row.get(2)?,
-                username: row.get(3)?,
-                password: row.get(4)?,
-                notes: row.get(5)?,
+                label: row.get(1)?,
+                field_type: row.get(2)?,
+                value: row.get(3)?,
             })
-        })
-        .optional()
+        })?;
+        password.custom_fields = fields.collect::<Result<_, _>>()?;
+
+        let mut stmt = connection
+            .prepare("select tag_id from PasswordTag where password_id = ? order by tag_id")?;
+        let tag_ids = stmt.query_map([password.id], |row| row.get(0))?;
+        password.tag_ids = tag_ids.collect::<Result<_, _>>()?;
+        Ok(password)
+    }
+    /// Decrypts the name of a `Password`.
+    ///
+    /// # Arguments
+    ///
+    /// - `name` - the encrypted name.
+    /// - `index` - the name's blind index, which it is bound to.
+    /// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+    ///
+    pub fn decrypt_name(
+        name: &EncryptedField,
+        index: &[u8],
+        key: &[u8],
+    ) -> Result<String, BackendError> {
+        decrypt_password_field(name, &name_associated_data(index), false, &name_cipher(key))
     }
     /// Decrypts a `Password`, which is assumed to already contain encrypted data.
     /// This function will return a result with a `GetPasswordError` if any step in the decryption process fails;
@@ -46,42 +174,74 @@ pub mod crud_operations {
     /// # Arguments
     ///
     /// - `password` - A `Password` with encrypted fields.
-    /// - `master` - a string slice that holds the master password. The master password should be verified/authenticated by the time this function is called.
+    /// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+    /// - `allow_legacy` - whether fields encrypted without associated data are accepted, see `db_ops::legacy_fields_allowed`.
     ///
     fn decrypt_password_info(
-        password: PasswordInfo,
-        master: &str,
+        password: EncryptedPasswordInfo,
+        key: &[u8],
+        allow_legacy: bool,
+    ) -> Result<PasswordInfo, BackendError> {
+        let name = decrypt_name(&password.name, &password.name_index, key)?;
+        decrypt_fields(password, name, key, allow_legacy)
+    }
+    // every field's key is derived from the plaintext name, so the name has to be decrypted first
+    // (or, for a row from before names were encrypted, read as it is)
+    fn decrypt_fields(
+        password: EncryptedPasswordInfo,
+        name: String,
+        key: &[u8],
+        allow_legacy: bool,
     ) -> Result<PasswordInfo, BackendError> {
         // fucking awesome partial struct destructuring
-        let PasswordInfo {
+        let EncryptedPasswordInfo {
             id,
-            name,
             .. // and the rest
         } = password;
 
         // this is not in the decrypt_field() function because it would involve deriving the key and generating the cipher 4 times
-        // considering the iterations involved in the kdf function it would be extremely inefficient
-        let cipher = gen_cipher(master, &name);
+        let cipher = gen_cipher(key, &name);
+        // fields from before the envelope format are keyed the old, slow way. only derived if there is one
+        let legacy_cipher = std::cell::OnceCell::new();
 
         // thank you @seaish for this fucking awesome function
         // ithis is so cool
-        let f = |field: Option<String>| {
+        let f = |field: Option<EncryptedField>, column: PasswordField| {
             field
                 .map(|data| {
-                    let decoded_data = hex::decode(data)?;
-                    let nonce = decoded_data
-                        .get(..12)
-                        .ok_or_else(|| BackendError::NoMatchingNonce)?;
-                    let ciphertext = decoded_data.get(12..).unwrap();
-                    decrypt_password_field(ciphertext, nonce, &cipher)
+                    let aad = associated_data(&name, &column);
+                    let cipher = match data {
+                        EncryptedField::LegacyHex(_) => {
+                            legacy_cipher.get_or_init(|| legacy_gen_cipher(key, &name))
+                        }
+                        EncryptedField::Envelope(_) => &cipher,
+                    };
+                    decrypt_password_field(&data, &aad, allow_legacy, cipher)
                 })
                 .transpose() // transpose switches "...the Option of a Result to a Result of an Option." ... that is so cool!!
         };
 
-        let email = f(password.email)?;
-        let username = f(password.username)?;
-        let pass = f(password.password)?;
-        let notes = f(password.notes)?;
+        let email = f(password.email, PasswordField::Email)?;
+        let username = f(password.username, PasswordField::Username)?;
+        let pass = f(password.password, PasswordField::Password)?;
+        let notes = f(password.notes, PasswordField::Notes)?;
+        let otp = f(password.otp, PasswordField::Otp)?;
+        // custom fields are newer than associated data, so there are no legacy ones
+        let custom_fields = password
+            .custom_fields
+            .iter()
+            .map(|field| {
+                let field_type = field.field_type.parse()?;
+                let label_aad = custom_field_associated_data(&name, field.id, "label");
+                let value_aad = custom_field_associated_data(&name, field.id, &field.field_type);
+                Ok(CustomField {
+                    id: field.id,
+                    label: decrypt_password_field(&field.label, &label_aad, false, &cipher)?,
+                    field_type,
+                    value: decrypt_password_field(&field.value, &value_aad, false, &cipher)?,
+                })
+            })
+            .collect::<Result<Vec<_>, BackendError>>()?;
 
         Ok(PasswordInfo {
             id,
@@ -90,32 +250,348 @@ pub mod crud_operations {
             username,
             notes,
             password: pass,
+            otp,
+            custom_fields,
+            folder_id: password.folder_id,
+            tag_ids: password.tag_ids,
+            created_at: password.created_at,
+            updated_at: password.updated_at,
+            last_accessed_at: password.last_accessed_at,
+            trashed_at: password.trashed_at,
         })
     }
 
+    /// Reads the names of every password in the SQLite table `PasswordInfo`, sorted alphabetically.
+    /// The master record is never included.
+    /// # Arguments
+    ///
+    /// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+    /// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+    ///
+    pub fn get_password_names(
+        connection: &Connection,
+        key: &[u8],
+    ) -> Result<Vec<String>, BackendError> {
+        // the names are encrypted, so SQLite can't sort them for us
+        let mut stmt = connection
+            .prepare("select name_index, name from PasswordInfo where name_index is not null and trashed_at is null")?;
+        let mut names = stmt
+            .query_map((), |row| {
+                Ok((row.get::<_, Vec<u8>>(0)?, row.get::<_, EncryptedField>(1)?))
+            })?
+            .map(|row| {
+                let (index, name) = row?;
+                decrypt_name(&name, &index, key)
+            })
+            .collect::<Result<Vec<_>, BackendError>>()?;
+        names.sort();
+        Ok(names)
+    }
+
+    /// Lists passwords without decrypting any of their fields, only their names, which is cheap enough to do for thousands of entries.
+    /// The master record is never included.
+    /// # Arguments
+    ///
+    /// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+    /// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+    /// - `options` - how to filter, sort and paginate the listing.
+    ///
+    pub fn list_password_infos(
+        connection: &Connection,
+        key: &[u8],
+        options: &ListOptions,
+    ) -> Result<PasswordPage, BackendError> {
+        let mut stmt = connection.prepare(
+            "select id, name_index, name, email is not null, username is not null, password is not null, notes is not null, folder_id,
+            created_at, max(coalesce(email_updated_at, 0), coalesce(username_updated_at, 0), coalesce(password_updated_at, 0), coalesce(notes_updated_at, 0), coalesce(otp_updated_at, 0), coalesce(custom_fields_updated_at, 0)),
+            last_accessed_at, trashed_at, otp is not null
+            from PasswordInfo where name_index is not null",
+        )?;
+        let rows = stmt.query_map((), |row| {
+            let set = [
+                (row.get::<_, bool>(3)?, PasswordField::Email),
+                (row.get(4)?, PasswordField::Username),
+                (row.get(5)?, PasswordField::Password),
+                (row.get(6)?, PasswordField::Notes),
+                (row.get(12)?, PasswordField::Otp),
+            ];
+            Ok((
+                row.get::<_, i32>(0)?,
+                row.get::<_, Vec<u8>>(1)?,
+                row.get::<_, EncryptedField>(2)?,
+                set.into_iter()
+                    .filter_map(|(is_set, field)| is_set.then_some(field))
+                    .collect::<Vec<_>>(),
+                row.get::<_, Option<i32>>(7)?,
+                (
+                    row.get::<_, Option<i64>>(8)?,
+                    // 0 means no field has a timestamp
+                    Some(row.get::<_, i64>(9)?).filter(|updated_at| *updated_at != 0),
+                    row.get::<_, Option<i64>>(10)?,
+                ),
+                row.get::<_, Option<i64>>(11)?,
+            ))
+        })?;
+
+        let folders = match options.folder {
+            Some(FolderFilter::Subtree(folder_id)) => Some(subtree(connection, folder_id)?),
+            Some(FolderFilter::Folder(folder_id)) => Some([folder_id].into_iter().collect()),
+            _ => None,
+        };
+        let mut tags: HashMap<i32, Vec<i32>> = HashMap::new();
+        let mut stmt =
+            connection.prepare("select password_id, tag_id from PasswordTag order by tag_id")?;
+        for row in stmt.query_map((), |row| Ok((row.get(0)?, row.get(1)?)))? {
+            let (password_id, tag_id) = row?;
+            tags.entry(password_id).or_default().push(tag_id);
+        }
+
+        // names are encrypted, so filtering and sorting by name has to happen here rather than in SQL
+        let filter = options.filter.as_ref().map(|filter| match filter {
+            NameFilter::Prefix(term) => NameFilter::Prefix(term.to_lowercase()),
+            NameFilter::Contains(term) => NameFilter::Contains(term.to_lowercase()),
+        });
+        let mut matches = Vec::new();
+        for row in rows {
+            let (
+                id,
+                index,
+                name,
+                fields,
+                folder_id,
+                (created_at, updated_at, last_accessed_at),
+                trashed_at,
+            ) = row?;
+            let in_trash = match options.trash {
+                TrashFilter::Exclude => trashed_at.is_none(),
+                TrashFilter::Include => true,
+                TrashFilter::Only => trashed_at.is_some(),
+            };
+            let in_folder = match (options.folder, &folders) {
+                (Some(FolderFilter::Unfiled), _) => folder_id.is_none(),
+                (_, Some(folders)) => folder_id.is_some_and(|id| folders.contains(&id)),
+                (_, None) => true,
+            };
+            let tag_ids = tags.remove(&id).unwrap_or_default();
+            let has_tag = options.tag_id.is_none_or(|tag| tag_ids.contains(&tag));
+            let is_stale = options
+                .updated_before
+                .is_none_or(|before| updated_at.is_none_or(|updated_at| updated_at < before));
+            if !in_trash || !in_folder || !has_tag || !is_stale {
+                continue;
+            }
+            let name = decrypt_name(&name, &index, key)?;
+            let lowercase = name.to_lowercase();
+            let is_match = match &filter {
+                None => true,
+                Some(NameFilter::Prefix(term)) => lowercase.starts_with(term.as_str()),
+                Some(NameFilter::Contains(term)) => lowercase.contains(term.as_str()),
+            };
+            if is_match {
+                matches.push((
+                    lowercase,
+                    PasswordSummary {
+                        id,
+                        name,
+                        fields,
+                        folder_id,
+                        tag_ids,
+                        created_at,
+                        updated_at,
+                        last_accessed_at,
+                        trashed_at,
+                    },
+                ));
+            }
+        }
+
+        match options.sort {
+            SortOrder::NameAscending => matches.sort_by(|(a, x), (b, y)| (a, x.id).cmp(&(b, y.id))),
+            SortOrder::NameDescending => {
+                matches.sort_by(|(a, x), (b, y)| (b, y.id).cmp(&(a, x.id)))
+            }
+            // ids are only ever handed out in increasing order
+            SortOrder::Newest => matches.sort_by_key(|(_, summary)| std::cmp::Reverse(summary.id)),
+            SortOrder::Oldest => matches.sort_by_key(|(_, summary)| summary.id),
+            // `None` sorts before any `Some`, so reversed it ends up last
+            SortOrder::RecentlyUsed => matches.sort_by_key(|(_, summary)| {
+                (std::cmp::Reverse(summary.last_accessed_at), summary.id)
+            }),
+            SortOrder::RecentlyUpdated => matches
+                .sort_by_key(|(_, summary)| (std::cmp::Reverse(summary.updated_at), summary.id)),
+        }
+
+        let total = matches.len();
+        let items = matches
+            .into_iter()
+            .map(|(_, summary)| summary)
+            .skip(options.offset)
+            .take(options.limit.unwrap_or(usize::MAX))
+            .collect();
+        Ok(PasswordPage { items, total })
+    }
+
+    /// Searches every password's decrypted fields for `query`, fuzzily, best matches first.
+    /// This decrypts every password, so it is a lot slower than `list_password_infos`.
+    /// Only the fields searched are read and decrypted: custom fields (attachments included) and the OTP never are.
+    /// # Arguments
+    ///
+    /// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+    /// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+    /// - `query` - a string slice that holds what to search for. Case is ignored.
+    /// - `options` - whether to search passwords, and how many results to return.
+    ///
+    pub fn search_password_infos(
+        connection: &Connection,
+        key: &[u8],
+        query: &str,
+        options: &SearchOptions,
+    ) -> Result<Vec<SearchResult>, BackendError> {
+        let allow_legacy = crate::db_ops::legacy_fields_allowed(connection)?;
+        // the master record has no blind index
+        let mut stmt = connection.prepare(
+            "select id, name_index, name, email, username, notes, case when ?1 then password end
+            from PasswordInfo where name_index is not null and trashed_at is null",
+        )?;
+        let rows = stmt.query_map([options.include_password], |row| {
+            Ok(EncryptedPasswordInfo {
+                id: row.get(0)?,
+                name_index: row.get(1)?,
+                name: row.get(2)?,
+                email: row.get(3)?,
+                username: row.get(4)?,
+                notes: row.get(5)?,
+                password: row.get(6)?,
+                otp: None,
+                custom_fields: Vec::new(),
+                folder_id: None,
+                tag_ids: Vec::new(),
+                created_at: None,
+                updated_at: FieldTimestamps::default(),
+                last_accessed_at: None,
+                trashed_at: None,
+            })
+        })?;
+        let mut results = Vec::new();
+        for encrypted in rows {
+            let password = decrypt_password_info(encrypted?, key, allow_legacy)?;
+            let mut fields = vec![
+                (SearchField::Name, Some(password.name.clone())),
+                (SearchField::Field(PasswordField::Email), password.email),
+                (
+                    SearchField::Field(PasswordField::Username),
+                    password.username,
+                ),
+                (SearchField::Field(PasswordField::Notes), password.notes),
+            ];
+            if options.include_password {
+                fields.push((
+                    SearchField::Field(PasswordField::Password),
+                    password.password,
+                ));
+            }
+
+            let highlights: Vec<SearchHighlight> = fields
+                .into_iter()
+                .filter_map(|(field, text)| {
+                    let text = text?;
+                    let m = fuzzy_match(query, &text)?;
+                    Some(SearchHighlight {
+                        field,
+                        text,
+                        indices: m.indices,
+                        score: m.score,
+                    })
+                })
+                .collect();
+            if let Some(score) = highlights.iter().map(|h| h.score).max() {
+                results.push(SearchResult {
+                    id: password.id,
+                    name: password.name,
+                    score,
+                    highlights,
+                });
+            }
+        }
+
+        results.sort_by(|a, b| b.score.cmp(&a.score).then_with(|| a.name.cmp(&b.name)));
+        results.truncate(options.limit.unwrap_or(usize::MAX));
+        Ok(results)
+    }
+
     // the following are functions that implement CRUD (create, read, update, delete)
 
-    /// Reads and decrypts a password from the SQLite table `PasswordInfo`.
+    /// Reads and decrypts a password from the SQLite table `PasswordInfo`, and records that it was accessed just now.
     /// This function will return a result with the `GetPasswordError` enum, which wraps an `Option`;
     /// If no `Password` name matches the given `search_term`, the function will return `None`.
     /// # Arguments
     ///
     /// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
     /// - `search_term` - a string slice that holds the name of the password to search for.
-    /// - `master` - a string slice holding the master password. The master password should be verified/authenticated by the time this function is called.
+    /// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
     ///
     pub fn read_password_info(
         connection: &Connection,
         search_term: &str,
-        master: &str,
+        key: &[u8],
     ) -> std::result::Result<std::option::Option<PasswordInfo>, BackendError> {
-        // interestingly this function is just a combination of 2 other functions..
-        get_password_info(connection, search_term)?
-            .map(|encrypted| decrypt_password_info(encrypted, master))
+        let password = match find_password_info(connection, search_term, key)? {
+            Some(password) => password,
+            None => return Ok(None),
+        };
+        let now = crate::clock::now();
+        connection.execute(
+            "update PasswordInfo set last_accessed_at = ?1 where id = ?2",
+            (now, password.id),
+        )?;
+        Ok(Some(PasswordInfo {
+            last_accessed_at: Some(now),
+            ..password
+        }))
+    }
+    // `read_password_info` without recording an access, for reads the user didn't ask for (e.g. merging an import)
+    pub(crate) fn find_password_info(
+        connection: &Connection,
+        password_name: &str,
+        key: &[u8],
+    ) -> Result<Option<PasswordInfo>, BackendError> {
+        let allow_legacy = crate::db_ops::legacy_fields_allowed(connection)?;
+        get_password_info(connection, password_name, key)?
+            .map(|encrypted| decrypt_password_info(encrypted, key, allow_legacy))
             .transpose()
     }
+    // every password outside the trash, decrypted (e.g. for an export)
+    pub(crate) fn find_all_password_infos(
+        connection: &Connection,
+        key: &[u8],
+    ) -> Result<Vec<PasswordInfo>, BackendError> {
+        let allow_legacy = crate::db_ops::legacy_fields_allowed(connection)?;
+        get_all_password_infos(connection, false)?
+            .into_iter()
+            .map(|encrypted| decrypt_password_info(encrypted, key, allow_legacy))
+            .collect()
+    }
+    /// Records that a password was used just now, e.g. copied or filled in, without reading it.
+    /// Returns how many passwords were marked: 0 if it doesn't exist, otherwise 1.
+    /// # Arguments
+    ///
+    /// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+    /// - `password_name` - a string slice that holds the name of the password.
+    /// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+    ///
+    pub fn mark_used(
+        connection: &Connection,
+        password_name: &str,
+        key: &[u8],
+    ) -> Result<usize, rusqlite::Error> {
+        connection.execute(
+            "update PasswordInfo set last_accessed_at = ?1 where name_index = ?2",
+            (crate::clock::now(), name_index(key, password_name)),
+        )
+    }
     /// Encrypts and inserts a field into the SQLite table `PasswordInfo`.
     /// This function makes use of SQLite's `UPSERT` statement, i.e. create an entry with the given value to insert, or update an existing entry.
+    /// The value being replaced, if any, is kept in the entry's history (see `history`).
     /// (Note: this function serves the purpose of Updating and Creating within the CRUD model)
     /// This function will return a result with the `InsertEncryptedFieldError` enum.
     /// If the function is successful it will return a `usize` of how many entries were updated - should be 1.
@@ -123,61 +599,563 @@ pub mod crud_operations {
     ///
     /// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
     /// - `password_name` - a string slice that holds the name of the password to insert or update into.
-    /// - `master` - a string slice holding the master password. The master password should be verified/authenticated by the time this function is called.
+    /// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
     /// - `column_name` - a `PasswordField` to insert or update data into.
     /// - `data` - a string slice holding the data to encrypt and insert into the entry.
     ///
     pub fn insert_data(
         connection: &Connection,
         password_name: &str,
-        master: &str,
+        key: &[u8],
         column_name: PasswordField,
         data: &str,
     ) -> std::result::Result<usize, BackendError> {
-        let cipher = gen_cipher(master, password_name);
-        let nonce: GenericArray<u8, typenum::U12> = Aes256Gcm::generate_nonce(OsRng);
-        let mut n = nonce.to_vec();
-
-        let mut encrypted = cipher.encrypt(&nonce, data.as_bytes()).unwrap();
-        n.append(&mut encrypted);
+        // only store URIs we can compute codes for
+        if column_name == PasswordField::Otp {
+            crate::otp::Otp::parse_uri(data)?;
+        }
+        let index = name_index(key, password_name);
+        // "github" updates an entry called "GitHub", whose fields are encrypted under "GitHub"
+        let (id, name) = match find_entry(connection, password_name, key)? {
+            Some((id, name)) => (Some(id), name),
+            None => (None, password_name.to_string()),
+        };
 
-        let ciphertext = hex::encode(n);
+        let cipher = gen_cipher(key, &name);
+        // keep the value we're about to replace. it's recorded first, so if the update fails
+        // the worst case is a history entry that's the same as the current value
+        if let Some(id) = id {
+            let previous: Option<EncryptedField> = connection.query_row(
+                format!("select {} from PasswordInfo where id = ?", column_name).as_str(),
+                [id],
+                |row| row.get(0),
+            )?;
+            if let Some(previous) = previous {
+                let allow_legacy = crate::db_ops::legacy_fields_allowed(connection)?;
+                let aad = associated_data(&name, &column_name);
+                let previous = match previous {
+                    EncryptedField::LegacyHex(_) => decrypt_password_field(
+                        &previous,
+                        &aad,
+                        allow_legacy,
+                        &legacy_gen_cipher(key, &name),
+                    )?,
+                    EncryptedField::Envelope(_) => {
+                        decrypt_password_field(&previous, &aad, allow_legacy, &cipher)?
+                    }
+                };
+                if previous == data {
+                    return Ok(1);
+                }
+                crate::history::record(connection, key, id, &column_name, &previous)?;
+            }
+        }
+        let ciphertext =
+            encrypt_password_field(data, &associated_data(&name, &column_name), &cipher);
+        let encrypted_name =
+            encrypt_password_field(&name, &name_associated_data(&index), &name_cipher(key));
 
-        let params = [password_name, ciphertext.as_str()];
+        let params = (&index, encrypted_name, ciphertext, crate::clock::now());
 
         Ok(connection.execute(
             format!(
-                "insert into PasswordInfo(name, {}) values (?1, ?2) on conflict(name) do update set {} = ?2 ",
-                column_name, column_name
+                "insert into PasswordInfo(name_index, name, {0}, {0}_updated_at, created_at) values (?1, ?2, ?3, ?4, ?4)
+                on conflict(name_index) do update set {0} = ?3, {0}_updated_at = ?4",
+                column_name
             )
             .as_str(),
             params,
         )?)
     }
 
-    /// Deletes one record from the SQLite table `PasswordInfo` Use with caution!.
+    // looks up the id and the actual (decrypted) name of the entry `password_name` refers to
+    pub(crate) fn find_entry(
+        connection: &Connection,
+        password_name: &str,
+        key: &[u8],
+    ) -> Result<Option<(i32, String)>, BackendError> {
+        let index = name_index(key, password_name);
+        let existing: Option<(i32, EncryptedField)> = connection
+            .query_row(
+                "select id, name from PasswordInfo where name_index = ?",
+                [&index],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()?;
+        existing
+            .map(|(id, name)| Ok((id, decrypt_name(&name, &index, key)?)))
+            .transpose()
+    }
+
+    // encrypts a custom field's label and value for the entry `password_name`, bound to `field.id`
+    fn encrypt_custom_field(
+        password_name: &str,
+        field: &CustomField,
+        cipher: &AesGcm<Aes256, U12>,
+    ) -> (EncryptedField, EncryptedField) {
+        let label_aad = custom_field_associated_data(password_name, field.id, "label");
+        let value_aad =
+            custom_field_associated_data(password_name, field.id, &field.field_type.to_string());
+        (
+            encrypt_password_field(&field.label, &label_aad, cipher),
+            encrypt_password_field(&field.value, &value_aad, cipher),
+        )
+    }
+
+    /// Encrypts and adds a custom field to a password, creating the password if it doesn't exist (like `insert_data`).
+    /// Runs inside a transaction. Returns the id of the new custom field.
+    /// # Arguments
+    ///
+    /// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+    /// - `password_name` - a string slice that holds the name of the password to add the field to.
+    /// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+    /// - `field` - the custom field to add. Its `id` is ignored.
+    ///
+    pub fn insert_custom_field(
+        connection: &Connection,
+        password_name: &str,
+        key: &[u8],
+        field: &CustomField,
+    ) -> Result<i32, BackendError> {
+        let transaction = connection.unchecked_transaction()?;
+        let id = add_custom_field(&transaction, password_name, key, field)?;
+        transaction.commit()?;
+        Ok(id)
+    }
+
+    // `insert_custom_field` without its own transaction, for callers that are already in one
+    pub(crate) fn add_custom_field(
+        transaction: &Connection,
+        password_name: &str,
+        key: &[u8],
+        field: &CustomField,
+    ) -> Result<i32, BackendError> {
+        field.validate()?;
+        let (password_id, name) = match find_entry(transaction, password_name, key)? {
+            Some(entry) => entry,
+            None => {
+                let index = name_index(key, password_name);
+                let encrypted_name = encrypt_password_field(
+                    password_name,
+                    &name_associated_data(&index),
+                    &name_cipher(key),
+                );
+                transaction.execute(
+                    "insert into PasswordInfo(name_index, name, created_at) values (?1, ?2, ?3)",
+                    (&index, encrypted_name, crate::clock::now()),
+                )?;
+                (
+                    transaction.last_insert_rowid() as i32,
+                    password_name.to_string(),
+                )
+            }
+        };
+
+        // the ciphertexts are bound to the id, which we only know once the row exists
+        transaction.execute(
+            "insert into CustomField(password_id, label, field_type, value) values (?1, x'', ?2, x'')",
+            (password_id, field.field_type.to_string()),
+        )?;
+        let field = CustomField {
+            id: transaction.last_insert_rowid() as i32,
+            ..field.clone()
+        };
+        let (label, value) = encrypt_custom_field(&name, &field, &gen_cipher(key, &name));
+        transaction.execute(
+            "update CustomField set label = ?1, value = ?2 where id = ?3",
+            (label, value, field.id),
+        )?;
+        touch_custom_fields(transaction, password_id)?;
+        Ok(field.id)
+    }
+
+    // the custom fields share one timestamp, see `FieldTimestamps::custom_fields`
+    fn touch_custom_fields(
+        connection: &Connection,
+        password_id: i32,
+    ) -> Result<usize, rusqlite::Error> {
+        connection.execute(
+            "update PasswordInfo set custom_fields_updated_at = ?1 where id = ?2",
+            (crate::clock::now(), password_id),
+        )
+    }
+
+    /// Encrypts and writes a custom field over the one with the same `id`, which must belong to the password `password_name`.
+    /// Returns how many custom fields were updated: 0 if there's no such field (or password), otherwise 1.
+    /// # Arguments
+    ///
+    /// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+    /// - `password_name` - a string slice that holds the name of the password the field belongs to.
+    /// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+    /// - `field` - the custom field to write.
+    ///
+    pub fn update_custom_field(
+        connection: &Connection,
+        password_name: &str,
+        key: &[u8],
+        field: &CustomField,
+    ) -> Result<usize, BackendError> {
+        field.validate()?;
+        let (password_id, name) = match find_entry(connection, password_name, key)? {
+            Some(entry) => entry,
+            None => return Ok(0),
+        };
+        let (label, value) = encrypt_custom_field(&name, field, &gen_cipher(key, &name));
+        let updated = connection.execute(
+            "update CustomField set label = ?1, field_type = ?2, value = ?3 where id = ?4 and password_id = ?5",
+            (label, field.field_type.to_string(), value, field.id, password_id),
+        )?;
+        if updated > 0 {
+            touch_custom_fields(connection, password_id)?;
+        }
+        Ok(updated)
+    }
+
+    /// Deletes a custom field, which must belong to the password `password_name`.
+    /// Returns how many custom fields were deleted: 0 if there's no such field (or password), otherwise 1.
+    /// # Arguments
+    ///
+    /// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+    /// - `password_name` - a string slice that holds the name of the password the field belongs to.
+    /// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+    /// - `field_id` - the id of the custom field to delete.
+    ///
+    pub fn delete_custom_field(
+        connection: &Connection,
+        password_name: &str,
+        key: &[u8],
+        field_id: i32,
+    ) -> Result<usize, rusqlite::Error> {
+        let index = name_index(key, password_name);
+        let deleted = connection.execute(
+            "delete from CustomField where id = ?1 and password_id = (select id from PasswordInfo where name_index = ?2)",
+            (field_id, &index),
+        )?;
+        if deleted > 0 {
+            connection.execute(
+                "update PasswordInfo set custom_fields_updated_at = ?1 where name_index = ?2",
+                (crate::clock::now(), &index),
+            )?;
+        }
+        Ok(deleted)
+    }
+
+    /// Encrypts every field of a decrypted `Password`, name included, and writes them over the row with the same `id`.
+    /// Custom fields are written over the ones with the same `id`; this doesn't add or remove any.
+    /// # Arguments
+    ///
+    /// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+    /// - `password` - a `Password` with decrypted fields. Its `id` must match an existing row.
+    /// - `key` - the vault key to encrypt under, see `db_ops::unlock_vault_key`.
+    ///
+    pub fn write_password_info(
+        connection: &Connection,
+        password: &PasswordInfo,
+        key: &[u8],
+    ) -> Result<usize, BackendError> {
+        let cipher = gen_cipher(key, &password.name);
+        let f = |field: &Option<String>, column: PasswordField| {
+            let aad = associated_data(&password.name, &column);
+            field
+                .as_deref()
+                .map(|data| encrypt_password_field(data, &aad, &cipher))
+        };
+        // a trashed password keeps its placeholder index, see `delete_password_info`
+        let index = match password.trashed_at {
+            Some(_) => trash_index(password.id),
+            None => name_index(key, &password.name),
+        };
+        let name = encrypt_password_field(
+            &password.name,
+            &name_associated_data(&index),
+            &name_cipher(key),
+        );
+        let updated = connection.execute(
+            "update PasswordInfo set name_index = ?7, name = ?1, email = ?2, username = ?3, password = ?4, notes = ?5, otp = ?8 where id = ?6",
+            (
+                name,
+                f(&password.email, PasswordField::Email),
+                f(&password.username, PasswordField::Username),
+                f(&password.password, PasswordField::Password),
+                f(&password.notes, PasswordField::Notes),
+                password.id,
+                index,
+                f(&password.otp, PasswordField::Otp),
+            ),
+        )?;
+        for field in &password.custom_fields {
+            let (label, value) = encrypt_custom_field(&password.name, field, &cipher);
+            connection.execute(
+                "update CustomField set label = ?1, field_type = ?2, value = ?3 where id = ?4 and password_id = ?5",
+                (label, field.field_type.to_string(), value, field.id, password.id),
+            )?;
+        }
+        Ok(updated)
+    }
+
+    /// Decrypts every password with `old_key` and encrypts it again with `new_key`.
+    /// Stops at the first error; run this inside a transaction so a failure leaves nothing half-done.
+    /// # Arguments
+    ///
+    /// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+    /// - `old_key` - the vault key every password is currently encrypted under.
+    /// - `new_key` - the vault key to encrypt under instead.
+    ///
+    pub fn reencrypt_password_infos(
+        connection: &Connection,
+        old_key: &[u8],
+        new_key: &[u8],
+    ) -> Result<usize, BackendError> {
+        let allow_legacy = crate::db_ops::legacy_fields_allowed(connection)?;
+        let encrypted = get_all_password_infos(connection, true)?;
+        for password in &encrypted {
+            let decrypted = decrypt_password_info(password.clone(), old_key, allow_legacy)?;
+            write_password_info(connection, &decrypted, new_key)?;
+        }
+        Ok(encrypted.len())
+    }
+
+    /// Finds a name that no password uses yet: `password_name` itself if it's free, otherwise `password_name (2)`, `password_name (3)`...
+    /// # Arguments
+    ///
+    /// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+    /// - `password_name` - a string slice that holds the name to start from.
+    /// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+    ///
+    pub fn available_name(
+        connection: &Connection,
+        password_name: &str,
+        key: &[u8],
+    ) -> Result<String, rusqlite::Error> {
+        let mut name = password_name.to_string();
+        let mut n = 2;
+        while crate::db_ops::check_password_info_exists(connection, &name, key)? {
+            name = format!("{} ({})", password_name, n);
+            n += 1;
+        }
+        Ok(name)
+    }
+
+    /// Encrypts the name of every password from before names were encrypted, giving clashing names a suffix (see `available_name`).
+    /// Run this inside a transaction. Returns how many passwords were migrated.
+    /// # Arguments
+    ///
+    /// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+    /// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+    ///
+    pub fn encrypt_legacy_names(
+        connection: &Connection,
+        key: &[u8],
+    ) -> Result<usize, BackendError> {
+        let allow_legacy = crate::db_ops::legacy_fields_allowed(connection)?;
+        let mut stmt = connection.prepare(
+            "select id, name, email, username, password, notes from PasswordInfo where name_index is null and name != ?",
+        )?;
+        let legacy = stmt
+            .query_map([crate::db_ops::MASTER_KEYWORD], |row| {
+                Ok((
+                    row.get::<_, String>(1)?,
+                    EncryptedPasswordInfo {
+                        id: row.get(0)?,
+                        // there's no encrypted name yet, `decrypt_fields` doesn't look at these
+                        name_index: Vec::new(),
+                        name: EncryptedField::Envelope(Vec::new()),
+                        email: row.get(2)?,
+                        username: row.get(3)?,
+                        password: row.get(4)?,
+                        notes: row.get(5)?,
+                        // there was no OTP field back then
+                        otp: None,
+                        // custom fields and tags need an encrypted name to be added
+                        custom_fields: Vec::new(),
+                        folder_id: None,
+                        tag_ids: Vec::new(),
+                        created_at: None,
+                        updated_at: FieldTimestamps::default(),
+                        last_accessed_at: None,
+                        trashed_at: None,
+                    },
+                ))
+            })?
+            .collect::<Result<Vec<_>, rusqlite::Error>>()?;
+
+        for (name, password) in &legacy {
+            let decrypted = decrypt_fields(password.clone(), name.clone(), key, allow_legacy)?;
+            let renamed = PasswordInfo {
+                name: available_name(connection, name, key)?,
+                ..decrypted
+            };
+            write_password_info(connection, &renamed, key)?;
+        }
+        Ok(legacy.len())
+    }
+
+    /// Renames a password, re-encrypting it under the new name. Runs inside a transaction.
+    /// Returns how many entries were renamed: 0 if `old_name` doesn't exist, otherwise 1.
+    /// # Arguments
+    ///
+    /// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+    /// - `old_name` - a string slice that holds the current name of the password.
+    /// - `new_name` - a string slice that holds the name to rename the password to.
+    /// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+    ///
+    pub fn rename_password_info(
+        connection: &Connection,
+        old_name: &str,
+        new_name: &str,
+        key: &[u8],
+    ) -> Result<usize, BackendError> {
+        for name in [old_name, new_name] {
+            if name == crate::db_ops::MASTER_KEYWORD {
+                return Err(BackendError::ReservedName(name.to_string()));
+            }
+        }
+
+        let transaction = connection.unchecked_transaction()?;
+        // not `read_password_info`, renaming isn't using the password
+        let allow_legacy = crate::db_ops::legacy_fields_allowed(&transaction)?;
+        let password = match get_password_info(&transaction, old_name, key)? {
+            Some(encrypted) => decrypt_password_info(encrypted, key, allow_legacy)?,
+            None => return Ok(0),
+        };
+        // renaming "github" to "GitHub" doesn't clash with itself
+        if name_index(key, old_name) != name_index(key, new_name)
+            && crate::db_ops::check_password_info_exists(&transaction, new_name, key)?
+        {
+            return Err(BackendError::NameAlreadyExists(new_name.to_string()));
+        }
+
+        let renamed = PasswordInfo {
+            name: new_name.to_string(),
+            ..password
+        };
+        let updated = write_password_info(&transaction, &renamed, key)?;
+        transaction.commit()?;
+        Ok(updated)
+    }
+
+    /// Moves a password to the trash, see `restore_password_info` and `delete_permanently`. Runs inside a transaction.
+    /// Returns how many passwords were trashed: 0 if none has the given name, otherwise 1.
     ///  # Arguments
     ///
     /// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
-    /// - `password_name` - a string slice that holds the name of the password to insert or update into.
+    /// - `password_name` - a string slice that holds the name of the password to trash.
+    /// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
     ///
     pub fn delete_password_info(
         connection: &Connection,
         password_name: &str,
+        key: &[u8],
+    ) -> Result<usize, BackendError> {
+        let transaction = connection.unchecked_transaction()?;
+        let allow_legacy = crate::db_ops::legacy_fields_allowed(&transaction)?;
+        let password = match get_password_info(&transaction, password_name, key)? {
+            Some(encrypted) => decrypt_password_info(encrypted, key, allow_legacy)?,
+            None => return Ok(0),
+        };
+        let now = crate::clock::now();
+        let trashed = PasswordInfo {
+            trashed_at: Some(now),
+            ..password
+        };
+        // the name is bound to its index, so it has to be encrypted again under the placeholder one
+        write_password_info(&transaction, &trashed, key)?;
+        transaction.execute(
+            "update PasswordInfo set trashed_at = ?1 where id = ?2",
+            (now, trashed.id),
+        )?;
+        transaction.commit()?;
+        Ok(1)
+    }
+
+    /// Takes a password back out of the trash, giving it a suffix if its name was taken. Runs inside a transaction.
+    /// Returns the name it was restored under, or `None` if no password in the trash has the given id.
+    ///  # Arguments
+    ///
+    /// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+    /// - `password_id` - the id of the trashed password.
+    /// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+    ///
+    pub fn restore_password_info(
+        connection: &Connection,
+        password_id: i32,
+        key: &[u8],
+    ) -> Result<Option<String>, BackendError> {
+        let transaction = connection.unchecked_transaction()?;
+        let allow_legacy = crate::db_ops::legacy_fields_allowed(&transaction)?;
+        let password = match get_trashed_password_info(&transaction, password_id)? {
+            Some(encrypted) => decrypt_password_info(encrypted, key, allow_legacy)?,
+            None => return Ok(None),
+        };
+        let name = available_name(&transaction, &password.name, key)?;
+        let restored = PasswordInfo {
+            name: name.clone(),
+            trashed_at: None,
+            ..password
+        };
+        write_password_info(&transaction, &restored, key)?;
+        transaction.execute(
+            "update PasswordInfo set trashed_at = null where id = ?",
+            [password_id],
+        )?;
+        transaction.commit()?;
+        Ok(Some(name))
+    }
+
+    /// Deletes a password in the trash for good, along with its custom fields, tags and history. There's no undo!
+    /// Runs inside a transaction. Returns how many passwords were deleted: 0 if no password in the trash has the given id, otherwise 1.
+    ///  # Arguments
+    ///
+    /// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+    /// - `password_id` - the id of the trashed password.
+    ///
+    pub fn delete_permanently(
+        connection: &Connection,
+        password_id: i32,
     ) -> Result<usize, rusqlite::Error> {
-        connection.execute("delete from PasswordInfo where name = ?", [password_name])
+        let transaction = connection.unchecked_transaction()?;
+        let deleted = remove_trashed(&transaction, password_id)?;
+        transaction.commit()?;
+        Ok(deleted)
+    }
+
+    // everything that refers to the password goes first
+    pub(crate) fn remove_trashed(
+        connection: &Connection,
+        password_id: i32,
+    ) -> Result<usize, rusqlite::Error> {
+        let trashed = connection
+            .query_row(
+                "select 1 from PasswordInfo where id = ? and trashed_at is not null",
+                [password_id],
+                |_| Ok(()),
+            )
+            .optional()?;
+        if trashed.is_none() {
+            return Ok(0);
+        }
+        for table in ["CustomField", "PasswordTag", "FieldHistory"] {
+            connection.execute(
+                format!("delete from {} where password_id = ?", table).as_str(),
+                [password_id],
+            )?;
+        }
+        connection.execute("delete from PasswordInfo where id = ?", [password_id])
     }
 }
 
 pub mod tauri {
-    use std::fs;
+    use std::{
+        fs,
+        path::{Path, PathBuf},
+    };
 
     use rusqlite::Connection;
     use tauri::AppHandle;
 
-    use super::create_table;
+    use crate::{error::BackendError, migrations};
 
-    pub fn init_database(app_handle: &AppHandle) -> Result<Connection, rusqlite::Error> {
+    pub fn init_database(app_handle: &AppHandle) -> Result<Connection, BackendError> {
         //
         // this is just preliminary stuff to setup the proper directory to store our data/database.
         // this is also sourced from : https://github.com/RandomEngy/tauri-sqlite
@@ -187,23 +1165,76 @@ pub mod tauri {
             .app_data_dir()
             .expect("The app data directory must exist.");
         fs::create_dir_all(&app_dir).expect("The app data directory should be created.");
+        open_database(&app_dir)
+    }
+
+    /// Opens (or creates) the database in `app_dir`, upgrading it if it's from an older version.
+    /// A copy of the database is kept before it's upgraded, see `backup_path`. The copy still holds whatever the upgrade
+    /// (and the upgrade at unlock, see `state::Session::open`) gets rid of, e.g. plaintext names and the old master hash,
+    /// so it's deleted once the vault has been unlocked, see `remove_backups`.
+    /// # Arguments
+    ///
+    /// - `app_dir` - the directory the database is stored in.
+    ///
+    pub fn open_database(app_dir: &Path) -> Result<Connection, BackendError> {
         let sqlite_path = app_dir.join("data.db");
 
         // now we'll actually open our connection to the database.
         let conn = Connection::open(sqlite_path)?;
-        create_table(&conn)?; // here we will create the table if it doesn't exist
+        // deleted (and overwritten) data is zeroed, so e.g. a name from before names were encrypted doesn't linger in a free page
+        conn.execute_batch("PRAGMA secure_delete = ON")?;
 
-        // TODO: implement check for and update database version ?
+        // create the tables if they don't exist, or upgrade them if they're from an older version.
+        let backup_path = backup_path(app_dir, migrations::user_version(&conn)?);
+        migrations::migrate(&conn, Some(&backup_path))?;
 
         Ok(conn)
     }
-}
 
-use crate::{crypto::*, error::*, password::PasswordField};
+    /// Picks a file name for a backup of the database at `version` that isn't taken yet:
+    /// `data.v{version}.db.bak`, otherwise `data.v{version}.2.db.bak`, `data.v{version}.3.db.bak`...
+    /// A failed upgrade leaves its backup behind, and `migrations::backup` can't write over it.
+    /// # Arguments
+    ///
+    /// - `app_dir` - the directory the database is stored in.
+    /// - `version` - the schema version of the database, see `migrations::user_version`.
+    ///
+    fn backup_path(app_dir: &Path, version: u32) -> PathBuf {
+        let mut path = app_dir.join(format!("data.v{}.db.bak", version));
+        let mut n = 2;
+        while path.exists() {
+            path = app_dir.join(format!("data.v{}.{}.db.bak", version, n));
+            n += 1;
+        }
+        path
+    }
+
+    /// Deletes every backup `open_database` has made (`data.v*.db.bak`). Returns how many were deleted.
+    /// # Arguments
+    ///
+    /// - `app_dir` - the directory the database is stored in.
+    ///
+    pub fn remove_backups(app_dir: &Path) -> std::io::Result<usize> {
+        let mut removed = 0;
+        for entry in fs::read_dir(app_dir)? {
+            let path = entry?.path();
+            let is_backup = path
+                .file_name()
+                .and_then(|name| name.to_str())
+                .is_some_and(|name| name.starts_with("data.v") && name.ends_with(".db.bak"));
+            if is_backup {
+                fs::remove_file(path)?;
+                removed += 1;
+            }
+        }
+        Ok(removed)
+    }
+}
 
-use rusqlite::{Connection, OptionalExtension};
+use crate::{crypto::*, error::*, migrations, password::PasswordField};
 
-use crud_operations::get_password_info;
+use rusqlite::{Connection, OptionalExtension, TransactionBehavior};
+use zeroize::Zeroizing;
 
 // I've considered using format!() here to make sure the struct name/fields match this statement
 // (and potentially other SQLite statement strings), but I think that may just be overengineering.
@@ -213,38 +1244,10 @@ pub fn establish_connection() -> Result<rusqlite::Connection, rusqlite::Error> {
     Connection::open("./data.db")
 }
 
-// I've considered using format!() here to make sure the struct name/fields match this statement
-// (and potentially other SQLite statement strings), but I think that may just be overengineering.
-
-/// Creates the SQLite table equivelant of the `Password` struct.
-pub fn create_table(connection: &Connection) -> Result<usize, rusqlite::Error> {
-    connection.execute(
-        "CREATE TABLE IF NOT EXISTS PasswordInfo (
-        id INTEGER NOT NULL PRIMARY KEY,
-        name TEXT NOT NULL UNIQUE,
-        username TEXT DEFAULT NULL,
-        email TEXT DEFAULT NULL,
-        password TEXT DEFAULT NULL,
-        notes TEXT DEFAULT NULL
-      );",
-        (),
-    )
-}
-///
-pub fn initalize_database() -> Result<rusqlite::Connection, rusqlite::Error> {
-    let connection = Connection::open("./data.db")?;
-    connection.execute(
-        "CREATE TABLE IF NOT EXISTS PasswordInfo (
-        id INTEGER NOT NULL PRIMARY KEY,
-        name TEXT NOT NULL UNIQUE,
-        username TEXT DEFAULT NULL,
-        email TEXT DEFAULT NULL,
-        password TEXT DEFAULT NULL,
-        notes TEXT DEFAULT NULL
-      );",
-        (),
-    )?;
-    Ok(connection)
+/// Creates the SQLite tables, or upgrades them if they're from an older version (see `migrations`).
+/// Returns how many migrations were applied.
+pub fn create_table(connection: &Connection) -> Result<usize, BackendError> {
+    migrations::migrate(connection, None)
 }
 
 /// Check if a password exists by checking if an `optional()` query `is_some()`, i.e. returns `false` if `None`.
@@ -252,46 +1255,442 @@ pub fn initalize_database() -> Result<rusqlite::Connection, rusqlite::Error> {
 ///
 /// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
 /// - `password_name` - a string slice that holds the name of the password to insert or update into.
+/// - `key` - the vault key of the unlocked session, see `unlock_vault_key`.
 ///
 pub fn check_password_info_exists(
     connection: &Connection,
     password_name: &str,
+    key: &[u8],
 ) -> Result<bool, rusqlite::Error> {
+    let mut stmt = connection.prepare("select * from PasswordInfo where name_index = ? ")?;
+    let exists = stmt
+        .query_row([name_index(key, password_name)], |_| Ok(()))
+        .optional()?
+        .is_some();
+    Ok(exists)
+}
+/// Check if a master password has been registered. The master record's name is the only one stored in plaintext,
+/// since it has to be found before the vault is unlocked.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+///
+pub fn check_master_exists(connection: &Connection) -> Result<bool, rusqlite::Error> {
     let mut stmt = connection.prepare("select * from PasswordInfo where name = ? ")?;
     let master_exists = stmt
-        .query_row([password_name], |_| Ok(()))
+        .query_row([MASTER_KEYWORD], |_| Ok(()))
         .optional()?
         .is_some();
     Ok(master_exists)
 }
-/// Check if a password exists. May fail with `rusqlite::Error`.
-/// Checks if an `optional()` query `is_some()`, i.e. returns `false` if `None`.
+/// Checks `master` against the master record's verifier, upgrading a legacy SHA-256 hash when it matches.
 /// ///  # Arguments
 ///
 /// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
 /// - `master` - a string slice that holds the master password.
+/// - `column` - which field of the master record to check against.
 ///
 pub fn authenticate(
     connection: &Connection,
     master: &str,
     column: PasswordField,
 ) -> Result<bool, BackendError> {
-    // unwrapping values because these values MUST exist at this point in the application
-    let master_record = get_password_info(connection, MASTER_KEYWORD)?.unwrap();
-    let data = match column {
-        PasswordField::Password => Ok(master_record.password.unwrap()),
-        PasswordField::Notes => Ok(master_record.notes.unwrap()),
-        _ => Err(BackendError::InvalidMasterRecordField),
-    }?;
-
-    Ok(hash(master.as_bytes()).to_vec() == hex::decode(data)?)
-}
-
+    if !matches!(column, PasswordField::Password | PasswordField::Notes) {
+        return Err(BackendError::InvalidMasterRecordField);
+    }
+    // the master record's fields are verifiers, not encrypted fields, so they're read as plain strings
+    let data: String = connection.query_row(
+        format!("select {} from PasswordInfo where name = ?", column).as_str(),
+        [MASTER_KEYWORD],
+        |row| row.get(0),
+    )?;
+
+    match verify_master(master, &data)? {
+        Verification::Valid => Ok(true),
+        Verification::Invalid => Ok(false),
+        Verification::ValidLegacy => {
+            // this is the only time we have the plaintext, so take the chance to replace the old unsalted hash
+            connection.execute(
+                format!("update PasswordInfo set {} = ?1 where name = ?2", column).as_str(),
+                [hash_master(master)?.as_str(), MASTER_KEYWORD],
+            )?;
+            Ok(true)
+        }
+    }
+}
+
+/// Reads a value from the `VaultMetadata` table. Returns `None` if the key isn't set.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `key` - a string slice that holds the metadata key.
+///
+pub fn get_metadata(connection: &Connection, key: &str) -> Result<Option<String>, rusqlite::Error> {
+    connection
+        .query_row(
+            "select value from VaultMetadata where key = ?",
+            [key],
+            |row| row.get(0),
+        )
+        .optional()
+}
+
+/// Sets a value in the `VaultMetadata` table, replacing any existing value.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `key` - a string slice that holds the metadata key.
+/// - `value` - a string slice that holds the value to store.
+///
+pub fn set_metadata(
+    connection: &Connection,
+    key: &str,
+    value: &str,
+) -> Result<usize, rusqlite::Error> {
+    connection.execute(
+        "insert into VaultMetadata(key, value) values (?1, ?2) on conflict(key) do update set value = ?2",
+        [key, value],
+    )
+}
+
+/// Reads the vault's KDF parameters. Legacy vaults (see `unlock_vault_key`) have none stored.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+///
+pub fn read_kdf_params(connection: &Connection) -> Result<KdfParams, BackendError> {
+    let algorithm = match get_metadata(connection, "kdf.algorithm")? {
+        Some(algorithm) => algorithm.parse()?,
+        None => return Err(BackendError::InvalidMetadata("kdf.algorithm".to_string())),
+    };
+    let number = |key: &str| -> Result<u32, BackendError> {
+        get_metadata(connection, key)?
+            .and_then(|value| value.parse().ok())
+            .ok_or_else(|| BackendError::InvalidMetadata(key.to_string()))
+    };
+    let salt = get_metadata(connection, "kdf.salt")?
+        .ok_or_else(|| BackendError::InvalidMetadata("kdf.salt".to_string()))?;
+
+    Ok(KdfParams {
+        algorithm,
+        memory_kib: number("kdf.memory_kib")?,
+        iterations: number("kdf.iterations")?,
+        parallelism: number("kdf.parallelism")?,
+        salt: hex::decode(salt)?,
+    })
+}
+
+/// Stores the vault's KDF parameters, replacing any existing ones.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `params` - the parameters to store.
+///
+pub fn write_kdf_params(
+    connection: &Connection,
+    params: &KdfParams,
+) -> Result<(), rusqlite::Error> {
+    set_metadata(connection, "kdf.algorithm", &params.algorithm.to_string())?;
+    set_metadata(connection, "kdf.memory_kib", &params.memory_kib.to_string())?;
+    set_metadata(connection, "kdf.iterations", &params.iterations.to_string())?;
+    set_metadata(
+        connection,
+        "kdf.parallelism",
+        &params.parallelism.to_string(),
+    )?;
+    set_metadata(connection, "kdf.salt", &hex::encode(&params.salt))?;
+    Ok(())
+}
+
+/// Checks whether the vault may still contain fields encrypted without associated data, i.e. `migrate_associated_data` hasn't run yet.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+///
+pub fn legacy_fields_allowed(connection: &Connection) -> Result<bool, rusqlite::Error> {
+    Ok(get_metadata(connection, "field_format")?.is_none())
+}
+
+/// Re-encrypts every field of a vault that predates associated data (see `crypto::associated_data`). Runs inside a transaction.
+/// Returns how many passwords were re-encrypted, 0 if the vault has already been migrated.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `key` - the vault key of the unlocked session, see `unlock_vault_key`.
+///
+pub fn migrate_associated_data(connection: &Connection, key: &[u8]) -> Result<usize, BackendError> {
+    if !legacy_fields_allowed(connection)? {
+        return Ok(0);
+    }
+    let transaction = connection.unchecked_transaction()?;
+    let migrated = crud_operations::reencrypt_password_infos(&transaction, key, key)?;
+    set_metadata(
+        &transaction,
+        "field_format",
+        &FIELD_FORMAT_VERSION.to_string(),
+    )?;
+    transaction.commit()?;
+    Ok(migrated)
+}
+
+/// How long passwords stay in the trash, unless the user sets otherwise.
+pub const DEFAULT_TRASH_RETENTION_DAYS: u32 = 30;
+
+/// Reads how many days passwords stay in the trash before `purge_trash` deletes them.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+///
+pub fn trash_retention(connection: &Connection) -> Result<u32, BackendError> {
+    match get_metadata(connection, "trash.retention_days")? {
+        Some(value) => value
+            .parse()
+            .map_err(|_| BackendError::InvalidMetadata("trash.retention_days".to_string())),
+        None => Ok(DEFAULT_TRASH_RETENTION_DAYS),
+    }
+}
+
+/// Sets how many days passwords stay in the trash before `purge_trash` deletes them.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `days` - how many days to keep trashed passwords. 0 means they go at the next purge.
+///
+pub fn set_trash_retention(connection: &Connection, days: u32) -> Result<usize, rusqlite::Error> {
+    set_metadata(connection, "trash.retention_days", &days.to_string())
+}
+
+/// Deletes every password that has been in the trash for longer than `trash_retention`, see `crud_operations::delete_permanently`.
+/// Runs inside a transaction. Returns how many passwords were deleted.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+///
+pub fn purge_trash(connection: &Connection) -> Result<usize, BackendError> {
+    let cutoff = crate::clock::now() - trash_retention(connection)? as i64 * 24 * 60 * 60;
+    let transaction = connection.unchecked_transaction()?;
+    let mut stmt = transaction.prepare("select id from PasswordInfo where trashed_at <= ?")?;
+    let expired = stmt
+        .query_map([cutoff], |row| row.get(0))?
+        .collect::<Result<Vec<i32>, _>>()?;
+    drop(stmt);
+    for password_id in &expired {
+        crud_operations::remove_trashed(&transaction, *password_id)?;
+    }
+    transaction.commit()?;
+    Ok(expired.len())
+}
+
+/// Encrypts the names of a vault from before names were encrypted, see `crud_operations::encrypt_legacy_names`.
+/// Runs inside a transaction. Does nothing, and returns 0, if every name is already encrypted.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `key` - the vault key of the unlocked session, see `unlock_vault_key`.
+///
+pub fn migrate_names(connection: &Connection, key: &[u8]) -> Result<usize, BackendError> {
+    let transaction = connection.unchecked_transaction()?;
+    let migrated = crud_operations::encrypt_legacy_names(&transaction, key)?;
+    transaction.commit()?;
+    Ok(migrated)
+}
+
+/// Unwraps the vault key an unlocked session uses. A legacy vault (no wrapped key) derives its entry keys
+/// straight from the master password, so it's moved to a new vault key first, see `upgrade_legacy_vault`.
+/// The master password should be verified/authenticated by the time this function is called.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `master` - a string slice that holds the master password.
+///
+pub fn unlock_vault_key(connection: &Connection, master: &str) -> Result<[u8; 32], BackendError> {
+    match get_metadata(connection, "vault_key")? {
+        Some(wrapped) => {
+            let master_key =
+                Zeroizing::new(derive_master_key(master, &read_kdf_params(connection)?)?);
+            unwrap_vault_key(&master_key, &hex::decode(wrapped)?)
+        }
+        None => upgrade_legacy_vault(connection, master),
+    }
+}
+
+/// Reads the key every per-entry key is derived from: the vault key, or the master password itself in a legacy vault.
+/// Unlike `unlock_vault_key` this doesn't upgrade a legacy vault, so it can be used inside a transaction.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `master` - a string slice that holds the master password.
+///
+fn entry_key_root(
+    connection: &Connection,
+    master: &str,
+) -> Result<Zeroizing<Vec<u8>>, BackendError> {
+    match get_metadata(connection, "vault_key")? {
+        Some(_) => Ok(Zeroizing::new(
+            unlock_vault_key(connection, master)?.to_vec(),
+        )),
+        None => Ok(Zeroizing::new(master.as_bytes().to_vec())),
+    }
+}
+
+/// Re-encrypts everything in the vault from `old_key` to `new_key`, encrypting any plaintext names on the way.
+/// Every field is written with associated data, so legacy fields aren't allowed anymore afterwards.
+/// Run this inside a transaction.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `old_key` - the key the vault is encrypted under, see `entry_key_root`.
+/// - `new_key` - the key to encrypt it under.
+///
+fn reencrypt_vault(
+    connection: &Connection,
+    old_key: &[u8],
+    new_key: &[u8],
+) -> Result<(), BackendError> {
+    crud_operations::encrypt_legacy_names(connection, old_key)?;
+    crud_operations::reencrypt_password_infos(connection, old_key, new_key)?;
+    crate::organize::reencrypt(connection, old_key, new_key)?;
+    crate::history::reencrypt(connection, old_key, new_key)?;
+    set_metadata(
+        connection,
+        "field_format",
+        &FIELD_FORMAT_VERSION.to_string(),
+    )?;
+    Ok(())
+}
+
+/// Moves a legacy vault, whose entry keys are derived straight from the master password, to a new random vault key
+/// wrapped under the master password. Runs inside a transaction. Returns the new vault key.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `master` - a string slice that holds the master password.
+///
+fn upgrade_legacy_vault(connection: &Connection, master: &str) -> Result<[u8; 32], BackendError> {
+    let transaction = connection.unchecked_transaction()?;
+    let vault_key = Zeroizing::new(generate_vault_key());
+    reencrypt_vault(&transaction, master.as_bytes(), vault_key.as_ref())?;
+    wrap_and_store_vault_key(&transaction, &vault_key, master)?;
+    transaction.commit()?;
+    Ok(*vault_key)
+}
+
+/// Wraps `vault_key` under a key derived from `new_master` with fresh KDF parameters, replacing the stored wrapped key.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `vault_key` - the vault key of the unlocked session.
+/// - `new_master` - a string slice that holds the master password to wrap the vault key under.
+///
+pub fn wrap_and_store_vault_key(
+    connection: &Connection,
+    vault_key: &[u8; 32],
+    new_master: &str,
+) -> Result<(), BackendError> {
+    let params = KdfParams::argon2id();
+    let master_key = Zeroizing::new(derive_master_key(new_master, &params)?);
+    write_kdf_params(connection, &params)?;
+    set_metadata(
+        connection,
+        "vault_key",
+        &hex::encode(wrap_vault_key(&master_key, vault_key)),
+    )?;
+    Ok(())
+}
+
+/// Changes the master password, re-encrypting every password under a brand new vault key.
+/// Everything happens inside one transaction, so if any step fails nothing is changed.
+/// # Arguments
+///
+/// - `connection` - a mutable reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `old` - a string slice that holds the current master password.
+/// - `new` - a string slice that holds the new master password.
+///
+pub fn change_master_password(
+    connection: &mut Connection,
+    old: &str,
+    new: &str,
+) -> Result<(), BackendError> {
+    let entropy = estimate_entropy(new);
+    if entropy < MIN_MASTER_ENTROPY {
+        return Err(BackendError::WeakMasterPassword(entropy));
+    }
+
+    let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    if !authenticate(&transaction, old, PasswordField::Password)? {
+        return Err(BackendError::IncorrectMasterPassword);
+    }
+
+    let old_key = entry_key_root(&transaction, old)?;
+    let new_key = Zeroizing::new(generate_vault_key());
+    reencrypt_vault(&transaction, &old_key, new_key.as_ref())?;
+
+    transaction.execute(
+        "update PasswordInfo set password = ?1 where name = ?2",
+        [hash_master(new)?.as_str(), MASTER_KEYWORD],
+    )?;
+    wrap_and_store_vault_key(&transaction, &new_key, new)?;
+
+    // dropping the transaction without committing rolls it back, so every `?` above undoes everything
+    transaction.commit()?;
+    Ok(())
+}
+
+/// The minimum estimated entropy, in bits, that a new master password must have. See `crypto::estimate_entropy`.
+pub const MIN_MASTER_ENTROPY: f64 = 50.0;
+
+/// Creates the master record and a new random vault key wrapped under the master password (see `wrap_and_store_vault_key`).
+/// The check and the insert run inside one transaction, so two concurrent registrations can't both succeed.
+/// # Arguments
+///
+/// - `connection` - a mutable reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `master` - a string slice that holds the new master password.
+/// - `recovery_note` - a string slice that holds the recovery note.
+///
+pub fn register_master(
+    connection: &mut Connection,
+    master: &str,
+    recovery_note: &str,
+) -> Result<(), BackendError> {
+    let entropy = estimate_entropy(master);
+    if entropy < MIN_MASTER_ENTROPY {
+        return Err(BackendError::WeakMasterPassword(entropy));
+    }
+    if recovery_note.is_empty() {
+        return Err(BackendError::EmptyRecoveryNote);
+    }
+
+    // an IMMEDIATE transaction takes the write lock up front, before we check for an existing record
+    let transaction = connection.transaction_with_behavior(TransactionBehavior::Immediate)?;
+    if check_master_exists(&transaction)? {
+        return Err(BackendError::MasterAlreadyExists);
+    }
+
+    let master_password = hash_master(master)?;
+    let note = hash_master(recovery_note)?;
+
+    transaction.execute(
+        "insert into PasswordInfo (name, password, notes) values (?1, ?2, ?3)",
+        [MASTER_KEYWORD, &master_password, &note],
+    )?;
+    wrap_and_store_vault_key(&transaction, &Zeroizing::new(generate_vault_key()), master)?;
+    // a new vault has no legacy fields to migrate
+    set_metadata(
+        &transaction,
+        "field_format",
+        &FIELD_FORMAT_VERSION.to_string(),
+    )?;
+    transaction.commit()?;
+    Ok(())
+}
+
 #[cfg(test)]
 mod tests {
     use super::MASTER_KEYWORD;
     use crate::{
-        crypto::derive_key,
+        crypto::{derive_key, derive_master_key, KdfAlgorithm, KdfParams},
+        error::BackendError,
+        test_utils,
         {crypto::hash, password::PasswordField},
     };
     use aes_gcm::{
@@ -315,6 +1714,55 @@ mod tests {
         assert!(super::create_table(&Connection::open_in_memory().unwrap()).is_ok());
     }
     #[test]
+    fn open_database() {
+        let dir = std::env::temp_dir().join(format!("passman-db-ops-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        // a database from before migrations existed, so it gets upgraded (and backed up) on open
+        let unversioned = || {
+            let _ = std::fs::remove_file(dir.join("data.db"));
+            Connection::open(dir.join("data.db"))
+                .unwrap()
+                .execute_batch(
+                    "CREATE TABLE PasswordInfo (
+                    id INTEGER NOT NULL PRIMARY KEY,
+                    name TEXT NOT NULL UNIQUE,
+                    username TEXT DEFAULT NULL,
+                    email TEXT DEFAULT NULL,
+                    password TEXT DEFAULT NULL,
+                    notes TEXT DEFAULT NULL
+                  );",
+                )
+                .unwrap();
+        };
+
+        // a backup left behind by an earlier, failed upgrade doesn't stop the next one
+        std::fs::write(dir.join("data.v0.db.bak"), b"earlier backup").unwrap();
+        for expected in ["data.v0.2.db.bak", "data.v0.3.db.bak"] {
+            unversioned();
+            let connection = super::tauri::open_database(&dir).unwrap();
+            assert_eq!(
+                crate::migrations::user_version(&connection).unwrap(),
+                crate::migrations::latest_version()
+            );
+            assert!(dir.join(expected).exists());
+        }
+        assert_eq!(
+            std::fs::read(dir.join("data.v0.db.bak")).unwrap(),
+            b"earlier backup"
+        );
+
+        // after the first unlock they all go, and only they do
+        assert_eq!(super::tauri::remove_backups(&dir).unwrap(), 3);
+        let left: Vec<_> = std::fs::read_dir(&dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name())
+            .collect();
+        assert_eq!(left, vec!["data.db"]);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+    #[test]
     fn test_data() {
         let connection = Connection::open_in_memory().unwrap();
         super::create_table(&connection).unwrap();
@@ -325,13 +1773,10 @@ mod tests {
     }
     #[test]
     fn read_password() {
-        let connection = Connection::open_in_memory().unwrap();
-        super::create_table(&connection).unwrap();
-
-        let master = "mymasterpassword";
+        let (connection, master_key) = test_utils::vault();
         let name = "test_name";
         let password = "coolpassword";
-        let derived = derive_key(master, name);
+        let derived = derive_key(master_key, name);
         let key = Key::<Aes256Gcm>::from_slice(&derived);
         let cipher = Aes256Gcm::new(key);
 
@@ -350,8 +1795,11 @@ mod tests {
             )
             .unwrap();
         assert_eq!(insert, 1);
+        // the legacy name is encrypted when the vault is unlocked
+        assert_eq!(super::migrate_names(&connection, &master_key).unwrap(), 1);
 
-        let res = super::crud_operations::read_password_info(&connection, name, master).unwrap();
+        let res =
+            super::crud_operations::read_password_info(&connection, name, &master_key).unwrap();
 
         assert_eq!(
             res.expect("no password found")
@@ -361,69 +1809,1132 @@ mod tests {
         );
     }
     #[test]
-    fn insert_data() {
-        let connection = Connection::open_in_memory().unwrap();
-        super::create_table(&connection).unwrap();
+    fn email_and_username_columns() {
+        let (connection, key) = test_utils::vault();
+        let insert = |name, field, data| {
+            super::crud_operations::insert_data(&connection, name, &key, field, data).unwrap();
+        };
+        insert("GitHub", PasswordField::Email, "me@example.com");
+        insert("GitHub", PasswordField::Username, "me");
+        insert("bank", PasswordField::Email, "bank@example.com");
 
-        let master = "mymasterpassword";
+        let github = super::crud_operations::read_password_info(&connection, "GitHub", &key)
+            .unwrap()
+            .unwrap();
+        assert_eq!(github.email.as_deref(), Some("me@example.com"));
+        assert_eq!(github.username.as_deref(), Some("me"));
+        let bank = super::crud_operations::read_password_info(&connection, "bank", &key)
+            .unwrap()
+            .unwrap();
+        assert_eq!(bank.email.as_deref(), Some("bank@example.com"));
+        assert_eq!(bank.username, None);
+        // stored in the column of the same name
+        let (email, username): (bool, bool) = connection
+            .query_row(
+                "select email is not null, username is not null from PasswordInfo where id = ?1",
+                [bank.id],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!((email, username), (true, false));
+    }
+    #[test]
+    fn insert_data() {
+        let (connection, key) = test_utils::vault();
         let name = "test_name";
         let password = "coolpassword";
 
         super::crud_operations::insert_data(
             &connection,
             name,
-            master,
+            &key,
             PasswordField::Password,
             password,
         )
         .unwrap();
 
-        let r = super::crud_operations::read_password_info(&connection, name, master)
+        let r = super::crud_operations::read_password_info(&connection, name, &key)
             .unwrap()
             .unwrap();
         assert_eq!(r.password.unwrap(), password);
     }
     #[test]
     fn delete() {
-        let connection = Connection::open_in_memory().unwrap();
-        super::create_table(&connection).unwrap();
-
-        let master = "mymasterpassword";
+        let (connection, key) = test_utils::vault();
         let name = "test_name";
         let password = "coolpassword";
 
         super::crud_operations::insert_data(
             &connection,
             name,
-            master,
+            &key,
             PasswordField::Password,
             password,
         )
         .unwrap();
 
-        super::crud_operations::delete_password_info(&connection, name).unwrap();
-        let result = super::crud_operations::read_password_info(&connection, name, master).unwrap();
+        super::crud_operations::delete_password_info(&connection, name, &key).unwrap();
+        let result = super::crud_operations::read_password_info(&connection, name, &key).unwrap();
         assert!(result.is_none())
     }
     #[test]
+    fn trash() {
+        use crate::{
+            clock,
+            password::{ListOptions, TrashFilter},
+        };
+
+        let (connection, key) = test_utils::vault();
+        let insert = |name, data| {
+            super::crud_operations::insert_data(
+                &connection,
+                name,
+                &key,
+                PasswordField::Password,
+                data,
+            )
+            .unwrap();
+        };
+        let names = |trash| {
+            super::crud_operations::list_password_infos(
+                &connection,
+                &key,
+                &ListOptions {
+                    trash,
+                    ..Default::default()
+                },
+            )
+            .unwrap()
+            .items
+            .into_iter()
+            .map(|summary| summary.name)
+            .collect::<Vec<_>>()
+        };
+
+        clock::set(Some(1000));
+        insert("GitHub", "old");
+        insert("bank", "supersecret");
+        let id = super::crud_operations::read_password_info(&connection, "GitHub", &key)
+            .unwrap()
+            .unwrap()
+            .id;
+        assert_eq!(
+            super::crud_operations::delete_password_info(&connection, "github", &key).unwrap(),
+            1
+        );
+        assert_eq!(
+            super::crud_operations::delete_password_info(&connection, "github", &key).unwrap(),
+            0
+        );
+        // gone from everywhere but the trash
+        assert!(
+            super::crud_operations::read_password_info(&connection, "GitHub", &key)
+                .unwrap()
+                .is_none()
+        );
+        assert_eq!(names(TrashFilter::Exclude), vec!["bank"]);
+        assert_eq!(names(TrashFilter::Only), vec!["GitHub"]);
+        assert_eq!(
+            super::crud_operations::get_password_names(&connection, &key).unwrap(),
+            vec!["bank"]
+        );
+
+        // the name is free again, so restoring it needs another one
+        insert("github", "new");
+        let restored =
+            super::crud_operations::restore_password_info(&connection, id, &key).unwrap();
+        assert_eq!(restored.as_deref(), Some("GitHub (2)"));
+        let password = super::crud_operations::read_password_info(&connection, "GitHub (2)", &key)
+            .unwrap()
+            .unwrap();
+        assert_eq!(password.password.as_deref(), Some("old"));
+        assert_eq!(password.trashed_at, None);
+        // only things in the trash can be restored or deleted for good
+        assert_eq!(
+            super::crud_operations::restore_password_info(&connection, id, &key).unwrap(),
+            None
+        );
+        assert_eq!(
+            super::crud_operations::delete_permanently(&connection, id).unwrap(),
+            0
+        );
+
+        // purged once they've been in the trash long enough
+        super::crud_operations::delete_password_info(&connection, "GitHub (2)", &key).unwrap();
+        clock::set(Some(2000));
+        super::crud_operations::delete_password_info(&connection, "bank", &key).unwrap();
+        super::set_trash_retention(&connection, 1).unwrap();
+        clock::set(Some(1000 + 24 * 60 * 60));
+        assert_eq!(super::purge_trash(&connection).unwrap(), 1);
+        assert_eq!(names(TrashFilter::Include), vec!["bank", "github"]);
+        clock::set(None);
+    }
+    #[test]
     fn check_exists() {
-        let connection = Connection::open_in_memory().unwrap();
-        super::create_table(&connection).unwrap();
-        let master = "masterpassword";
+        let (connection, key) = test_utils::vault();
         let name = "test";
         // first, make sure the function returns false if no data exists
-        assert!(!super::check_password_info_exists(&connection, name).unwrap());
+        assert!(!super::check_password_info_exists(&connection, name, &key).unwrap());
         // now lets insert some data
         super::crud_operations::insert_data(
             &connection,
             name,
-            master,
+            &key,
             PasswordField::Password,
             "supersecret",
         )
         .unwrap();
         // finally, we'll check one more time to make sure it's returning true since we added data
-        assert!(super::check_password_info_exists(&connection, name).unwrap());
+        assert!(super::check_password_info_exists(&connection, name, &key).unwrap());
+    }
+    #[test]
+    fn password_names() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        super::create_table(&connection).unwrap();
+        let master = "mymasterpassword";
+        super::register_master(&mut connection, master, "abcd").unwrap();
+        let key = super::unlock_vault_key(&connection, master).unwrap();
+
+        for name in ["zebra", "apple"] {
+            super::crud_operations::insert_data(
+                &connection,
+                name,
+                &key,
+                PasswordField::Password,
+                "supersecret",
+            )
+            .unwrap();
+        }
+        // sorted, and the master record is left out
+        let names = super::crud_operations::get_password_names(&connection, &key).unwrap();
+        assert_eq!(names, vec!["apple", "zebra"]);
+    }
+    #[test]
+    fn list_password_infos() {
+        use crate::{
+            organize,
+            password::{FolderFilter, ListOptions, NameFilter, SortOrder},
+        };
+
+        let mut connection = Connection::open_in_memory().unwrap();
+        super::create_table(&connection).unwrap();
+        let master = "mymasterpassword";
+        super::register_master(&mut connection, master, "abcd").unwrap();
+        let key = super::unlock_vault_key(&connection, master).unwrap();
+
+        for (name, field, data) in [
+            ("GitHub", PasswordField::Password, "coolpassword"),
+            ("GitHub", PasswordField::Email, "cool_user@usermail.com"),
+            ("gitlab", PasswordField::Password, "supersecret"),
+            ("Bitbucket", PasswordField::Notes, "some notes"),
+            ("zebra", PasswordField::Username, "cool_user1"),
+        ] {
+            super::crud_operations::insert_data(&connection, name, &key, field, data).unwrap();
+        }
+        let list = |options: ListOptions| {
+            super::crud_operations::list_password_infos(&connection, &key, &options).unwrap()
+        };
+        let names = |page: &crate::password::PasswordPage| {
+            page.items
+                .iter()
+                .map(|summary| summary.name.clone())
+                .collect::<Vec<_>>()
+        };
+
+        // everything but the master record, alphabetically regardless of case
+        let all = list(ListOptions::default());
+        assert_eq!(all.total, 4);
+        assert_eq!(names(&all), vec!["Bitbucket", "GitHub", "gitlab", "zebra"]);
+        assert_eq!(
+            all.items[1].fields,
+            vec![PasswordField::Email, PasswordField::Password]
+        );
+        // no secrets in there
+        assert!(!format!("{:?}", all).contains("coolpassword"));
+
+        let prefix = list(ListOptions {
+            filter: Some(NameFilter::Prefix("GIT".to_string())),
+            ..Default::default()
+        });
+        assert_eq!(names(&prefix), vec!["GitHub", "gitlab"]);
+        let contains = list(ListOptions {
+            filter: Some(NameFilter::Contains("B".to_string())),
+            sort: SortOrder::NameDescending,
+            ..Default::default()
+        });
+        assert_eq!(
+            names(&contains),
+            vec!["zebra", "gitlab", "GitHub", "Bitbucket"]
+        );
+
+        let newest = list(ListOptions {
+            sort: SortOrder::Newest,
+            ..Default::default()
+        });
+        assert_eq!(
+            names(&newest),
+            vec!["zebra", "Bitbucket", "gitlab", "GitHub"]
+        );
+
+        // pages, with the total counting every match
+        let page = list(ListOptions {
+            offset: 1,
+            limit: Some(2),
+            ..Default::default()
+        });
+        assert_eq!(page.total, 4);
+        assert_eq!(names(&page), vec!["GitHub", "gitlab"]);
+        let past_the_end = list(ListOptions {
+            offset: 10,
+            ..Default::default()
+        });
+        assert_eq!(past_the_end.total, 4);
+        assert!(past_the_end.items.is_empty());
+
+        // folders and tags
+        let work = organize::create_folder(&connection, &key, "Work", None).unwrap();
+        let code = organize::create_folder(&connection, &key, "Code", Some(work)).unwrap();
+        organize::move_password(&connection, &key, "github", Some(code)).unwrap();
+        organize::move_password(&connection, &key, "bitbucket", Some(work)).unwrap();
+        organize::tag_password(&connection, &key, "gitlab", "dev").unwrap();
+        organize::tag_password(&connection, &key, "GitHub", "dev").unwrap();
+        let folder = |folder| {
+            names(&list(ListOptions {
+                folder: Some(folder),
+                ..Default::default()
+            }))
+        };
+        assert_eq!(folder(FolderFilter::Folder(work)), vec!["Bitbucket"]);
+        assert_eq!(
+            folder(FolderFilter::Subtree(work)),
+            vec!["Bitbucket", "GitHub"]
+        );
+        assert_eq!(folder(FolderFilter::Unfiled), vec!["gitlab", "zebra"]);
+        let dev = organize::list_tags(&connection, &key).unwrap()[0].id;
+        let tagged = list(ListOptions {
+            tag_id: Some(dev),
+            folder: Some(FolderFilter::Unfiled),
+            ..Default::default()
+        });
+        assert_eq!(names(&tagged), vec!["gitlab"]);
+        assert_eq!(tagged.items[0].tag_ids, vec![dev]);
+    }
+    #[test]
+    fn timestamps() {
+        use crate::{
+            clock,
+            password::{CustomField, CustomFieldType, ListOptions, SortOrder},
+        };
+
+        let (connection, key) = test_utils::vault();
+        let insert = |name, field, data| {
+            super::crud_operations::insert_data(&connection, name, &key, field, data).unwrap();
+        };
+
+        clock::set(Some(1000));
+        insert("GitHub", PasswordField::Password, "coolpassword");
+        insert("bank", PasswordField::Password, "supersecret");
+        clock::set(Some(2000));
+        insert("GitHub", PasswordField::Email, "me@example.com");
+        // not a change
+        insert("GitHub", PasswordField::Password, "coolpassword");
+
+        clock::set(Some(3000));
+        let password = super::crud_operations::read_password_info(&connection, "github", &key)
+            .unwrap()
+            .unwrap();
+        assert_eq!(password.created_at, Some(1000));
+        assert_eq!(password.updated_at.password, Some(1000));
+        assert_eq!(password.updated_at.email, Some(2000));
+        assert_eq!(password.updated_at.notes, None);
+        assert_eq!(password.updated_at.latest(), Some(2000));
+        assert_eq!(password.last_accessed_at, Some(3000));
+        // reads the user didn't ask for don't count
+        clock::set(Some(3100));
+        let read = || {
+            super::crud_operations::find_password_info(&connection, "github", &key)
+                .unwrap()
+                .unwrap()
+        };
+        assert_eq!(read().last_accessed_at, Some(3000));
+
+        // custom fields share a timestamp
+        clock::set(Some(3500));
+        let mut field = CustomField {
+            id: 0,
+            label: "PIN".to_string(),
+            field_type: CustomFieldType::Hidden,
+            value: "1234".to_string(),
+        };
+        field.id = super::crud_operations::insert_custom_field(&connection, "GitHub", &key, &field)
+            .unwrap();
+        assert_eq!(read().updated_at.custom_fields, Some(3500));
+        clock::set(Some(3600));
+        field.value = "4321".to_string();
+        super::crud_operations::update_custom_field(&connection, "GitHub", &key, &field).unwrap();
+        assert_eq!(read().updated_at.custom_fields, Some(3600));
+        clock::set(Some(3700));
+        super::crud_operations::delete_custom_field(&connection, "GitHub", &key, field.id).unwrap();
+        assert_eq!(read().updated_at.custom_fields, Some(3700));
+        assert_eq!(read().updated_at.latest(), Some(3700));
+
+        // renaming isn't using it
+        clock::set(Some(4000));
+        super::crud_operations::rename_password_info(&connection, "GitHub", "GitHub work", &key)
+            .unwrap();
+        let list = |options: ListOptions| {
+            super::crud_operations::list_password_infos(&connection, &key, &options)
+                .unwrap()
+                .items
+                .into_iter()
+                .map(|summary| (summary.name, summary.last_accessed_at))
+                .collect::<Vec<_>>()
+        };
+        let used = list(ListOptions {
+            sort: SortOrder::RecentlyUsed,
+            ..Default::default()
+        });
+        assert_eq!(
+            used,
+            vec![
+                ("GitHub work".to_string(), Some(3000)),
+                ("bank".to_string(), None)
+            ]
+        );
+
+        assert_eq!(
+            super::crud_operations::mark_used(&connection, "bank", &key).unwrap(),
+            1
+        );
+        let used = list(ListOptions {
+            sort: SortOrder::RecentlyUsed,
+            ..Default::default()
+        });
+        assert_eq!(used[0], ("bank".to_string(), Some(4000)));
+
+        // bank hasn't changed since 1000
+        let stale = list(ListOptions {
+            updated_before: Some(1500),
+            ..Default::default()
+        });
+        assert_eq!(stale.len(), 1);
+        assert_eq!(stale[0].0, "bank");
+        clock::set(None);
+    }
+    #[test]
+    fn search_password_infos() {
+        use crate::password::{SearchField, SearchOptions};
+
+        let (connection, key) = test_utils::vault();
+        for (name, field, data) in [
+            ("GitHub", PasswordField::Email, "cool_user@usermail.com"),
+            ("GitHub", PasswordField::Password, "hunter2"),
+            ("bank", PasswordField::Username, "user1"),
+            ("bank", PasswordField::Notes, "pin is in the usual place"),
+            ("forum", PasswordField::Email, "other@example.com"),
+        ] {
+            super::crud_operations::insert_data(&connection, name, &key, field, data).unwrap();
+        }
+        let search = |query: &str, options: SearchOptions| {
+            super::crud_operations::search_password_infos(&connection, &key, query, &options)
+                .unwrap()
+        };
+
+        // "which entry uses this email?"
+        let results = search("usermail", SearchOptions::default());
+        assert_eq!(results.len(), 1);
+        assert_eq!(results[0].name, "GitHub");
+        let highlight = &results[0].highlights[0];
+        assert_eq!(highlight.field, SearchField::Field(PasswordField::Email));
+        assert_eq!(highlight.text, "cool_user@usermail.com");
+        assert_eq!(highlight.indices, (10..18).collect::<Vec<_>>());
+
+        // better matches come first
+        let results = search("user", SearchOptions::default());
+        assert_eq!(
+            results.iter().map(|r| r.name.as_str()).collect::<Vec<_>>(),
+            vec!["bank", "GitHub"]
+        );
+        assert_eq!(
+            search(
+                "user",
+                SearchOptions {
+                    limit: Some(1),
+                    ..Default::default()
+                }
+            )
+            .len(),
+            1
+        );
+
+        // passwords are only searched when asked for
+        assert!(search("hunter2", SearchOptions::default()).is_empty());
+        let results = search(
+            "hunter2",
+            SearchOptions {
+                include_password: true,
+                ..Default::default()
+            },
+        );
+        assert_eq!(results[0].name, "GitHub");
+        assert_eq!(
+            results[0].highlights[0].field,
+            SearchField::Field(PasswordField::Password)
+        );
+        // ...and never show up in a highlight otherwise
+        assert!(!format!("{:?}", search("github", SearchOptions::default())).contains("hunter2"));
+
+        // custom fields aren't searched, so they aren't even read
+        let field = crate::password::CustomField {
+            id: 0,
+            label: "recovery codes".to_string(),
+            field_type: crate::password::CustomFieldType::Attachment,
+            value: "dXNlcg==".to_string(),
+        };
+        super::crud_operations::insert_custom_field(&connection, "forum", &key, &field).unwrap();
+        connection
+            .execute("update CustomField set value = X'00'", [])
+            .unwrap();
+        assert_eq!(search("user", SearchOptions::default()).len(), 2);
+    }
+    #[test]
+    fn custom_fields() {
+        use crate::password::{CustomField, CustomFieldType};
+
+        let mut connection = Connection::open_in_memory().unwrap();
+        super::create_table(&connection).unwrap();
+        let master = "mymasterpassword";
+        super::register_master(&mut connection, master, "abcd").unwrap();
+        let key = super::unlock_vault_key(&connection, master).unwrap();
+        let field = |label: &str, field_type, value: &str| CustomField {
+            id: 0,
+            label: label.to_string(),
+            field_type,
+            value: value.to_string(),
+        };
+
+        // adding a field to a password that doesn't exist yet creates it
+        let pin = super::crud_operations::insert_custom_field(
+            &connection,
+            "bank",
+            &key,
+            &field("PIN", CustomFieldType::Hidden, "1234"),
+        )
+        .unwrap();
+        let question = super::crud_operations::insert_custom_field(
+            &connection,
+            "bank",
+            &key,
+            &field("first pet?", CustomFieldType::Text, "rex"),
+        )
+        .unwrap();
+        super::crud_operations::insert_data(
+            &connection,
+            "bank",
+            &key,
+            PasswordField::Password,
+            "coolpassword",
+        )
+        .unwrap();
+        let read = |connection: &Connection, name: &str, key: &[u8]| {
+            super::crud_operations::read_password_info(connection, name, key)
+                .unwrap()
+                .unwrap()
+        };
+        let bank = read(&connection, "bank", &key);
+        assert_eq!(bank.password.unwrap(), "coolpassword");
+        assert_eq!(
+            bank.custom_fields,
+            vec![
+                CustomField {
+                    id: pin,
+                    ..field("PIN", CustomFieldType::Hidden, "1234")
+                },
+                CustomField {
+                    id: question,
+                    ..field("first pet?", CustomFieldType::Text, "rex")
+                },
+            ]
+        );
+
+        // values have to fit their type
+        assert!(matches!(
+            super::crud_operations::insert_custom_field(
+                &connection,
+                "bank",
+                &key,
+                &field("opened", CustomFieldType::Date, "last tuesday"),
+            ),
+            Err(BackendError::InvalidCustomField(_))
+        ));
+
+        let updated = CustomField {
+            id: pin,
+            ..field("PIN", CustomFieldType::Hidden, "4321")
+        };
+        assert_eq!(
+            super::crud_operations::update_custom_field(&connection, "bank", &key, &updated)
+                .unwrap(),
+            1
+        );
+        // a field can only be changed through the password it belongs to
+        super::crud_operations::insert_data(
+            &connection,
+            "other",
+            &key,
+            PasswordField::Password,
+            "supersecret",
+        )
+        .unwrap();
+        assert_eq!(
+            super::crud_operations::update_custom_field(&connection, "other", &key, &updated)
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            super::crud_operations::delete_custom_field(&connection, "other", &key, question)
+                .unwrap(),
+            0
+        );
+        assert_eq!(
+            super::crud_operations::delete_custom_field(&connection, "bank", &key, question)
+                .unwrap(),
+            1
+        );
+        assert_eq!(
+            read(&connection, "bank", &key).custom_fields,
+            vec![updated.clone()]
+        );
+
+        // the value is bound to its type, so it can't be revealed by flipping the type
+        connection
+            .execute(
+                "update CustomField set field_type = 'text' where id = ?",
+                [pin],
+            )
+            .unwrap();
+        assert!(matches!(
+            super::crud_operations::read_password_info(&connection, "bank", &key),
+            Err(BackendError::AesError)
+        ));
+        connection
+            .execute(
+                "update CustomField set field_type = 'hidden' where id = ?",
+                [pin],
+            )
+            .unwrap();
+
+        // custom fields follow the password through a rename and a new master password
+        super::crud_operations::rename_password_info(&connection, "bank", "my bank", &key).unwrap();
+        assert_eq!(
+            read(&connection, "my bank", &key).custom_fields,
+            vec![updated.clone()]
+        );
+        super::change_master_password(&mut connection, master, "mynewmasterpassword").unwrap();
+        let new_key = super::unlock_vault_key(&connection, "mynewmasterpassword").unwrap();
+        assert_eq!(
+            read(&connection, "my bank", &new_key).custom_fields,
+            vec![updated]
+        );
+
+        // and are deleted with it, once it's gone for good
+        let id = read(&connection, "my bank", &new_key).id;
+        super::crud_operations::delete_password_info(&connection, "my bank", &new_key).unwrap();
+        super::crud_operations::delete_permanently(&connection, id).unwrap();
+        let remaining: i32 = connection
+            .query_row("select count(*) from CustomField", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(remaining, 0);
+    }
+    #[test]
+    fn register_master() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        super::create_table(&connection).unwrap();
+        assert!(!super::check_master_exists(&connection).unwrap());
+
+        super::register_master(&mut connection, "mymasterpassword", "abcd").unwrap();
+
+        assert!(super::check_master_exists(&connection).unwrap());
+        assert!(
+            super::authenticate(&connection, "mymasterpassword", PasswordField::Password).unwrap()
+        );
+        assert!(super::authenticate(&connection, "abcd", PasswordField::Notes).unwrap());
+
+        // a second registration must not replace the first
+        assert!(matches!(
+            super::register_master(&mut connection, "anothermasterpassword", "efgh"),
+            Err(BackendError::MasterAlreadyExists)
+        ));
+        assert!(
+            super::authenticate(&connection, "mymasterpassword", PasswordField::Password).unwrap()
+        );
+    }
+    #[test]
+    fn register_weak_master() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        super::create_table(&connection).unwrap();
+
+        assert!(matches!(
+            super::register_master(&mut connection, "hunter2", "abcd"),
+            Err(BackendError::WeakMasterPassword(_))
+        ));
+        assert!(matches!(
+            super::register_master(&mut connection, "mymasterpassword", ""),
+            Err(BackendError::EmptyRecoveryNote)
+        ));
+        // nothing should have been written
+        assert!(!super::check_master_exists(&connection).unwrap());
+    }
+    #[test]
+    fn metadata() {
+        let connection = Connection::open_in_memory().unwrap();
+        super::create_table(&connection).unwrap();
+
+        assert_eq!(super::get_metadata(&connection, "key").unwrap(), None);
+        super::set_metadata(&connection, "key", "value").unwrap();
+        assert_eq!(
+            super::get_metadata(&connection, "key").unwrap().as_deref(),
+            Some("value")
+        );
+        super::set_metadata(&connection, "key", "new value").unwrap();
+        assert_eq!(
+            super::get_metadata(&connection, "key").unwrap().as_deref(),
+            Some("new value")
+        );
+    }
+    #[test]
+    fn kdf_params() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        super::create_table(&connection).unwrap();
+
+        // legacy vaults have no parameters stored
+        assert!(matches!(
+            super::read_kdf_params(&connection),
+            Err(BackendError::InvalidMetadata(_))
+        ));
+
+        // new vaults get argon2id
+        super::register_master(&mut connection, "mymasterpassword", "abcd").unwrap();
+        let params = super::read_kdf_params(&connection).unwrap();
+        assert_eq!(params.algorithm, KdfAlgorithm::Argon2id);
+        assert_eq!(params.salt.len(), 16);
+
+        // and whatever is written is read back
+        let custom = KdfParams {
+            algorithm: KdfAlgorithm::Argon2id,
+            memory_kib: 1024,
+            iterations: 1,
+            parallelism: 1,
+            salt: b"somesalt".to_vec(),
+        };
+        super::write_kdf_params(&connection, &custom).unwrap();
+        assert_eq!(super::read_kdf_params(&connection).unwrap(), custom);
+
+        super::set_metadata(&connection, "kdf.algorithm", "rot13").unwrap();
+        assert!(matches!(
+            super::read_kdf_params(&connection),
+            Err(BackendError::UnsupportedKdf(_))
+        ));
+    }
+    #[test]
+    fn vault_key() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        super::create_table(&connection).unwrap();
+        let master = "mymasterpassword";
+        super::register_master(&mut connection, master, "abcd").unwrap();
+
+        let vault_key = super::unlock_vault_key(&connection, master).unwrap();
+        let params = super::read_kdf_params(&connection).unwrap();
+        // the vault key is random, not derived from the master password
+        assert_ne!(vault_key, derive_master_key(master, &params).unwrap());
+        assert_eq!(
+            super::unlock_vault_key(&connection, master).unwrap(),
+            vault_key
+        );
+
+        super::crud_operations::insert_data(
+            &connection,
+            "test_name",
+            &vault_key,
+            PasswordField::Password,
+            "coolpassword",
+        )
+        .unwrap();
+
+        // re-wrapping under a new master password keeps the same vault key, so entries still decrypt
+        super::wrap_and_store_vault_key(&connection, &vault_key, "mynewmasterpassword").unwrap();
+        assert_ne!(super::read_kdf_params(&connection).unwrap(), params);
+        let rewrapped = super::unlock_vault_key(&connection, "mynewmasterpassword").unwrap();
+        assert_eq!(rewrapped, vault_key);
+        let r = super::crud_operations::read_password_info(&connection, "test_name", &rewrapped)
+            .unwrap()
+            .unwrap();
+        assert_eq!(r.password.unwrap(), "coolpassword");
+
+        // the old master password no longer unwraps it
+        assert!(matches!(
+            super::unlock_vault_key(&connection, master),
+            Err(BackendError::AesError)
+        ));
+    }
+    #[test]
+    fn legacy_vault() {
+        let master = "mymasterpassword";
+        // a vault from before the vault key: entry keys come straight from the master password,
+        // and fields are hex encoded nonce + ciphertext, without associated data
+        let baseline_field = |name: &str, data: &str| {
+            let cipher = crate::crypto::legacy_gen_cipher(master, name);
+            let nonce = Aes256Gcm::generate_nonce(OsRng);
+            let mut field = nonce.to_vec();
+            field.append(&mut cipher.encrypt(&nonce, data.as_bytes()).unwrap());
+            hex::encode(field)
+        };
+        let baseline_vault = || {
+            let connection = Connection::open_in_memory().unwrap();
+            super::create_table(&connection).unwrap();
+            connection
+                .execute(
+                    "insert into PasswordInfo (name, password, notes) values (?1, ?2, ?3)",
+                    [
+                        MASTER_KEYWORD,
+                        &hex::encode(hash(master.as_bytes())),
+                        &hex::encode(hash(b"abcd")),
+                    ],
+                )
+                .unwrap();
+            connection
+                .execute(
+                    "insert into PasswordInfo (name, email, password) values (?1, ?2, ?3)",
+                    [
+                        "GitHub",
+                        &baseline_field("GitHub", "cool_user@usermail.com"),
+                        &baseline_field("GitHub", "coolpassword"),
+                    ],
+                )
+                .unwrap();
+            connection
+        };
+
+        // unlocking moves it to a wrapped vault key, with every field bound to its entry
+        let connection = baseline_vault();
+        assert!(super::authenticate(&connection, master, PasswordField::Password).unwrap());
+        let key = super::unlock_vault_key(&connection, master).unwrap();
+        assert!(super::get_metadata(&connection, "vault_key")
+            .unwrap()
+            .is_some());
+        assert!(!super::legacy_fields_allowed(&connection).unwrap());
+        let r = super::crud_operations::read_password_info(&connection, "GitHub", &key)
+            .unwrap()
+            .unwrap();
+        assert_eq!(r.email.unwrap(), "cool_user@usermail.com");
+        assert_eq!(r.password.unwrap(), "coolpassword");
+        assert_eq!(super::unlock_vault_key(&connection, master).unwrap(), key);
+
+        // and so does changing the master password without unlocking first
+        let mut connection = baseline_vault();
+        super::change_master_password(&mut connection, master, "mynewmasterpassword").unwrap();
+        let key = super::unlock_vault_key(&connection, "mynewmasterpassword").unwrap();
+        let r = super::crud_operations::read_password_info(&connection, "GitHub", &key)
+            .unwrap()
+            .unwrap();
+        assert_eq!(r.password.unwrap(), "coolpassword");
+    }
+    #[test]
+    fn rename() {
+        let (connection, key) = test_utils::vault();
+
+        for (name, field, data) in [
+            ("old_name", PasswordField::Password, "coolpassword"),
+            ("old_name", PasswordField::Email, "cool_user@usermail.com"),
+            ("taken", PasswordField::Password, "supersecret"),
+        ] {
+            super::crud_operations::insert_data(&connection, name, &key, field, data).unwrap();
+        }
+        let original = super::crud_operations::read_password_info(&connection, "old_name", &key)
+            .unwrap()
+            .unwrap();
+
+        let renamed =
+            super::crud_operations::rename_password_info(&connection, "old_name", "new_name", &key)
+                .unwrap();
+        assert_eq!(renamed, 1);
+
+        // the renamed password still decrypts, with the same id and fields
+        let r = super::crud_operations::read_password_info(&connection, "new_name", &key)
+            .unwrap()
+            .unwrap();
+        assert_eq!(r.id, original.id);
+        assert_eq!(r.password, original.password);
+        assert_eq!(r.email, original.email);
+        assert!(!super::check_password_info_exists(&connection, "old_name", &key).unwrap());
+
+        // renaming something that doesn't exist does nothing
+        assert_eq!(
+            super::crud_operations::rename_password_info(&connection, "old_name", "other", &key)
+                .unwrap(),
+            0
+        );
+    }
+    #[test]
+    fn rename_collisions() {
+        let (connection, key) = test_utils::vault();
+        for name in ["first", "second"] {
+            super::crud_operations::insert_data(
+                &connection,
+                name,
+                &key,
+                PasswordField::Password,
+                name,
+            )
+            .unwrap();
+        }
+
+        assert!(matches!(
+            super::crud_operations::rename_password_info(&connection, "first", "second", &key),
+            Err(BackendError::NameAlreadyExists(_))
+        ));
+        assert!(matches!(
+            super::crud_operations::rename_password_info(
+                &connection,
+                "first",
+                MASTER_KEYWORD,
+                &key
+            ),
+            Err(BackendError::ReservedName(_))
+        ));
+        assert!(matches!(
+            super::crud_operations::rename_password_info(
+                &connection,
+                MASTER_KEYWORD,
+                "third",
+                &key
+            ),
+            Err(BackendError::ReservedName(_))
+        ));
+
+        // both passwords are untouched
+        for name in ["first", "second"] {
+            let r = super::crud_operations::read_password_info(&connection, name, &key)
+                .unwrap()
+                .unwrap();
+            assert_eq!(r.password.unwrap(), name);
+        }
+    }
+    #[test]
+    fn change_master_password() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        super::create_table(&connection).unwrap();
+        let old = "mymasterpassword";
+        let new = "mynewmasterpassword";
+        super::register_master(&mut connection, old, "abcd").unwrap();
+        let old_key = super::unlock_vault_key(&connection, old).unwrap();
+
+        for (name, field, data) in [
+            ("first", PasswordField::Password, "coolpassword"),
+            ("first", PasswordField::Email, "cool_user@usermail.com"),
+            ("second", PasswordField::Username, "cool_user1"),
+            ("second", PasswordField::Notes, "some notes"),
+        ] {
+            super::crud_operations::insert_data(&connection, name, &old_key, field, data).unwrap();
+        }
+
+        // the old password has to be right
+        assert!(matches!(
+            super::change_master_password(&mut connection, "random_guess", new),
+            Err(BackendError::IncorrectMasterPassword)
+        ));
+        super::change_master_password(&mut connection, old, new).unwrap();
+
+        assert!(super::authenticate(&connection, new, PasswordField::Password).unwrap());
+        assert!(!super::authenticate(&connection, old, PasswordField::Password).unwrap());
+        // the recovery note is unchanged
+        assert!(super::authenticate(&connection, "abcd", PasswordField::Notes).unwrap());
+
+        // everything was re-encrypted under a new vault key
+        let new_key = super::unlock_vault_key(&connection, new).unwrap();
+        assert_ne!(new_key, old_key);
+        let first = super::crud_operations::read_password_info(&connection, "first", &new_key)
+            .unwrap()
+            .unwrap();
+        assert_eq!(first.password.unwrap(), "coolpassword");
+        assert_eq!(first.email.unwrap(), "cool_user@usermail.com");
+        assert_eq!(first.username, None);
+        let second = super::crud_operations::read_password_info(&connection, "second", &new_key)
+            .unwrap()
+            .unwrap();
+        assert_eq!(second.username.unwrap(), "cool_user1");
+        assert_eq!(second.notes.unwrap(), "some notes");
+        // the old key can't even find it
+        assert!(
+            super::crud_operations::read_password_info(&connection, "first", &old_key)
+                .unwrap()
+                .is_none()
+        );
+    }
+    #[test]
+    fn change_master_password_rollback() {
+        let mut connection = Connection::open_in_memory().unwrap();
+        super::create_table(&connection).unwrap();
+        let old = "mymasterpassword";
+        super::register_master(&mut connection, old, "abcd").unwrap();
+        let old_key = super::unlock_vault_key(&connection, old).unwrap();
+
+        super::crud_operations::insert_data(
+            &connection,
+            "good",
+            &old_key,
+            PasswordField::Password,
+            "coolpassword",
+        )
+        .unwrap();
+        // a row that can't be decrypted makes the re-encryption fail part of the way through
+        connection
+            .execute(
+                "insert into PasswordInfo (name_index, name, password) values (x'00', 'zzz_corrupt', 'not hex')",
+                (),
+            )
+            .unwrap();
+
+        assert!(
+            super::change_master_password(&mut connection, old, "mynewmasterpassword").is_err()
+        );
+
+        // nothing changed
+        assert!(super::authenticate(&connection, old, PasswordField::Password).unwrap());
+        assert_eq!(super::unlock_vault_key(&connection, old).unwrap(), old_key);
+        let good = super::crud_operations::read_password_info(&connection, "good", &old_key)
+            .unwrap()
+            .unwrap();
+        assert_eq!(good.password.unwrap(), "coolpassword");
+    }
+    #[test]
+    fn associated_data() {
+        let (connection, key) = test_utils::vault();
+
+        // a legacy field, encrypted without associated data, the way `insert_data` used to
+        let cipher = crate::crypto::legacy_gen_cipher(key, "test_name");
+        let nonce = Aes256Gcm::generate_nonce(OsRng);
+        let mut legacy = nonce.to_vec();
+        legacy.append(&mut cipher.encrypt(&nonce, b"legacy secret".as_ref()).unwrap());
+        connection
+            .execute(
+                "insert into PasswordInfo (name, password) values ('test_name', ?1)",
+                [hex::encode(&legacy)],
+            )
+            .unwrap();
+        // encrypting the name re-encrypts the fields too, which still accepts the legacy field
+        assert_eq!(super::migrate_names(&connection, &key).unwrap(), 1);
+        assert_eq!(super::migrate_names(&connection, &key).unwrap(), 0);
+        super::crud_operations::insert_data(
+            &connection,
+            "test_name",
+            &key,
+            PasswordField::Notes,
+            "some notes",
+        )
+        .unwrap();
+
+        assert!(super::legacy_fields_allowed(&connection).unwrap());
+        let r = super::crud_operations::read_password_info(&connection, "test_name", &key)
+            .unwrap()
+            .unwrap();
+        assert_eq!(r.password.unwrap(), "legacy secret");
+        assert_eq!(r.notes.unwrap(), "some notes");
+
+        assert_eq!(
+            super::migrate_associated_data(&connection, &key).unwrap(),
+            1
+        );
+        assert!(!super::legacy_fields_allowed(&connection).unwrap());
+        // running it again does nothing
+        assert_eq!(
+            super::migrate_associated_data(&connection, &key).unwrap(),
+            0
+        );
+        let r = super::crud_operations::read_password_info(&connection, "test_name", &key)
+            .unwrap()
+            .unwrap();
+        assert_eq!(r.password.unwrap(), "legacy secret");
+
+        // copying the (now bound) password ciphertext into the notes column no longer decrypts...
+        connection
+            .execute("update PasswordInfo set notes = password", ())
+            .unwrap();
+        assert!(matches!(
+            super::crud_operations::read_password_info(&connection, "test_name", &key),
+            Err(BackendError::AesError)
+        ));
+        // ...and neither does replaying the old legacy ciphertext
+        connection
+            .execute(
+                "update PasswordInfo set notes = null, password = ?1",
+                [hex::encode(&legacy)],
+            )
+            .unwrap();
+        assert!(matches!(
+            super::crud_operations::read_password_info(&connection, "test_name", &key),
+            Err(BackendError::AesError)
+        ));
+    }
+    #[test]
+    fn encrypted_names() {
+        let (connection, key) = test_utils::vault();
+
+        super::crud_operations::insert_data(
+            &connection,
+            "GitHub",
+            &key,
+            PasswordField::Password,
+            "coolpassword",
+        )
+        .unwrap();
+        // the name isn't stored anywhere in plaintext
+        let stored: Vec<u8> = connection
+            .query_row("select name from PasswordInfo", (), |row| row.get(0))
+            .unwrap();
+        assert!(!stored.windows(6).any(|w| w == b"GitHub"));
+
+        // lookups ignore case and surrounding whitespace, and an upsert updates the same entry
+        assert!(super::check_password_info_exists(&connection, " GITHUB ", &key).unwrap());
+        super::crud_operations::insert_data(
+            &connection,
+            "github",
+            &key,
+            PasswordField::Email,
+            "cool_user@usermail.com",
+        )
+        .unwrap();
+        let r = super::crud_operations::read_password_info(&connection, "github", &key)
+            .unwrap()
+            .unwrap();
+        assert_eq!(r.name, "GitHub");
+        assert_eq!(r.password.unwrap(), "coolpassword");
+        assert_eq!(r.email.unwrap(), "cool_user@usermail.com");
+        assert_eq!(
+            super::crud_operations::get_password_names(&connection, &key).unwrap(),
+            vec!["GitHub"]
+        );
+
+        // a different key can't find (or read) anything
+        let other_key = crate::crypto::generate_vault_key();
+        assert!(!super::check_password_info_exists(&connection, "GitHub", &other_key).unwrap());
+        assert!(super::crud_operations::get_password_names(&connection, &other_key).is_err());
+    }
+    #[test]
+    fn legacy_name_clashes() {
+        let (connection, key) = test_utils::vault();
+
+        // two plaintext names that only differ in case used to be two entries
+        for name in ["GitHub", "github"] {
+            connection
+                .execute("insert into PasswordInfo (name) values (?1)", [name])
+                .unwrap();
+        }
+        assert_eq!(super::migrate_names(&connection, &key).unwrap(), 2);
+        assert_eq!(
+            super::crud_operations::get_password_names(&connection, &key).unwrap(),
+            vec!["GitHub", "github (2)"]
+        );
     }
     #[test]
     fn authenticate() {
@@ -449,5 +2960,24 @@ mod tests {
         assert!(
             !super::authenticate(&connection, "random_guess", PasswordField::Password).unwrap()
         );
+
+        // the successful login replaced the legacy hash with a salted verifier...
+        let (password, notes): (String, String) = connection
+            .query_row(
+                "select password, notes from PasswordInfo where name = ?",
+                [MASTER_KEYWORD],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert!(password.starts_with("$argon2id$"));
+        // ...but the recovery note hasn't been used yet, so it's still the old one
+        assert_eq!(notes, note);
+        // and the upgraded verifier still works
+        assert!(
+            super::authenticate(&connection, "mymasterpassword", PasswordField::Password).unwrap()
+        );
+        assert!(
+            !super::authenticate(&connection, "random_guess", PasswordField::Password).unwrap()
+        );
     }
 }
@@ -23,14 +23,142 @@ pub enum BackendError {
 
     #[error("Attempted to authenticate invalid master record field")]
     InvalidMasterRecordField,
+
+    #[error("the vault is locked")]
+    Locked,
+
+    #[error("the name `{0}` is reserved")]
+    ReservedName(String),
+
+    #[error("the master password is incorrect")]
+    IncorrectMasterPassword,
+
+    #[error("a password named `{0}` already exists")]
+    NameAlreadyExists(String),
+
+    #[error("a master password has already been registered")]
+    MasterAlreadyExists,
+
+    #[error("the master password is too weak ({0:.0} bits of estimated entropy)")]
+    WeakMasterPassword(f64),
+
+    #[error("a recovery note is required")]
+    EmptyRecoveryNote,
+
+    #[error("unsupported field format (version {0}, algorithm {1})")]
+    UnsupportedFormat(u8, u8),
+
+    #[error("error deriving key: {0}")]
+    KdfError(#[from] argon2::Error),
+
+    #[error("error hashing or verifying the master password: {0}")]
+    PasswordHashError(#[from] argon2::password_hash::Error),
+
+    #[error("unsupported key derivation function `{0}`")]
+    UnsupportedKdf(String),
+
+    #[error("invalid vault metadata `{0}`")]
+    InvalidMetadata(String),
+
+    #[error("invalid custom field: {0}")]
+    InvalidCustomField(String),
+
+    #[error("unknown field `{0}`")]
+    UnknownField(String),
+
+    #[error("invalid OTP: {0}")]
+    InvalidOtp(String),
+
+    #[error("invalid import: {0}")]
+    InvalidImport(String),
+
+    #[error("the export is encrypted, its password is needed to import it")]
+    ImportPasswordRequired,
+
+    #[error("incorrect password for the export")]
+    IncorrectImportPassword,
+
+    #[error("invalid session timeouts: {0}")]
+    InvalidSessionTimeouts(String),
+
+    #[error("no folder with id {0}")]
+    FolderNotFound(i32),
+
+    #[error("a folder can't be moved into itself or one of its subfolders")]
+    FolderCycle,
+
+    #[error("the database is at schema version {0}, which is newer than this version of the app supports")]
+    UnsupportedSchemaVersion(u32),
+
+    #[error("migrating the database to schema version {version} ({description}) failed: {source}")]
+    MigrationFailed {
+        version: u32,
+        description: &'static str,
+        source: rusqlite::Error,
+    },
+}
+
+impl BackendError {
+    /// A short, stable identifier for the error variant, so the frontend can branch on it
+    /// without parsing the message.
+    pub fn kind(&self) -> &'static str {
+        match self {
+            BackendError::DecodeError(_) => "DecodeError",
+            BackendError::ToStringError(_) => "ToStringError",
+            BackendError::AesError => "AesError",
+            BackendError::SQLiteError(_) => "SQLiteError",
+            BackendError::NoMatchingNonce => "NoMatchingNonce",
+            BackendError::InvalidMasterRecordField => "InvalidMasterRecordField",
+            BackendError::Locked => "Locked",
+            BackendError::ReservedName(_) => "ReservedName",
+            BackendError::IncorrectMasterPassword => "IncorrectMasterPassword",
+            BackendError::NameAlreadyExists(_) => "NameAlreadyExists",
+            BackendError::MasterAlreadyExists => "MasterAlreadyExists",
+            BackendError::WeakMasterPassword(_) => "WeakMasterPassword",
+            BackendError::EmptyRecoveryNote => "EmptyRecoveryNote",
+            BackendError::UnsupportedFormat(..) => "UnsupportedFormat",
+            BackendError::KdfError(_) => "KdfError",
+            BackendError::PasswordHashError(_) => "PasswordHashError",
+            BackendError::UnsupportedKdf(_) => "UnsupportedKdf",
+            BackendError::InvalidMetadata(_) => "InvalidMetadata",
+            BackendError::InvalidCustomField(_) => "InvalidCustomField",
+            BackendError::UnknownField(_) => "UnknownField",
+            BackendError::InvalidOtp(_) => "InvalidOtp",
+            BackendError::InvalidImport(_) => "InvalidImport",
+            BackendError::ImportPasswordRequired => "ImportPasswordRequired",
+            BackendError::IncorrectImportPassword => "IncorrectImportPassword",
+            BackendError::InvalidSessionTimeouts(_) => "InvalidSessionTimeouts",
+            BackendError::FolderNotFound(_) => "FolderNotFound",
+            BackendError::FolderCycle => "FolderCycle",
+            BackendError::UnsupportedSchemaVersion(_) => "UnsupportedSchemaVersion",
+            BackendError::MigrationFailed { .. } => "MigrationFailed",
+        }
+    }
 }
 
 // we must manually implement serde::Serialize
+// errors cross IPC as `{ kind, message }`
 impl serde::Serialize for BackendError {
     fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
     where
         S: serde::ser::Serializer,
     {
-        serializer.serialize_str(self.to_string().as_ref())
+        use serde::ser::SerializeStruct;
+        let mut state = serializer.serialize_struct("BackendError", 2)?;
+        state.serialize_field("kind", self.kind())?;
+        state.serialize_field("message", &self.to_string())?;
+        state.end()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::BackendError;
+
+    #[test]
+    fn serialize() {
+        let json = serde_json::to_value(BackendError::Locked).unwrap();
+        assert_eq!(json["kind"], "Locked");
+        assert_eq!(json["message"], "the vault is locked");
     }
 }
@@ -0,0 +1,603 @@
+// moving out to other apps: the whole vault, minus the trash, written in a format they can import.
+// the reverse of `import`, so an export imports back into passman as it was.
+
+use std::collections::{HashMap, HashSet};
+
+use data_encoding::{BASE32_NOPAD, BASE64};
+use rusqlite::Connection;
+
+use crate::{
+    db_ops::crud_operations,
+    error::BackendError,
+    history,
+    import::{
+        generic_csv::{
+            custom_field_header, escape_cell, folder_separator, CsvColumn, CsvPreset,
+            DEFAULT_COLUMNS,
+        },
+        keepass::string_key,
+    },
+    kdbx::{self, new_uuid, Attachment, Database, Entry, EntryString, Group, KdbxSettings, Times},
+    organize,
+    otp::Otp,
+    password::{CustomField, CustomFieldType, Folder, PasswordField, PasswordInfo},
+};
+
+const FIELDS: [PasswordField; 5] = [
+    PasswordField::Username,
+    PasswordField::Password,
+    PasswordField::Notes,
+    PasswordField::Email,
+    PasswordField::Otp,
+];
+
+fn field_value<'a>(password: &'a PasswordInfo, field: &PasswordField) -> Option<&'a String> {
+    match field {
+        PasswordField::Email => password.email.as_ref(),
+        PasswordField::Username => password.username.as_ref(),
+        PasswordField::Password => password.password.as_ref(),
+        PasswordField::Notes => password.notes.as_ref(),
+        PasswordField::Otp => password.otp.as_ref(),
+    }
+}
+
+// sets a field of an entry, adding it if the entry doesn't have it yet
+fn set_string(entry: &mut Entry, key: &str, value: &str, protected: bool) {
+    match entry.strings.iter_mut().find(|string| string.key == key) {
+        Some(string) => string.value = value.to_string(),
+        None => entry.strings.push(EntryString {
+            key: key.to_string(),
+            value: value.to_string(),
+            protected,
+        }),
+    }
+}
+
+// the previous versions of an entry, oldest first, rebuilt from the history of its fields.
+// values replaced at the same time were replaced by the same edit, so they make one version
+fn history_versions(
+    connection: &Connection,
+    key: &[u8],
+    password: &PasswordInfo,
+    current: &Entry,
+) -> Result<Vec<Entry>, BackendError> {
+    let mut versions: Vec<Entry> = Vec::new();
+    let mut state = current.clone();
+    // newest first
+    let mut changes = history::list_history(connection, key, &password.name, None)?
+        .into_iter()
+        .peekable();
+    while let Some(change) = changes.next() {
+        let replaced_at = change.replaced_at;
+        // the version after this one was saved when this one was replaced
+        if let Some(newer) = versions.last_mut() {
+            newer.times.modified = Some(replaced_at);
+        }
+        let (name, protected) = string_key(&change.field);
+        set_string(&mut state, name, &change.value, protected);
+        while let Some(change) = changes.next_if(|next| next.replaced_at == replaced_at) {
+            let (name, protected) = string_key(&change.field);
+            set_string(&mut state, name, &change.value, protected);
+        }
+        versions.push(Entry {
+            times: Times {
+                created: password.created_at,
+                modified: password.created_at,
+                accessed: None,
+            },
+            ..state.clone()
+        });
+    }
+    versions.reverse();
+    Ok(versions)
+}
+
+fn to_entry(
+    connection: &Connection,
+    key: &[u8],
+    password: &PasswordInfo,
+) -> Result<Entry, BackendError> {
+    let mut entry = Entry {
+        uuid: new_uuid(),
+        times: Times {
+            created: password.created_at,
+            modified: password.updated_at.latest().or(password.created_at),
+            accessed: password.last_accessed_at,
+        },
+        ..Default::default()
+    };
+    set_string(&mut entry, "Title", &password.name, false);
+    for field in &FIELDS {
+        if let Some(value) = field_value(password, field) {
+            let (name, protected) = string_key(field);
+            set_string(&mut entry, name, value, protected);
+        }
+    }
+    for field in &password.custom_fields {
+        if field.field_type == CustomFieldType::Attachment {
+            entry.attachments.push(Attachment {
+                name: field.label.clone(),
+                data: BASE64.decode(field.value.as_bytes()).map_err(|_| {
+                    BackendError::InvalidCustomField(format!(
+                        "the attachment `{}` isn't base64",
+                        field.label
+                    ))
+                })?,
+            });
+            continue;
+        }
+        // every field needs its own key, and a custom field may be called e.g. "Password" too
+        let keys: HashSet<_> = entry.strings.iter().map(|string| &string.key).collect();
+        let mut label = field.label.clone();
+        let mut n = 2;
+        while keys.contains(&label) {
+            label = format!("{} ({})", field.label, n);
+            n += 1;
+        }
+        entry.strings.push(EntryString {
+            key: label,
+            value: field.value.clone(),
+            protected: field.field_type == CustomFieldType::Hidden,
+        });
+    }
+    entry.history = history_versions(connection, key, password, &entry)?;
+    Ok(entry)
+}
+
+fn to_group(
+    name: &str,
+    folder_id: Option<i32>,
+    folders: &[Folder],
+    entries: &mut HashMap<Option<i32>, Vec<Entry>>,
+) -> Group {
+    Group {
+        uuid: new_uuid(),
+        name: name.to_string(),
+        groups: folders
+            .iter()
+            .filter(|folder| folder.parent_id == folder_id)
+            .map(|folder| to_group(&folder.name, Some(folder.id), folders, entries))
+            .collect(),
+        entries: entries.remove(&folder_id).unwrap_or_default(),
+        ..Default::default()
+    }
+}
+
+/// Writes every password outside the trash to a KeePass database (KDBX 4).
+/// Folders become groups, and the history of each password becomes the entry's previous versions.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+/// - `password` - a string slice that holds the password to protect the database with.
+/// - `settings` - how to encrypt the database.
+///
+pub fn to_kdbx(
+    connection: &Connection,
+    key: &[u8],
+    password: &str,
+    settings: &KdbxSettings,
+) -> Result<Vec<u8>, BackendError> {
+    let mut entries: HashMap<Option<i32>, Vec<Entry>> = HashMap::new();
+    let mut passwords = crud_operations::find_all_password_infos(connection, key)?;
+    passwords.sort_by_key(|password| password.name.to_lowercase());
+    for password in &passwords {
+        entries
+            .entry(password.folder_id)
+            .or_default()
+            .push(to_entry(connection, key, password)?);
+    }
+    let folders = organize::list_folders(connection, key)?;
+    let database = Database {
+        name: "passman".to_string(),
+        root: to_group("passman", None, &folders, &mut entries),
+        recycle_bin: None,
+    };
+    kdbx::write(&database, password, settings)
+}
+
+// the names of the folders leading to each folder, by id
+fn folder_paths(folders: &[Folder]) -> HashMap<i32, Vec<String>> {
+    fn path(folders: &[Folder], folder_id: i32) -> Vec<String> {
+        match folders.iter().find(|folder| folder.id == folder_id) {
+            Some(folder) => {
+                let mut path = folder
+                    .parent_id
+                    .map(|parent_id| path(folders, parent_id))
+                    .unwrap_or_default();
+                path.push(folder.name.clone());
+                path
+            }
+            None => Vec::new(),
+        }
+    }
+    folders
+        .iter()
+        .map(|folder| (folder.id, path(folders, folder.id)))
+        .collect()
+}
+
+// the custom field that goes in the url column: the first URL, or in passman's own format the first one
+// the importer labels "URL" again. passman's format has columns for the others
+fn url_field(password: &PasswordInfo, preset: Option<CsvPreset>) -> Option<&CustomField> {
+    password.custom_fields.iter().find(|field| {
+        field.field_type == CustomFieldType::Url && (preset.is_some() || field.label == "URL")
+    })
+}
+
+// the custom fields of passman's own format that aren't in the url column, as their column's header and value
+fn custom_columns(password: &PasswordInfo) -> Vec<(String, String)> {
+    let url = url_field(password, None);
+    password
+        .custom_fields
+        .iter()
+        .filter(|field| !url.is_some_and(|url| std::ptr::eq(url, *field)))
+        .map(|field| {
+            (
+                custom_field_header(&field.label, field.field_type),
+                field.value.clone(),
+            )
+        })
+        .collect()
+}
+
+/// Writes every password outside the trash as CSV, in the format of `preset`'s exports,
+/// or with passman's own columns (see `import::generic_csv::DEFAULT_COLUMNS`) if `None`, escaped for spreadsheets.
+/// Other apps' formats only have the first URL of the custom fields. Passman's has a column for each of the others after
+/// its own, see `import::generic_csv::custom_field_header`, repeated as often as one password has fields with that header.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+/// - `preset` - the app to write the format of.
+///
+pub fn to_csv(
+    connection: &Connection,
+    key: &[u8],
+    preset: Option<CsvPreset>,
+) -> Result<String, BackendError> {
+    let columns = match preset {
+        Some(preset) => preset.columns(),
+        None => &DEFAULT_COLUMNS[..],
+    };
+    // most apps only have a username, which an email address can stand in for
+    let has_email = columns
+        .iter()
+        .any(|(_, column)| *column == CsvColumn::Email);
+    let folders = folder_paths(&organize::list_folders(connection, key)?);
+    let mut passwords = crud_operations::find_all_password_infos(connection, key)?;
+    passwords.sort_by_key(|password| password.name.to_lowercase());
+
+    let mut custom_headers: Vec<String> = Vec::new();
+    if preset.is_none() {
+        for password in &passwords {
+            let fields = custom_columns(password);
+            for (i, (header, _)) in fields.iter().enumerate() {
+                // the password's nth field with this header needs an nth column for it
+                let nth = fields[..i]
+                    .iter()
+                    .filter(|(other, _)| other == header)
+                    .count();
+                if custom_headers
+                    .iter()
+                    .filter(|other| *other == header)
+                    .count()
+                    == nth
+                {
+                    custom_headers.push(header.clone());
+                }
+            }
+        }
+    }
+
+    // writing to memory can't fail, and every record has the same number of fields
+    let mut writer = csv::Writer::from_writer(Vec::new());
+    writer
+        .write_record(
+            columns
+                .iter()
+                .map(|(header, _)| *header)
+                .chain(custom_headers.iter().map(String::as_str)),
+        )
+        .unwrap();
+    for password in &passwords {
+        let mut fields = match preset {
+            Some(_) => Vec::new(),
+            None => custom_columns(password),
+        };
+        // the columns with the same header take the fields with it in order
+        let custom: Vec<_> = custom_headers
+            .iter()
+            .map(|header| {
+                let i = fields.iter().position(|(other, _)| other == header)?;
+                Some(fields.remove(i).1)
+            })
+            .collect();
+        let record = columns.iter().map(|(_, column)| match column {
+            CsvColumn::Name => Some(password.name.clone()),
+            CsvColumn::Url => url_field(password, preset).map(|field| field.value.clone()),
+            CsvColumn::Username if has_email => password.username.clone(),
+            CsvColumn::Username => password.username.clone().or(password.email.clone()),
+            CsvColumn::Email => password.email.clone(),
+            CsvColumn::Password => password.password.clone(),
+            CsvColumn::Notes => password.notes.clone(),
+            // LastPass wants just the secret
+            CsvColumn::Otp if preset == Some(CsvPreset::LastPass) => password
+                .otp
+                .as_deref()
+                .and_then(|uri| Otp::parse_uri(uri).ok())
+                .map(|otp| BASE32_NOPAD.encode(&otp.secret)),
+            CsvColumn::Otp => password.otp.clone(),
+            CsvColumn::Folder => password
+                .folder_id
+                .and_then(|folder_id| folders.get(&folder_id))
+                .map(|path| path.join(&folder_separator(preset).to_string())),
+            CsvColumn::Custom | CsvColumn::Skip => None,
+        });
+        let record = record.chain(custom);
+        // other apps would import the escaped value as it is, so only passman's own format is escaped
+        let record = record
+            .map(Option::unwrap_or_default)
+            .map(|value| match preset {
+                None => escape_cell(&value),
+                Some(_) => value,
+            });
+        writer.write_record(record).unwrap();
+    }
+    Ok(String::from_utf8(writer.into_inner().unwrap())?)
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use crate::{
+        clock,
+        db_ops::{self, crud_operations},
+        history,
+        import::{
+            self,
+            generic_csv::{self, CsvPreset},
+            keepass, NameCollision,
+        },
+        kdbx::{self, Attachment, KdbxSettings, Kdf},
+        organize,
+        password::{CustomField, CustomFieldType, PasswordField},
+        test_utils,
+    };
+
+    #[test]
+    fn kdbx_round_trip() {
+        let (connection, key) = test_utils::vault();
+        let set = |field, value| {
+            crud_operations::insert_data(&connection, "GitHub", &key, field, value).unwrap();
+        };
+        clock::set(Some(100));
+        set(PasswordField::Username, "me");
+        set(PasswordField::Password, "first");
+        clock::set(Some(200));
+        set(PasswordField::Password, "second");
+        clock::set(Some(300));
+        set(PasswordField::Password, "third");
+        set(PasswordField::Email, "me@example.com");
+        set(
+            PasswordField::Otp,
+            "otpauth://totp/GitHub:me?secret=GEZDGNBVGY3TQOJQ&issuer=GitHub&algorithm=SHA1&digits=6&period=30",
+        );
+        for (label, field_type, value) in [
+            ("Password", CustomFieldType::Hidden, "a custom field"),
+            ("URL", CustomFieldType::Url, "https://github.com"),
+            // only attachments become attachments, whatever a field is called
+            (
+                "attachment:notes.txt",
+                CustomFieldType::Hidden,
+                "not base64",
+            ),
+            ("codes.txt", CustomFieldType::Attachment, "MTIzNA=="),
+        ] {
+            let field = CustomField {
+                id: 0,
+                label: label.to_string(),
+                field_type,
+                value: value.to_string(),
+            };
+            crud_operations::insert_custom_field(&connection, "GitHub", &key, &field).unwrap();
+        }
+        clock::set(None);
+        let work = organize::create_folder(&connection, &key, "Work", None).unwrap();
+        let servers = organize::create_folder(&connection, &key, "Servers", Some(work)).unwrap();
+        organize::move_password(&connection, &key, "GitHub", Some(servers)).unwrap();
+        crud_operations::insert_data(&connection, "trashed", &key, PasswordField::Notes, "")
+            .unwrap();
+        crud_operations::delete_password_info(&connection, "trashed", &key).unwrap();
+
+        let settings = KdbxSettings {
+            kdf: Kdf::AesKdf { rounds: 10 },
+            ..Default::default()
+        };
+        let file = super::to_kdbx(&connection, &key, "secret", &settings).unwrap();
+
+        let database = kdbx::read(&file, "secret").unwrap();
+        assert!(database.root.entries.is_empty());
+        let entry = &database.root.groups[0].groups[0].entries[0];
+        assert_eq!(entry.get("Title"), Some("GitHub"));
+        assert_eq!(entry.get("Password"), Some("third"));
+        assert_eq!(entry.get("Password (2)"), Some("a custom field"));
+        assert_eq!(
+            entry.attachments,
+            vec![Attachment {
+                name: "codes.txt".to_string(),
+                data: b"1234".to_vec(),
+            }]
+        );
+        assert_eq!(entry.get("attachment:notes.txt"), Some("not base64"));
+        let passwords: Vec<_> = entry
+            .history
+            .iter()
+            .map(|version| (version.get("Password"), version.times.modified))
+            .collect();
+        assert_eq!(
+            passwords,
+            vec![(Some("first"), Some(100)), (Some("second"), Some(200))]
+        );
+
+        // and it imports back as it was
+        let other = Connection::open_in_memory().unwrap();
+        db_ops::create_table(&other).unwrap();
+        let export = keepass::parse(&file, "secret").unwrap();
+        let report = import::add_entries(&other, &key, export, NameCollision::Suffix).unwrap();
+        assert_eq!(report.imported, vec!["GitHub"]);
+        let original = crud_operations::read_password_info(&connection, "GitHub", &key)
+            .unwrap()
+            .unwrap();
+        let imported = crud_operations::read_password_info(&other, "GitHub", &key)
+            .unwrap()
+            .unwrap();
+        for field in [
+            PasswordField::Email,
+            PasswordField::Username,
+            PasswordField::Password,
+            PasswordField::Otp,
+        ] {
+            assert_eq!(
+                super::field_value(&imported, &field),
+                super::field_value(&original, &field)
+            );
+        }
+        let fields = |password: &crate::password::PasswordInfo| {
+            password
+                .custom_fields
+                .iter()
+                .map(|field| (field.label.clone(), field.value.clone()))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(fields(&imported)[1..], fields(&original)[1..]);
+        let folders = organize::list_folders(&other, &key).unwrap();
+        let folder = folders.iter().find(|folder| folder.name == "Servers");
+        assert_eq!(imported.folder_id, folder.map(|folder| folder.id));
+        let previous: Vec<_> = history::list_history(&other, &key, "GitHub", None)
+            .unwrap()
+            .into_iter()
+            .map(|entry| (entry.value, entry.replaced_at))
+            .collect();
+        assert_eq!(
+            previous,
+            vec![("second".to_string(), 300), ("first".to_string(), 200)]
+        );
+    }
+
+    #[test]
+    fn csv() {
+        let (connection, key) = test_utils::vault();
+        for (field, value) in [
+            (PasswordField::Email, "me@example.com"),
+            (PasswordField::Password, " hunter2, \"quoted\" "),
+            (PasswordField::Notes, "=HYPERLINK(\"https://example.com\")"),
+
+            (
+                PasswordField::Otp,
+                "otpauth://totp/GitHub:me?secret=GEZDGNBVGY3TQOJQ&issuer=GitHub&algorithm=SHA1&digits=6&period=30",
+            ),
+        ] {
+            crud_operations::insert_data(&connection, "GitHub", &key, field, value).unwrap();
+        }
+        let url = CustomField {
+            id: 0,
+            label: "URL".to_string(),
+            field_type: CustomFieldType::Url,
+            value: "https://github.com".to_string(),
+        };
+        crud_operations::insert_custom_field(&connection, "GitHub", &key, &url).unwrap();
+        let work = organize::create_folder(&connection, &key, "Work", None).unwrap();
+        let dev = organize::create_folder(&connection, &key, "Dev", Some(work)).unwrap();
+        organize::move_password(&connection, &key, "GitHub", Some(dev)).unwrap();
+
+        let file = super::to_csv(&connection, &key, None).unwrap();
+        assert_eq!(
+            file.lines().next(),
+            Some("name,url,username,email,password,notes,otp,folder")
+        );
+        // nothing in passman's own format is taken for a formula by a spreadsheet, and it reads back as it was
+        assert!(file.contains(",\"'=HYPERLINK(\"\"https://example.com\"\")\","));
+        let row = &generic_csv::preview(&file, None).unwrap().rows[0];
+        assert_eq!(
+            row.entry.notes.as_deref(),
+            Some("=HYPERLINK(\"https://example.com\")")
+        );
+
+        // passman's own format keeps every custom field, with its type
+        crud_operations::insert_data(&connection, "Vault", &key, PasswordField::Notes, "").unwrap();
+        for (label, field_type, value) in [
+            ("Website", CustomFieldType::Url, "https://example.com"),
+            ("PIN", CustomFieldType::Hidden, "-1234"),
+            ("Security question", CustomFieldType::Text, "Pet?"),
+            ("Security question", CustomFieldType::Text, "City?"),
+            ("codes.txt", CustomFieldType::Attachment, "MTIzNA=="),
+        ] {
+            let field = CustomField {
+                id: 0,
+                label: label.to_string(),
+                field_type,
+                value: value.to_string(),
+            };
+            crud_operations::insert_custom_field(&connection, "Vault", &key, &field).unwrap();
+        }
+        let file = super::to_csv(&connection, &key, None).unwrap();
+        assert_eq!(
+            file.lines().next(),
+            Some("name,url,username,email,password,notes,otp,folder,Website [url],PIN [hidden],Security question [text],Security question [text],codes.txt [attachment]")
+        );
+        let rows = generic_csv::preview(&file, None).unwrap().rows;
+        let fields = |fields: &[CustomField]| {
+            let mut fields: Vec<_> = fields
+                .iter()
+                .map(|field| (field.label.clone(), field.field_type, field.value.clone()))
+                .collect();
+            fields.sort_by(|a, b| (&a.0, &a.2).cmp(&(&b.0, &b.2)));
+            fields
+        };
+        for (row, name) in rows.iter().zip(["GitHub", "Vault"]) {
+            let original = crud_operations::find_password_info(&connection, name, &key)
+                .unwrap()
+                .unwrap();
+            assert_eq!(
+                fields(&row.entry.custom_fields),
+                fields(&original.custom_fields)
+            );
+        }
+
+        // every preset's export is recognized as that app's, and reads back
+        for preset in [None].into_iter().chain(CsvPreset::ALL.map(Some)) {
+            let file = super::to_csv(&connection, &key, preset).unwrap();
+            let preview = generic_csv::preview(&file, None).unwrap();
+            assert_eq!(preview.preset, preset);
+            let row = &preview.rows[0];
+            assert!(row.errors.is_empty(), "{:?}", row.errors);
+            // Firefox's are named after their site
+            let name = match preset {
+                Some(CsvPreset::Firefox) => "github.com",
+                _ => "GitHub",
+            };
+            assert_eq!(row.entry.name, name);
+            assert_eq!(row.entry.password.as_deref(), Some(" hunter2, \"quoted\" "));
+            assert_eq!(row.entry.custom_fields[0].value, "https://github.com");
+            // the email address stands in for the missing username
+            let (username, email) = match preset {
+                None => (None, Some("me@example.com")),
+                Some(_) => (Some("me@example.com"), None),
+            };
+            assert_eq!(row.entry.username.as_deref(), username);
+            assert_eq!(row.entry.email.as_deref(), email);
+            if matches!(preset, None | Some(CsvPreset::LastPass)) {
+                assert_eq!(row.entry.folder, vec!["Work", "Dev"]);
+            }
+            if matches!(
+                preset,
+                None | Some(CsvPreset::LastPass | CsvPreset::Safari | CsvPreset::OnePassword)
+            ) {
+                let otp = row.entry.otp.as_deref().unwrap();
+                assert!(otp.contains("secret=GEZDGNBVGY3TQOJQ"), "{}", otp);
+            }
+        }
+    }
+}
@@ -0,0 +1,379 @@
+// previous values of the fixed fields (email, username, notes and password), so an overwrite can be undone.
+// `crud_operations::insert_data` records the old value every time it replaces one, and only the most recent
+// `retention` values of each field are kept.
+
+use rusqlite::{Connection, OptionalExtension};
+
+use crate::{
+    clock,
+    crypto::*,
+    db_ops::{crud_operations, get_metadata, set_metadata},
+    error::BackendError,
+    password::{HistoryEntry, PasswordField},
+};
+
+/// How many previous values of each field are kept, unless the user sets otherwise.
+pub const DEFAULT_RETENTION: usize = 10;
+
+const RETENTION_KEY: &str = "history.retention";
+
+/// Reads how many previous values of each field are kept.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+///
+pub fn retention(connection: &Connection) -> Result<usize, BackendError> {
+    match get_metadata(connection, RETENTION_KEY)? {
+        Some(value) => value
+            .parse()
+            .map_err(|_| BackendError::InvalidMetadata(RETENTION_KEY.to_string())),
+        None => Ok(DEFAULT_RETENTION),
+    }
+}
+
+/// Sets how many previous values of each field are kept, deleting any that are now too old. 0 turns history off.
+/// Runs inside a transaction. Returns how many values were deleted.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `count` - how many values to keep per field.
+///
+pub fn set_retention(connection: &Connection, count: usize) -> Result<usize, BackendError> {
+    let transaction = connection.unchecked_transaction()?;
+    set_metadata(&transaction, RETENTION_KEY, &count.to_string())?;
+    let mut stmt = transaction.prepare("select distinct password_id, field from FieldHistory")?;
+    let fields = stmt
+        .query_map((), |row| {
+            Ok((row.get::<_, i32>(0)?, row.get::<_, String>(1)?))
+        })?
+        .collect::<Result<Vec<_>, _>>()?;
+    drop(stmt);
+    let mut pruned = 0;
+    for (password_id, field) in fields {
+        pruned += prune(&transaction, password_id, &field, count)?;
+    }
+    transaction.commit()?;
+    Ok(pruned)
+}
+
+// keeps the `count` newest values of one field. ids only ever go up, so they order by age even within the same second
+fn prune(
+    connection: &Connection,
+    password_id: i32,
+    field: &str,
+    count: usize,
+) -> Result<usize, rusqlite::Error> {
+    connection.execute(
+        "delete from FieldHistory where password_id = ?1 and field = ?2 and id not in (
+            select id from FieldHistory where password_id = ?1 and field = ?2 order by id desc limit ?3
+        )",
+        (password_id, field, count as i64),
+    )
+}
+
+/// Records `value` as a previous value of one of a password's fields, then drops the oldest values past the retention count.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+/// - `password_id` - the id of the password the value belonged to.
+/// - `field` - the field the value was in.
+/// - `value` - the value being replaced, decrypted.
+///
+pub fn record(
+    connection: &Connection,
+    key: &[u8],
+    password_id: i32,
+    field: &PasswordField,
+    value: &str,
+) -> Result<(), BackendError> {
+    record_at(connection, key, password_id, field, value, clock::now())
+}
+
+/// `record`, for a value that was replaced at some other time than now, e.g. one from another app's history.
+/// Values are listed in the order they're recorded, so record the oldest first.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+/// - `password_id` - the id of the password the value belonged to.
+/// - `field` - the field the value was in.
+/// - `value` - the value being replaced, decrypted.
+/// - `replaced_at` - when it was replaced, in seconds since the Unix epoch.
+///
+pub fn record_at(
+    connection: &Connection,
+    key: &[u8],
+    password_id: i32,
+    field: &PasswordField,
+    value: &str,
+    replaced_at: i64,
+) -> Result<(), BackendError> {
+    let retention = retention(connection)?;
+    if retention == 0 {
+        return Ok(());
+    }
+    // the value is bound to the row's id, which we only know once the row exists
+    connection.execute(
+        "insert into FieldHistory(password_id, field, value, replaced_at) values (?1, ?2, x'', ?3)",
+        (password_id, field.to_string(), replaced_at),
+    )?;
+    let id = connection.last_insert_rowid() as i32;
+    let encrypted = encrypt_password_field(
+        value,
+        &history_associated_data(password_id, field, id),
+        &history_cipher(key),
+    );
+    connection.execute(
+        "update FieldHistory set value = ?1 where id = ?2",
+        (encrypted, id),
+    )?;
+    prune(connection, password_id, &field.to_string(), retention)?;
+    Ok(())
+}
+
+fn history_entry_from_row(
+    row: &rusqlite::Row,
+) -> Result<(i32, i32, String, EncryptedField, i64), rusqlite::Error> {
+    Ok((
+        row.get(0)?,
+        row.get(1)?,
+        row.get(2)?,
+        row.get(3)?,
+        row.get(4)?,
+    ))
+}
+
+fn decrypt_history_entry(
+    (id, password_id, field, value, replaced_at): (i32, i32, String, EncryptedField, i64),
+    key: &[u8],
+) -> Result<HistoryEntry, BackendError> {
+    let field: PasswordField = field.parse()?;
+    let aad = history_associated_data(password_id, &field, id);
+    Ok(HistoryEntry {
+        id,
+        value: decrypt_password_field(&value, &aad, false, &history_cipher(key))?,
+        field,
+        replaced_at,
+    })
+}
+
+/// Lists the previous values of a password's fields, newest first. Returns an empty list if the password doesn't exist.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+/// - `password_name` - a string slice that holds the name of the password.
+/// - `field` - only list the values of this field, or of every field if `None`.
+///
+pub fn list_history(
+    connection: &Connection,
+    key: &[u8],
+    password_name: &str,
+    field: Option<PasswordField>,
+) -> Result<Vec<HistoryEntry>, BackendError> {
+    let mut stmt = connection.prepare(
+        "select FieldHistory.id, password_id, field, value, replaced_at from FieldHistory
+        join PasswordInfo on PasswordInfo.id = password_id
+        where name_index = ?1 and (?2 is null or field = ?2)
+        order by FieldHistory.id desc",
+    )?;
+    let rows = stmt.query_map(
+        (
+            name_index(key, password_name),
+            field.map(|field| field.to_string()),
+        ),
+        history_entry_from_row,
+    )?;
+    rows.map(|row| decrypt_history_entry(row?, key)).collect()
+}
+
+/// Puts a previous value back into its field, recording the replaced value in the history. Runs inside a transaction.
+/// Returns how many fields were restored: 0 if the password or the history entry doesn't exist, otherwise 1.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+/// - `password_name` - a string slice that holds the name of the password.
+/// - `history_id` - the id of the history entry to restore, see `list_history`.
+///
+pub fn restore_history(
+    connection: &Connection,
+    key: &[u8],
+    password_name: &str,
+    history_id: i32,
+) -> Result<usize, BackendError> {
+    let transaction = connection.unchecked_transaction()?;
+    let row = transaction
+        .query_row(
+            "select FieldHistory.id, password_id, field, value, replaced_at from FieldHistory
+            join PasswordInfo on PasswordInfo.id = password_id
+            where FieldHistory.id = ?1 and name_index = ?2",
+            (history_id, name_index(key, password_name)),
+            history_entry_from_row,
+        )
+        .optional()?;
+    let entry = match row {
+        Some(row) => decrypt_history_entry(row, key)?,
+        None => return Ok(0),
+    };
+    let restored =
+        crud_operations::insert_data(&transaction, password_name, key, entry.field, &entry.value)?;
+    transaction.commit()?;
+    Ok(restored)
+}
+
+/// Encrypts every previous value again under `new_key`, for when the vault key changes.
+/// Stops at the first error; run this inside a transaction so a failure leaves nothing half-done.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `old_key` - the vault key every value is currently encrypted under.
+/// - `new_key` - the vault key to encrypt under instead.
+///
+pub fn reencrypt(
+    connection: &Connection,
+    old_key: &[u8],
+    new_key: &[u8],
+) -> Result<(), BackendError> {
+    let mut stmt = connection
+        .prepare("select id, password_id, field, value, replaced_at from FieldHistory")?;
+    let entries = stmt
+        .query_map((), history_entry_from_row)?
+        .collect::<Result<Vec<_>, _>>()?;
+    let cipher = history_cipher(new_key);
+    for row in entries {
+        let password_id = row.1;
+        let entry = decrypt_history_entry(row, old_key)?;
+        let aad = history_associated_data(password_id, &entry.field, entry.id);
+        connection.execute(
+            "update FieldHistory set value = ?1 where id = ?2",
+            (
+                encrypt_password_field(&entry.value, &aad, &cipher),
+                entry.id,
+            ),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use crate::{db_ops::crud_operations, password::PasswordField, test_utils};
+
+    fn set(connection: &Connection, key: &[u8], field: PasswordField, value: &str) {
+        crud_operations::insert_data(connection, "GitHub", key, field, value).unwrap();
+    }
+
+    #[test]
+    fn history() {
+        let (connection, key) = test_utils::vault();
+        set(&connection, &key, PasswordField::Password, "first");
+        // nothing was replaced yet
+        assert!(super::list_history(&connection, &key, "GitHub", None)
+            .unwrap()
+            .is_empty());
+
+        set(&connection, &key, PasswordField::Password, "second");
+        // the same value again isn't a change
+        set(&connection, &key, PasswordField::Password, "second");
+        set(&connection, &key, PasswordField::Password, "third");
+        set(&connection, &key, PasswordField::Email, "me@example.com");
+        set(&connection, &key, PasswordField::Email, "you@example.com");
+
+        let history = super::list_history(&connection, &key, "github", None).unwrap();
+        let values: Vec<_> = history.iter().map(|e| e.value.as_str()).collect();
+        assert_eq!(values, vec!["me@example.com", "second", "first"]);
+        let passwords =
+            super::list_history(&connection, &key, "GitHub", Some(PasswordField::Password))
+                .unwrap();
+        assert_eq!(passwords.len(), 2);
+        assert!(super::list_history(&connection, &key, "nothing", None)
+            .unwrap()
+            .is_empty());
+
+        // restoring puts the current value into the history
+        let first = passwords[1].id;
+        assert_eq!(
+            super::restore_history(&connection, &key, "GitHub", first).unwrap(),
+            1
+        );
+        let password = crud_operations::read_password_info(&connection, "GitHub", &key)
+            .unwrap()
+            .unwrap();
+        assert_eq!(password.password.unwrap(), "first");
+        let passwords =
+            super::list_history(&connection, &key, "GitHub", Some(PasswordField::Password))
+                .unwrap();
+        assert_eq!(passwords[0].value, "third");
+
+        // a history entry only restores into its own password
+        set(&connection, &key, PasswordField::Password, "x");
+        crud_operations::insert_data(&connection, "bank", &key, PasswordField::Password, "y")
+            .unwrap();
+        assert_eq!(
+            super::restore_history(&connection, &key, "bank", first).unwrap(),
+            0
+        );
+
+        // and it goes when the password does, but not while it's in the trash
+        let id = password.id;
+        crud_operations::delete_password_info(&connection, "GitHub", &key).unwrap();
+        crud_operations::delete_permanently(&connection, id).unwrap();
+        let left: i32 = connection
+            .query_row("select count(*) from FieldHistory", (), |row| row.get(0))
+            .unwrap();
+        assert_eq!(left, 0);
+    }
+
+    #[test]
+    fn retention() {
+        let (connection, key) = test_utils::vault();
+        assert_eq!(
+            super::retention(&connection).unwrap(),
+            super::DEFAULT_RETENTION
+        );
+        for n in 0..15 {
+            set(&connection, &key, PasswordField::Password, &n.to_string());
+        }
+        let history = super::list_history(&connection, &key, "GitHub", None).unwrap();
+        assert_eq!(history.len(), super::DEFAULT_RETENTION);
+        assert_eq!(history[0].value, "13");
+
+        assert_eq!(super::set_retention(&connection, 3).unwrap(), 7);
+        let history = super::list_history(&connection, &key, "GitHub", None).unwrap();
+        let values: Vec<_> = history.iter().map(|e| e.value.as_str()).collect();
+        assert_eq!(values, vec!["13", "12", "11"]);
+
+        super::set_retention(&connection, 0).unwrap();
+        set(&connection, &key, PasswordField::Password, "off");
+        assert!(super::list_history(&connection, &key, "GitHub", None)
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn survives_rename_and_reencrypt() {
+        let (connection, key) = test_utils::vault();
+        set(&connection, &key, PasswordField::Password, "old");
+        set(&connection, &key, PasswordField::Password, "new");
+        crud_operations::rename_password_info(&connection, "GitHub", "GitHub work", &key).unwrap();
+        let history = super::list_history(&connection, &key, "GitHub work", None).unwrap();
+        assert_eq!(history[0].value, "old");
+
+        let new_key = [7u8; 32];
+        super::reencrypt(&connection, &key, &new_key).unwrap();
+        // the blind index is keyed too, so look the row up directly
+        let value: crate::crypto::EncryptedField = connection
+            .query_row("select value from FieldHistory", (), |row| row.get(0))
+            .unwrap();
+        let entry = super::decrypt_history_entry(
+            (history[0].id, 1, "password".to_string(), value, 0),
+            &new_key,
+        )
+        .unwrap();
+        assert_eq!(entry.value, "old");
+    }
+}
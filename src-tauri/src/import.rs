@@ -0,0 +1,325 @@
+// moving in from other apps. each submodule reads one kind of export into `ImportedEntry`s,
+// which `add_entries` then writes to the vault like any other password.
+
+pub mod authenticator;
+pub mod bitwarden;
+pub mod generic_csv;
+pub mod keepass;
+
+use std::collections::HashMap;
+
+use rusqlite::Connection;
+
+use crate::{
+    db_ops::{crud_operations, MASTER_KEYWORD},
+    error::BackendError,
+    history, organize,
+    otp::{decode_secret, Otp, OtpKind},
+    password::{CustomField, PasswordField},
+};
+
+/// The most memory the Argon2 KDF of an encrypted export may ask for, in KiB. The KDF runs before the export's
+/// password can be checked, so this is shared by every importer that reads one (Bitwarden, KeePass).
+pub(crate) const MAX_ARGON2_MEMORY_KIB: u32 = 1024 * 1024;
+
+/// An entry read from another app's export, not yet in the vault.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct ImportedEntry {
+    pub name: String,
+    pub email: Option<String>,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    pub notes: Option<String>,
+    /// An `otpauth://` URI, see `otp`.
+    pub otp: Option<String>,
+    pub custom_fields: Vec<CustomField>,
+    /// The folder the entry goes in, as the names of the folders leading to it, e.g. `["Work", "Servers"]`.
+    /// Empty for no folder. Folders that don't exist yet are created.
+    pub folder: Vec<String>,
+    /// Previous values of the entry's fields, oldest first. They go into the history, see `history`.
+    pub history: Vec<PreviousValue>,
+}
+
+/// A value one of an entry's fields had before, in another app.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct PreviousValue {
+    pub field: PasswordField,
+    pub value: String,
+    /// When it was replaced, in seconds since the Unix epoch.
+    pub replaced_at: i64,
+}
+
+/// What to do with an entry whose name is already taken.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum NameCollision {
+    /// Import it as a new password, with a suffix, see `crud_operations::available_name`.
+    #[default]
+    Suffix,
+    /// Write its fields over the existing password's (the old values go into the history),
+    /// and add any custom fields the existing password doesn't have.
+    Merge,
+}
+
+/// Something in an export that couldn't be imported, and why.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct SkippedItem {
+    pub name: String,
+    pub reason: String,
+}
+
+/// What an importer got out of an export.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct ParsedExport {
+    pub entries: Vec<ImportedEntry>,
+    pub skipped: Vec<SkippedItem>,
+}
+
+impl ParsedExport {
+    pub fn skip(&mut self, name: &str, reason: impl Into<String>) {
+        self.skipped.push(SkippedItem {
+            name: name.to_string(),
+            reason: reason.into(),
+        });
+    }
+}
+
+/// The result of an import.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq, Default)]
+pub struct ImportReport {
+    /// The names the entries were added under. A name that was already taken gets a suffix, see `crud_operations::available_name`.
+    pub imported: Vec<String>,
+    /// The existing passwords entries were merged into, see `NameCollision::Merge`.
+    pub merged: Vec<String>,
+    pub skipped: Vec<SkippedItem>,
+}
+
+/// Turns the TOTP secret of an export into an `otpauth://` URI. Most apps store either a URI or just a base32 secret,
+/// which is taken to be the usual 6 digit, 30 second TOTP.
+/// # Arguments
+///
+/// - `text` - a string slice that holds the URI or the secret.
+/// - `issuer` - who the codes are for, used when there's only a secret. Usually the entry's name.
+/// - `account` - the account the codes are for, used when there's only a secret. Usually the entry's username.
+///
+pub fn otp_uri(
+    text: &str,
+    issuer: Option<&str>,
+    account: Option<&str>,
+) -> Result<String, BackendError> {
+    let text = text.trim();
+    if text.starts_with("otpauth://") {
+        Otp::parse_uri(text)?;
+        return Ok(text.to_string());
+    }
+    let otp = Otp {
+        kind: OtpKind::Totp { period: 30 },
+        secret: decode_secret(text)?,
+        algorithm: Default::default(),
+        digits: 6,
+        issuer: issuer.map(str::to_string),
+        account: account.map(str::to_string),
+    };
+    Ok(otp.to_uri())
+}
+
+// finds the folder at the end of `path`, creating whatever's missing. `folders` caches what's been looked up
+fn find_or_create_folder(
+    connection: &Connection,
+    key: &[u8],
+    path: &[String],
+    folders: &mut HashMap<(Option<i32>, String), i32>,
+) -> Result<Option<i32>, BackendError> {
+    if folders.is_empty() {
+        for folder in organize::list_folders(connection, key)? {
+            folders.insert(
+                (folder.parent_id, folder.name.trim().to_lowercase()),
+                folder.id,
+            );
+        }
+    }
+    let mut parent_id = None;
+    for name in path
+        .iter()
+        .map(|name| name.trim())
+        .filter(|name| !name.is_empty())
+    {
+        let id = match folders.get(&(parent_id, name.to_lowercase())) {
+            Some(id) => *id,
+            None => {
+                let id = organize::insert_folder(connection, key, name, parent_id)?;
+                folders.insert((parent_id, name.to_lowercase()), id);
+                id
+            }
+        };
+        parent_id = Some(id);
+    }
+    Ok(parent_id)
+}
+
+fn record_history(
+    connection: &Connection,
+    key: &[u8],
+    password_id: i32,
+    values: &[PreviousValue],
+) -> Result<(), BackendError> {
+    for previous in values {
+        history::record_at(
+            connection,
+            key,
+            password_id,
+            &previous.field,
+            &previous.value,
+            previous.replaced_at,
+        )?;
+    }
+    Ok(())
+}
+
+/// Adds every entry of an export to the vault.
+/// Runs inside a transaction, so either everything is imported or nothing is.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+/// - `export` - what an importer read.
+/// - `collision` - what to do with entries whose name is already taken.
+///
+pub fn add_entries(
+    connection: &Connection,
+    key: &[u8],
+    export: ParsedExport,
+    collision: NameCollision,
+) -> Result<ImportReport, BackendError> {
+    let transaction = connection.unchecked_transaction()?;
+    let mut imported = Vec::new();
+    let mut merged = Vec::new();
+    let mut folders = HashMap::new();
+    for entry in export.entries {
+        let name = match entry.name.trim() {
+            "" => "Imported",
+            // would look like the master record
+            MASTER_KEYWORD => "master",
+            name => name,
+        };
+        let existing = match collision {
+            NameCollision::Merge => crud_operations::find_password_info(&transaction, name, key)?,
+            NameCollision::Suffix => None,
+        };
+        let name = match &existing {
+            Some(existing) => existing.name.clone(),
+            None => crud_operations::available_name(&transaction, name, key)?,
+        };
+        let fields = [
+            (PasswordField::Email, entry.email),
+            (PasswordField::Username, entry.username),
+            (PasswordField::Password, entry.password),
+            (PasswordField::Notes, entry.notes),
+            (PasswordField::Otp, entry.otp),
+        ];
+        let mut fields: Vec<_> = fields
+            .into_iter()
+            .filter_map(|(field, value)| Some((field, value.filter(|value| !value.is_empty())?)))
+            .collect();
+        // an entry needs at least one field to exist
+        if fields.is_empty() && entry.custom_fields.is_empty() && existing.is_none() {
+            fields.push((PasswordField::Notes, String::new()));
+        }
+        // a merged entry's history goes in before its new values replace the current ones, so it stays in order
+        if let Some(existing) = &existing {
+            record_history(&transaction, key, existing.id, &entry.history)?;
+        }
+        for (field, value) in fields {
+            crud_operations::insert_data(&transaction, &name, key, field, &value)?;
+        }
+        for field in &entry.custom_fields {
+            let duplicate = existing
+                .iter()
+                .flat_map(|existing| &existing.custom_fields)
+                .any(|existing| existing.label == field.label && existing.value == field.value);
+            if !duplicate {
+                crud_operations::add_custom_field(&transaction, &name, key, field)?;
+            }
+        }
+        if existing.is_none() && !entry.history.is_empty() {
+            if let Some((id, _)) = crud_operations::find_entry(&transaction, &name, key)? {
+                record_history(&transaction, key, id, &entry.history)?;
+            }
+        }
+        if !entry.folder.is_empty() {
+            let folder_id = find_or_create_folder(&transaction, key, &entry.folder, &mut folders)?;
+            organize::move_password(&transaction, key, &name, folder_id)?;
+        }
+        match existing {
+            Some(_) => merged.push(name),
+            None => imported.push(name),
+        }
+    }
+    transaction.commit()?;
+    Ok(ImportReport {
+        imported,
+        merged,
+        skipped: export.skipped,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ImportedEntry, NameCollision, ParsedExport};
+    use crate::{db_ops::crud_operations, password::PasswordField, test_utils};
+
+    #[test]
+    fn add_entries() {
+        let (connection, key) = test_utils::vault();
+        crud_operations::insert_data(&connection, "GitHub", &key, PasswordField::Email, "me")
+            .unwrap();
+
+        let mut export = ParsedExport::default();
+        for name in ["github", "", ".master"] {
+            export.entries.push(ImportedEntry {
+                name: name.to_string(),
+                username: Some("someone".to_string()),
+                password: Some(String::new()),
+                ..Default::default()
+            });
+        }
+        export.skip("Steam", "unsupported");
+        let report = super::add_entries(&connection, &key, export, NameCollision::Suffix).unwrap();
+        assert_eq!(report.imported, vec!["github (2)", "Imported", "master"]);
+        assert_eq!(report.skipped[0].name, "Steam");
+
+        let password = crud_operations::read_password_info(&connection, "github (2)", &key)
+            .unwrap()
+            .unwrap();
+        assert_eq!(password.username.as_deref(), Some("someone"));
+        // empty fields aren't set
+        assert_eq!(password.password, None);
+        // the existing one is untouched
+        let password = crud_operations::read_password_info(&connection, "github", &key)
+            .unwrap()
+            .unwrap();
+        assert_eq!(password.username, None);
+
+        // nothing is imported if anything fails
+        let export = ParsedExport {
+            entries: vec![
+                ImportedEntry {
+                    name: "first".to_string(),
+                    notes: Some("hi".to_string()),
+                    ..Default::default()
+                },
+                ImportedEntry {
+                    name: "second".to_string(),
+                    otp: Some("not a uri".to_string()),
+                    ..Default::default()
+                },
+            ],
+            skipped: Vec::new(),
+        };
+        assert!(super::add_entries(&connection, &key, export, NameCollision::Suffix).is_err());
+        assert!(
+            crud_operations::read_password_info(&connection, "first", &key)
+                .unwrap()
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,676 @@
+// exports of authenticator apps, so two-factor codes can follow you off your phone.
+// every account becomes a password with an OTP field (see `otp`), named after its issuer.
+// accounts we can't compute codes for (Steam, mOTP...) are skipped and reported.
+
+use aes_gcm::{aead::Aead, Aes256Gcm, Key, KeyInit, Nonce};
+use data_encoding::BASE64;
+use percent_encoding::percent_decode_str;
+use prost::Message;
+use serde::Deserialize;
+
+use super::{ImportedEntry, ParsedExport};
+use crate::{
+    error::BackendError,
+    otp::{decode_secret, Otp, OtpAlgorithm, OtpKind},
+};
+
+/// The authenticator apps we can import from.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum AuthenticatorFormat {
+    /// `otpauth-migration://` URIs, see `parse_google_migration`.
+    GoogleAuthenticator,
+    Aegis,
+    AndOtp,
+}
+
+/// Reads an export in the given format. `password` is only used by encrypted Aegis vaults.
+pub fn parse(
+    format: AuthenticatorFormat,
+    data: &str,
+    password: Option<&str>,
+) -> Result<ParsedExport, BackendError> {
+    match format {
+        AuthenticatorFormat::GoogleAuthenticator => parse_google_migration(data),
+        AuthenticatorFormat::Aegis => parse_aegis(data, password),
+        AuthenticatorFormat::AndOtp => parse_andotp(data),
+    }
+}
+
+fn invalid(message: impl Into<String>) -> BackendError {
+    BackendError::InvalidImport(message.into())
+}
+
+// scrypt runs before the password can be checked, so a vault can't ask for more than this.
+// Aegis itself uses N = 2^15, r = 8, p = 1
+const MAX_SCRYPT_LOG_N: u32 = 20;
+const MAX_SCRYPT_R: u32 = 32;
+const MAX_SCRYPT_P: u32 = 16;
+
+// what the skipped list calls an account
+fn display_name(issuer: &Option<String>, account: &Option<String>) -> String {
+    match (issuer, account) {
+        (Some(issuer), Some(account)) => format!("{} ({})", issuer, account),
+        (Some(name), None) | (None, Some(name)) => name.clone(),
+        (None, None) => "unnamed account".to_string(),
+    }
+}
+
+fn non_empty(text: &str) -> Option<String> {
+    let text = text.trim();
+    (!text.is_empty()).then(|| text.to_string())
+}
+
+fn algorithm(name: &str) -> Result<OtpAlgorithm, String> {
+    match name.to_ascii_uppercase().as_str() {
+        "SHA1" => Ok(OtpAlgorithm::Sha1),
+        "SHA256" => Ok(OtpAlgorithm::Sha256),
+        "SHA512" => Ok(OtpAlgorithm::Sha512),
+        _ => Err(format!("unsupported algorithm `{}`", name)),
+    }
+}
+
+// adds the account, or skips it if we can't compute its codes
+fn add_account(
+    export: &mut ParsedExport,
+    otp: Result<Otp, String>,
+    notes: Option<String>,
+    name: String,
+) {
+    let otp = match otp.and_then(|otp| otp.validate().map(|_| otp).map_err(|e| e.to_string())) {
+        Ok(otp) => otp,
+        Err(reason) => return export.skip(&name, reason),
+    };
+    export.entries.push(ImportedEntry {
+        name: otp
+            .issuer
+            .clone()
+            .or_else(|| otp.account.clone())
+            .unwrap_or_default(),
+        // without an issuer, the account is already the name
+        username: otp.issuer.as_ref().and(otp.account.clone()),
+        notes,
+        otp: Some(otp.to_uri()),
+        ..Default::default()
+    });
+}
+
+// Google Authenticator's "transfer accounts" QR codes.
+// the format isn't documented, this is what everyone has worked out from the app
+#[derive(Clone, PartialEq, Message)]
+struct MigrationPayload {
+    #[prost(message, repeated, tag = "1")]
+    otp_parameters: Vec<OtpParameters>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+struct OtpParameters {
+    #[prost(bytes = "vec", tag = "1")]
+    secret: Vec<u8>,
+    #[prost(string, tag = "2")]
+    name: String,
+    #[prost(string, tag = "3")]
+    issuer: String,
+    // 0 unspecified, 1 SHA1, 2 SHA256, 3 SHA512, 4 MD5
+    #[prost(int32, tag = "4")]
+    algorithm: i32,
+    // 0 unspecified, 1 six, 2 eight
+    #[prost(int32, tag = "5")]
+    digits: i32,
+    // 0 unspecified, 1 HOTP, 2 TOTP
+    #[prost(int32, tag = "6")]
+    kind: i32,
+    #[prost(int64, tag = "7")]
+    counter: i64,
+}
+
+/// Reads Google Authenticator's `otpauth-migration://offline?data=...` URIs, the contents of its export QR codes.
+/// A big export is split over several QR codes, so this takes any number of URIs, one per line.
+/// # Arguments
+///
+/// - `data` - a string slice that holds the URIs.
+///
+pub fn parse_google_migration(data: &str) -> Result<ParsedExport, BackendError> {
+    if data.trim().is_empty() {
+        return Err(invalid("there's nothing to import"));
+    }
+    let mut export = ParsedExport::default();
+    for uri in data.lines().map(str::trim).filter(|line| !line.is_empty()) {
+        let query = uri
+            .strip_prefix("otpauth-migration://offline?")
+            .ok_or_else(|| invalid("not an otpauth-migration:// URI"))?;
+        let payload = query
+            .split('&')
+            .find_map(|pair| pair.strip_prefix("data="))
+            .ok_or_else(|| invalid("the URI has no data"))?;
+        let payload = percent_decode_str(payload).collect::<Vec<_>>();
+        let payload = BASE64
+            .decode(&payload)
+            .map_err(|_| invalid("the data isn't valid base64"))?;
+        let payload = MigrationPayload::decode(payload.as_slice())
+            .map_err(|e| invalid(format!("the data couldn't be read: {}", e)))?;
+
+        for parameters in payload.otp_parameters {
+            let issuer = non_empty(&parameters.issuer);
+            // the name is sometimes `issuer:account`
+            let account = match parameters.name.split_once(':') {
+                Some((prefix, account)) if Some(prefix.trim()) == issuer.as_deref() => {
+                    non_empty(account)
+                }
+                _ => non_empty(&parameters.name),
+            };
+            let name = display_name(&issuer, &account);
+            let algorithm = match parameters.algorithm {
+                0 | 1 => Ok(OtpAlgorithm::Sha1),
+                2 => Ok(OtpAlgorithm::Sha256),
+                3 => Ok(OtpAlgorithm::Sha512),
+                _ => Err("unsupported algorithm".to_string()),
+            };
+            let digits = match parameters.digits {
+                0 | 1 => Ok(6),
+                2 => Ok(8),
+                _ => Err("unsupported number of digits".to_string()),
+            };
+            let kind = match parameters.kind {
+                1 => Ok(OtpKind::Hotp {
+                    counter: parameters.counter.max(0) as u64,
+                }),
+                // the app only exports 30 second codes
+                0 | 2 => Ok(OtpKind::Totp { period: 30 }),
+                _ => Err("unsupported type".to_string()),
+            };
+            let otp = (|| {
+                Ok(Otp {
+                    kind: kind?,
+                    secret: parameters.secret,
+                    algorithm: algorithm?,
+                    digits: digits?,
+                    issuer,
+                    account,
+                })
+            })();
+            add_account(&mut export, otp, None, name);
+        }
+    }
+    Ok(export)
+}
+
+#[derive(Deserialize)]
+struct AegisFile {
+    header: AegisHeader,
+    db: serde_json::Value,
+}
+
+#[derive(Deserialize)]
+struct AegisHeader {
+    slots: Option<Vec<AegisSlot>>,
+    params: Option<AegisParams>,
+}
+
+#[derive(Deserialize)]
+struct AegisSlot {
+    // 1 is a password, the others (a raw key, biometrics) can't be used off the phone
+    #[serde(rename = "type")]
+    kind: u8,
+    key: String,
+    key_params: AegisParams,
+    n: Option<u64>,
+    r: Option<u32>,
+    p: Option<u32>,
+    salt: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct AegisParams {
+    nonce: String,
+    tag: String,
+}
+
+#[derive(Deserialize)]
+struct AegisDb {
+    entries: Vec<AegisEntry>,
+}
+
+#[derive(Deserialize)]
+struct AegisEntry {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    issuer: String,
+    #[serde(default)]
+    note: String,
+    info: AegisInfo,
+}
+
+#[derive(Deserialize)]
+struct AegisInfo {
+    secret: String,
+    #[serde(default = "default_algorithm")]
+    algo: String,
+    #[serde(default = "default_digits")]
+    digits: u32,
+    period: Option<u64>,
+    counter: Option<u64>,
+}
+
+fn default_algorithm() -> String {
+    "SHA1".to_string()
+}
+
+fn default_digits() -> u32 {
+    6
+}
+
+fn hex(text: &str) -> Result<Vec<u8>, BackendError> {
+    hex::decode(text).map_err(|_| invalid("the vault isn't valid hex"))
+}
+
+// AES-256-GCM, with the tag stored apart from the ciphertext
+fn decrypt(key: &[u8], params: &AegisParams, ciphertext: &[u8]) -> Option<Vec<u8>> {
+    let nonce = hex(&params.nonce).ok()?;
+    if key.len() != 32 || nonce.len() != 12 {
+        return None;
+    }
+    let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+    let message = [ciphertext, &hex(&params.tag).ok()?].concat();
+    cipher
+        .decrypt(Nonce::from_slice(&nonce), message.as_slice())
+        .ok()
+}
+
+// the vault's key is encrypted once per slot. each password slot has its own scrypt parameters
+fn decrypt_aegis_db(
+    header: &AegisHeader,
+    db: &str,
+    password: &str,
+) -> Result<Vec<u8>, BackendError> {
+    let db_params = header
+        .params
+        .as_ref()
+        .ok_or_else(|| invalid("the vault has no encryption parameters"))?;
+    let db = BASE64
+        .decode(db.as_bytes())
+        .map_err(|_| invalid("the vault isn't valid base64"))?;
+    for slot in header.slots.iter().flatten().filter(|slot| slot.kind == 1) {
+        let (Some(n), Some(r), Some(p), Some(salt)) = (slot.n, slot.r, slot.p, &slot.salt) else {
+            continue;
+        };
+        if !n.is_power_of_two() {
+            return Err(invalid("the vault's scrypt N isn't a power of two"));
+        }
+        let log_n = n.trailing_zeros();
+        if log_n > MAX_SCRYPT_LOG_N || r > MAX_SCRYPT_R || p > MAX_SCRYPT_P {
+            return Err(invalid("the vault asks for too much scrypt work"));
+        }
+        let params = scrypt::Params::new(log_n as u8, r, p, 32)
+            .map_err(|_| invalid("the vault has invalid scrypt parameters"))?;
+        let mut derived = [0u8; 32];
+        scrypt::scrypt(password.as_bytes(), &hex(salt)?, &params, &mut derived)
+            .map_err(|_| invalid("the vault has invalid scrypt parameters"))?;
+        if let Some(master_key) = decrypt(&derived, &slot.key_params, &hex(&slot.key)?) {
+            return decrypt(&master_key, db_params, &db)
+                .ok_or_else(|| invalid("the vault couldn't be decrypted"));
+        }
+    }
+    Err(BackendError::IncorrectImportPassword)
+}
+
+/// Reads an Aegis vault export, plain or encrypted.
+/// Fails with `BackendError::ImportPasswordRequired` if it's encrypted and there's no password,
+/// or with `BackendError::IncorrectImportPassword` if the password is wrong.
+/// # Arguments
+///
+/// - `data` - a string slice that holds the exported JSON.
+/// - `password` - the password the export is encrypted with, if it is.
+///
+pub fn parse_aegis(data: &str, password: Option<&str>) -> Result<ParsedExport, BackendError> {
+    let file: AegisFile =
+        serde_json::from_str(data).map_err(|e| invalid(format!("not an Aegis vault: {}", e)))?;
+    let db: AegisDb = match &file.db {
+        serde_json::Value::String(encrypted) => {
+            let password = password.ok_or(BackendError::ImportPasswordRequired)?;
+            let db = decrypt_aegis_db(&file.header, encrypted, password)?;
+            serde_json::from_slice(&db)
+        }
+        db => AegisDb::deserialize(db),
+    }
+    .map_err(|e| invalid(format!("not an Aegis vault: {}", e)))?;
+
+    let mut export = ParsedExport::default();
+    for entry in db.entries {
+        let issuer = non_empty(&entry.issuer);
+        let account = non_empty(&entry.name);
+        let name = display_name(&issuer, &account);
+        let info = entry.info;
+        let kind = match entry.kind.as_str() {
+            "totp" => Ok(OtpKind::Totp {
+                period: info.period.unwrap_or(30),
+            }),
+            "hotp" => Ok(OtpKind::Hotp {
+                counter: info.counter.unwrap_or(0),
+            }),
+            kind => Err(format!("unsupported type `{}`", kind)),
+        };
+        let otp = kind.and_then(|kind| {
+            Ok(Otp {
+                kind,
+                secret: decode_secret(&info.secret).map_err(|e| e.to_string())?,
+                algorithm: algorithm(&info.algo)?,
+                digits: info.digits,
+                issuer,
+                account,
+            })
+        });
+        add_account(&mut export, otp, non_empty(&entry.note), name);
+    }
+    Ok(export)
+}
+
+#[derive(Deserialize)]
+struct AndOtpEntry {
+    secret: String,
+    #[serde(default)]
+    issuer: String,
+    #[serde(default)]
+    label: String,
+    #[serde(default = "default_digits")]
+    digits: u32,
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default = "default_algorithm")]
+    algorithm: String,
+    period: Option<u64>,
+    counter: Option<u64>,
+}
+
+/// Reads an andOTP backup. Only plain JSON backups are supported, not encrypted ones.
+/// # Arguments
+///
+/// - `data` - a string slice that holds the backup's JSON.
+///
+pub fn parse_andotp(data: &str) -> Result<ParsedExport, BackendError> {
+    let entries: Vec<AndOtpEntry> =
+        serde_json::from_str(data).map_err(|e| invalid(format!("not an andOTP backup: {}", e)))?;
+    let mut export = ParsedExport::default();
+    for entry in entries {
+        let issuer = non_empty(&entry.issuer);
+        let account = non_empty(&entry.label);
+        let name = display_name(&issuer, &account);
+        let kind = match entry.kind.to_ascii_uppercase().as_str() {
+            "TOTP" => Ok(OtpKind::Totp {
+                period: entry.period.unwrap_or(30),
+            }),
+            "HOTP" => Ok(OtpKind::Hotp {
+                counter: entry.counter.unwrap_or(0),
+            }),
+            kind => Err(format!("unsupported type `{}`", kind)),
+        };
+        let otp = kind.and_then(|kind| {
+            Ok(Otp {
+                kind,
+                secret: decode_secret(&entry.secret).map_err(|e| e.to_string())?,
+                algorithm: algorithm(&entry.algorithm)?,
+                digits: entry.digits,
+                issuer,
+                account,
+            })
+        });
+        add_account(&mut export, otp, None, name);
+    }
+    Ok(export)
+}
+
+#[cfg(test)]
+mod tests {
+    use aes_gcm::{aead::Aead, Aes256Gcm, Key, KeyInit, Nonce};
+    use data_encoding::BASE64;
+    use prost::Message;
+    use serde_json::json;
+
+    use super::{
+        parse_aegis, parse_andotp, parse_google_migration, MigrationPayload, OtpParameters,
+    };
+    use crate::{
+        error::BackendError,
+        otp::{Otp, OtpAlgorithm, OtpKind},
+    };
+
+    // "12345678901234567890" in base32
+    const SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+    fn otp(uri: &Option<String>) -> Otp {
+        Otp::parse_uri(uri.as_deref().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn google_migration() {
+        let payload = MigrationPayload {
+            otp_parameters: vec![
+                OtpParameters {
+                    secret: b"12345678901234567890".to_vec(),
+                    name: "GitHub:me@example.com".to_string(),
+                    issuer: "GitHub".to_string(),
+                    algorithm: 1,
+                    digits: 1,
+                    kind: 2,
+                    counter: 0,
+                },
+                OtpParameters {
+                    secret: b"12345678901234567890".to_vec(),
+                    name: "bank".to_string(),
+                    issuer: String::new(),
+                    algorithm: 2,
+                    digits: 2,
+                    kind: 1,
+                    counter: 7,
+                },
+                OtpParameters {
+                    secret: b"12345678901234567890".to_vec(),
+                    name: "old".to_string(),
+                    issuer: "Legacy".to_string(),
+                    algorithm: 4,
+                    digits: 1,
+                    kind: 2,
+                    counter: 0,
+                },
+            ],
+        };
+        // base64 has characters that are escaped in the URI
+        let data = BASE64
+            .encode(&payload.encode_to_vec())
+            .replace('+', "%2B")
+            .replace('/', "%2F")
+            .replace('=', "%3D");
+        let export =
+            parse_google_migration(&format!("otpauth-migration://offline?data={}", data)).unwrap();
+
+        assert_eq!(export.entries.len(), 2);
+        let github = &export.entries[0];
+        assert_eq!(github.name, "GitHub");
+        assert_eq!(github.username.as_deref(), Some("me@example.com"));
+        let github = otp(&github.otp);
+        assert_eq!(github.kind, OtpKind::Totp { period: 30 });
+        assert_eq!(github.code_at(59).code, "287082");
+
+        let bank = &export.entries[1];
+        assert_eq!(bank.name, "bank");
+        assert_eq!(bank.username, None);
+        let bank = otp(&bank.otp);
+        assert_eq!(bank.kind, OtpKind::Hotp { counter: 7 });
+        assert_eq!(bank.algorithm, OtpAlgorithm::Sha256);
+        assert_eq!(bank.digits, 8);
+
+        assert_eq!(export.skipped.len(), 1);
+        assert_eq!(export.skipped[0].name, "Legacy (old)");
+
+        assert!(matches!(
+            parse_google_migration("otpauth://totp/me?secret=GEZDGNBV"),
+            Err(BackendError::InvalidImport(_))
+        ));
+        assert!(matches!(
+            parse_google_migration("otpauth-migration://offline?data=AAAA%21"),
+            Err(BackendError::InvalidImport(_))
+        ));
+    }
+
+    fn aegis_db() -> serde_json::Value {
+        json!({
+            "version": 2,
+            "entries": [
+                {
+                    "type": "totp",
+                    "uuid": "01234567-89ab-cdef-0123-456789abcdef",
+                    "name": "me@example.com",
+                    "issuer": "GitHub",
+                    "note": "recovery codes are in the safe",
+                    "info": { "secret": SECRET, "algo": "SHA1", "digits": 8, "period": 30 }
+                },
+                {
+                    "type": "hotp",
+                    "name": "bank",
+                    "issuer": "",
+                    "info": { "secret": SECRET, "algo": "SHA512", "digits": 6, "counter": 3 }
+                },
+                {
+                    "type": "steam",
+                    "name": "me",
+                    "issuer": "Steam",
+                    "info": { "secret": SECRET, "algo": "SHA1", "digits": 5, "period": 30 }
+                }
+            ]
+        })
+    }
+
+    fn check_aegis(export: &super::ParsedExport) {
+        assert_eq!(export.entries.len(), 2);
+        let github = &export.entries[0];
+        assert_eq!(github.name, "GitHub");
+        assert_eq!(
+            github.notes.as_deref(),
+            Some("recovery codes are in the safe")
+        );
+        assert_eq!(otp(&github.otp).code_at(59).code, "94287082");
+        let bank = otp(&export.entries[1].otp);
+        assert_eq!(bank.kind, OtpKind::Hotp { counter: 3 });
+        assert_eq!(bank.algorithm, OtpAlgorithm::Sha512);
+        assert_eq!(export.skipped.len(), 1);
+        assert_eq!(export.skipped[0].name, "Steam (me)");
+        assert!(export.skipped[0].reason.contains("steam"));
+    }
+
+    #[test]
+    fn aegis() {
+        let plain = json!({
+            "version": 1,
+            "header": { "slots": null, "params": null },
+            "db": aegis_db(),
+        });
+        check_aegis(&parse_aegis(&plain.to_string(), None).unwrap());
+
+        // encrypted the way the app does it, with a small scrypt cost so the test is quick
+        let encrypt = |key: &[u8], nonce: &[u8], data: &[u8]| {
+            let cipher = Aes256Gcm::new(Key::<Aes256Gcm>::from_slice(key));
+            let mut sealed = cipher.encrypt(Nonce::from_slice(nonce), data).unwrap();
+            let tag = sealed.split_off(sealed.len() - 16);
+            (sealed, tag)
+        };
+        let master_key = [7u8; 32];
+        let salt = [1u8; 32];
+        let mut derived = [0u8; 32];
+        let params = scrypt::Params::new(10, 8, 1, 32).unwrap();
+        scrypt::scrypt(b"hunter2", &salt, &params, &mut derived).unwrap();
+        let (key, key_tag) = encrypt(&derived, &[2; 12], &master_key);
+        let (db, db_tag) = encrypt(&master_key, &[3; 12], aegis_db().to_string().as_bytes());
+        let encrypted = json!({
+            "version": 1,
+            "header": {
+                "slots": [
+                    // a biometric slot, which is skipped
+                    {
+                        "type": 2, "uuid": "", "key": "00",
+                        "key_params": { "nonce": "00", "tag": "00" }
+                    },
+                    {
+                        "type": 1, "uuid": "", "key": hex::encode(key),
+                        "key_params": { "nonce": hex::encode([2; 12]), "tag": hex::encode(key_tag) },
+                        "n": 1024, "r": 8, "p": 1, "salt": hex::encode(salt)
+                    }
+                ],
+                "params": { "nonce": hex::encode([3; 12]), "tag": hex::encode(db_tag) }
+            },
+            "db": BASE64.encode(&db),
+        })
+        .to_string();
+
+        check_aegis(&parse_aegis(&encrypted, Some("hunter2")).unwrap());
+        assert!(matches!(
+            parse_aegis(&encrypted, None),
+            Err(BackendError::ImportPasswordRequired)
+        ));
+        assert!(matches!(
+            parse_aegis(&encrypted, Some("hunter3")),
+            Err(BackendError::IncorrectImportPassword)
+        ));
+        assert!(matches!(
+            parse_aegis("[]", None),
+            Err(BackendError::InvalidImport(_))
+        ));
+
+        // parameters that aren't scrypt's, or would take forever, are refused before any work is done
+        for (name, value) in [
+            ("n", json!(1000)),
+            ("n", json!(1u64 << 40)),
+            ("r", json!(u32::MAX)),
+            ("p", json!(u32::MAX)),
+        ] {
+            let mut crafted: serde_json::Value = serde_json::from_str(&encrypted).unwrap();
+            crafted["header"]["slots"][1][name] = value;
+            assert!(matches!(
+                parse_aegis(&crafted.to_string(), Some("hunter2")),
+                Err(BackendError::InvalidImport(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn andotp() {
+        let backup = json!([
+            {
+                "secret": SECRET, "issuer": "GitHub", "label": "me@example.com", "digits": 6,
+                "type": "TOTP", "algorithm": "SHA1", "thumbnail": "Default", "last_used": 0,
+                "used_frequency": 0, "period": 60, "tags": []
+            },
+            {
+                "secret": SECRET, "issuer": "", "label": "bank", "digits": 6,
+                "type": "HOTP", "algorithm": "SHA1", "counter": 1, "tags": []
+            },
+            {
+                "secret": SECRET, "issuer": "Steam", "label": "me", "digits": 5,
+                "type": "STEAM", "algorithm": "SHA1", "period": 30, "tags": []
+            },
+            {
+                "secret": "not base32!", "issuer": "Broken", "label": "", "digits": 6,
+                "type": "TOTP", "algorithm": "SHA1", "period": 30, "tags": []
+            }
+        ]);
+        let export = parse_andotp(&backup.to_string()).unwrap();
+        assert_eq!(export.entries.len(), 2);
+        let github = otp(&export.entries[0].otp);
+        assert_eq!(github.kind, OtpKind::Totp { period: 60 });
+        assert_eq!(github.account.as_deref(), Some("me@example.com"));
+        let bank = otp(&export.entries[1].otp);
+        assert_eq!(bank.code_at(0).code, "287082");
+        let skipped: Vec<_> = export
+            .skipped
+            .iter()
+            .map(|item| item.name.as_str())
+            .collect();
+        assert_eq!(skipped, vec!["Steam (me)", "Broken"]);
+
+        assert!(matches!(
+            parse_andotp("{}"),
+            Err(BackendError::InvalidImport(_))
+        ));
+    }
+}
@@ -0,0 +1,740 @@
+// Bitwarden's exports: JSON (plain or password protected) and CSV.
+// logins and secure notes are imported, with their custom fields, URIs, folder and TOTP secret.
+// cards and identities don't fit a password, so they're skipped.
+
+use aes_gcm::aes::Aes256;
+use cbc::cipher::{block_padding::Pkcs7, BlockDecryptMut, KeyIvInit};
+use data_encoding::BASE64;
+use hmac::{Hmac, Mac};
+use pbkdf2::pbkdf2_hmac;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+
+use super::{otp_uri, ImportedEntry, ParsedExport, MAX_ARGON2_MEMORY_KIB};
+use crate::{
+    error::BackendError,
+    password::{CustomField, CustomFieldType},
+};
+
+fn invalid(message: impl Into<String>) -> BackendError {
+    BackendError::InvalidImport(message.into())
+}
+
+// the KDF runs before the password can be checked, so nothing above what Bitwarden itself allows is accepted
+const MAX_PBKDF2_ITERATIONS: u32 = 2_000_000;
+const MAX_ARGON2_ITERATIONS: u32 = 10;
+const MAX_ARGON2_PARALLELISM: u32 = 16;
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BitwardenExport {
+    #[serde(default)]
+    encrypted: bool,
+    #[serde(default)]
+    password_protected: bool,
+    salt: Option<String>,
+    kdf_type: Option<u8>,
+    kdf_iterations: Option<u32>,
+    kdf_memory: Option<u32>,
+    kdf_parallelism: Option<u32>,
+    #[serde(rename = "encKeyValidation_DO_NOT_EDIT")]
+    key_validation: Option<String>,
+    data: Option<String>,
+    #[serde(default)]
+    folders: Vec<BitwardenFolder>,
+    #[serde(default)]
+    items: Vec<BitwardenItem>,
+}
+
+#[derive(Deserialize)]
+struct BitwardenFolder {
+    id: String,
+    name: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct BitwardenItem {
+    folder_id: Option<String>,
+    #[serde(rename = "type")]
+    kind: u8,
+    #[serde(default)]
+    name: String,
+    notes: Option<String>,
+    fields: Option<Vec<BitwardenField>>,
+    login: Option<BitwardenLogin>,
+}
+
+#[derive(Deserialize)]
+struct BitwardenField {
+    name: Option<String>,
+    value: Option<String>,
+    // 0 text, 1 hidden, 2 boolean, 3 linked to another field
+    #[serde(rename = "type")]
+    kind: u8,
+}
+
+#[derive(Deserialize)]
+struct BitwardenLogin {
+    uris: Option<Vec<BitwardenUri>>,
+    username: Option<String>,
+    password: Option<String>,
+    totp: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct BitwardenUri {
+    uri: Option<String>,
+}
+
+// what's common to both formats
+struct Login {
+    name: String,
+    notes: Option<String>,
+    folder: Option<String>,
+    username: Option<String>,
+    password: Option<String>,
+    totp: Option<String>,
+    uris: Vec<String>,
+    fields: Vec<CustomField>,
+}
+
+fn non_empty(text: Option<String>) -> Option<String> {
+    text.filter(|text| !text.trim().is_empty())
+}
+
+fn add_login(export: &mut ParsedExport, login: Login) {
+    let username = non_empty(login.username);
+    let otp = match non_empty(login.totp) {
+        Some(totp) => match otp_uri(&totp, Some(&login.name), username.as_deref()) {
+            Ok(uri) => Some(uri),
+            // the rest of the login is still worth having
+            Err(e) => {
+                export.skip(
+                    &format!("{} (TOTP)", login.name),
+                    format!("the TOTP secret couldn't be imported: {}", e),
+                );
+                None
+            }
+        },
+        None => None,
+    };
+    // there's no URL field, so URIs become custom fields
+    let uris = login.uris.into_iter().filter(|uri| !uri.trim().is_empty());
+    let uris = uris.enumerate().map(|(i, uri)| CustomField {
+        id: 0,
+        label: match i {
+            0 => "URL".to_string(),
+            i => format!("URL {}", i + 1),
+        },
+        field_type: CustomFieldType::Url,
+        value: uri,
+    });
+    export.entries.push(ImportedEntry {
+        name: login.name,
+        username,
+        password: non_empty(login.password),
+        notes: non_empty(login.notes),
+        otp,
+        custom_fields: uris.chain(login.fields).collect(),
+        // nested folders are named `parent/child`
+        folder: login
+            .folder
+            .map(|folder| folder.split('/').map(str::to_string).collect())
+            .unwrap_or_default(),
+        ..Default::default()
+    });
+}
+
+// the keys a password protected export is encrypted with
+struct ExportKeys {
+    encryption: [u8; 32],
+    mac: [u8; 32],
+}
+
+// HKDF-Expand (RFC 5869) for a single block, which is all Bitwarden uses
+fn hkdf_expand(key: &[u8], info: &[u8]) -> [u8; 32] {
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(key).unwrap();
+    mac.update(info);
+    mac.update(&[1]);
+    mac.finalize().into_bytes().into()
+}
+
+fn derive_keys(export: &BitwardenExport, password: &str) -> Result<ExportKeys, BackendError> {
+    let salt = export
+        .salt
+        .as_ref()
+        .ok_or_else(|| invalid("the export has no salt"))?;
+    let iterations = export
+        .kdf_iterations
+        .ok_or_else(|| invalid("the export has no KDF iterations"))?;
+    let mut key = [0u8; 32];
+    match export.kdf_type {
+        // the salt is used as it is, not decoded
+        Some(0) => {
+            if iterations > MAX_PBKDF2_ITERATIONS {
+                return Err(invalid("the export asks for too many KDF iterations"));
+            }
+            pbkdf2_hmac::<Sha256>(password.as_bytes(), salt.as_bytes(), iterations, &mut key)
+        }
+        Some(1) => {
+            let memory = export
+                .kdf_memory
+                .ok_or_else(|| invalid("the export has no KDF memory"))?;
+            let parallelism = export
+                .kdf_parallelism
+                .ok_or_else(|| invalid("the export has no KDF parallelism"))?;
+            let memory_kib = memory
+                .checked_mul(1024)
+                .filter(|memory_kib| *memory_kib <= MAX_ARGON2_MEMORY_KIB)
+                .ok_or_else(|| invalid("the export asks for too much KDF memory"))?;
+            if iterations > MAX_ARGON2_ITERATIONS || parallelism > MAX_ARGON2_PARALLELISM {
+                return Err(invalid("the export asks for too much KDF work"));
+            }
+            let params = argon2::Params::new(memory_kib, iterations, parallelism, Some(32))
+                .map_err(|e| invalid(format!("invalid Argon2 parameters: {}", e)))?;
+            argon2::Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+                .hash_password_into(
+                    password.as_bytes(),
+                    &Sha256::digest(salt.as_bytes()),
+                    &mut key,
+                )?;
+        }
+        kdf => {
+            return Err(BackendError::UnsupportedKdf(format!(
+                "bitwarden kdf {}",
+                kdf.map_or("none".to_string(), |kdf| kdf.to_string())
+            )))
+        }
+    }
+    Ok(ExportKeys {
+        encryption: hkdf_expand(&key, b"enc"),
+        mac: hkdf_expand(&key, b"mac"),
+    })
+}
+
+// an "EncString": `2.iv|ciphertext|mac`, base64, which is AES-256-CBC and HMAC-SHA256.
+// `None` if the MAC doesn't match, i.e. the password is wrong
+fn decrypt(text: &str, keys: &ExportKeys) -> Result<Option<Vec<u8>>, BackendError> {
+    let parts = text
+        .strip_prefix("2.")
+        .ok_or_else(|| invalid("unsupported encryption type"))?
+        .split('|')
+        .map(|part| BASE64.decode(part.as_bytes()))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|_| invalid("the encrypted data isn't valid base64"))?;
+    let [iv, ciphertext, tag] = parts.as_slice() else {
+        return Err(invalid("the encrypted data is malformed"));
+    };
+    if iv.len() != 16 {
+        return Err(invalid("the encrypted data is malformed"));
+    }
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&keys.mac).unwrap();
+    mac.update(iv);
+    mac.update(ciphertext);
+    if mac.verify_slice(tag).is_err() {
+        return Ok(None);
+    }
+    cbc::Decryptor::<Aes256>::new(&keys.encryption.into(), iv.as_slice().into())
+        .decrypt_padded_vec_mut::<Pkcs7>(ciphertext)
+        .map(Some)
+        .map_err(|_| invalid("the encrypted data is malformed"))
+}
+
+fn decrypt_export(
+    export: &BitwardenExport,
+    password: &str,
+) -> Result<BitwardenExport, BackendError> {
+    let keys = derive_keys(export, password)?;
+    // this is only there to check the password
+    let validation = export
+        .key_validation
+        .as_ref()
+        .ok_or_else(|| invalid("the export has no key validation"))?;
+    if decrypt(validation, &keys)?.is_none() {
+        return Err(BackendError::IncorrectImportPassword);
+    }
+    let data = export
+        .data
+        .as_ref()
+        .ok_or_else(|| invalid("the export has no data"))?;
+    let data = decrypt(data, &keys)?.ok_or(BackendError::IncorrectImportPassword)?;
+    serde_json::from_slice(&data).map_err(|e| invalid(format!("not a Bitwarden export: {}", e)))
+}
+
+/// Reads a Bitwarden JSON export, plain or password protected. "Account restricted" exports can't be read.
+/// # Arguments
+///
+/// - `data` - a string slice that holds the exported JSON.
+/// - `password` - the password the export is protected with, if it is.
+///
+pub fn parse_json(data: &str, password: Option<&str>) -> Result<ParsedExport, BackendError> {
+    let mut export: BitwardenExport = serde_json::from_str(data)
+        .map_err(|e| invalid(format!("not a Bitwarden export: {}", e)))?;
+    if export.encrypted {
+        if !export.password_protected {
+            return Err(invalid(
+                "account restricted exports can only be read by Bitwarden, export with a password instead",
+            ));
+        }
+        let password = password.ok_or(BackendError::ImportPasswordRequired)?;
+        export = decrypt_export(&export, password)?;
+    }
+
+    let mut parsed = ParsedExport::default();
+    for item in export.items {
+        let folder = item.folder_id.and_then(|id| {
+            export
+                .folders
+                .iter()
+                .find(|folder| folder.id == id)
+                .map(|folder| folder.name.clone())
+        });
+        let kind = match item.kind {
+            1 | 2 => item.kind,
+            3 => {
+                parsed.skip(&item.name, "cards aren't supported");
+                continue;
+            }
+            4 => {
+                parsed.skip(&item.name, "identities aren't supported");
+                continue;
+            }
+            kind => {
+                parsed.skip(&item.name, format!("unsupported item type {}", kind));
+                continue;
+            }
+        };
+        let mut fields = Vec::new();
+        for field in item.fields.into_iter().flatten() {
+            let label = non_empty(field.name).unwrap_or_else(|| "Field".to_string());
+            let field_type = match field.kind {
+                0 => CustomFieldType::Text,
+                1 => CustomFieldType::Hidden,
+                2 => CustomFieldType::Boolean,
+                // its value lives in another field
+                _ => {
+                    parsed.skip(
+                        &format!("{} ({})", item.name, label),
+                        "linked fields aren't supported",
+                    );
+                    continue;
+                }
+            };
+            let field = CustomField {
+                id: 0,
+                label,
+                field_type,
+                value: field.value.unwrap_or_default(),
+            };
+            fields.push(match field.validate() {
+                Ok(_) => field,
+                Err(_) => CustomField {
+                    field_type: CustomFieldType::Text,
+                    ..field
+                },
+            });
+        }
+        let login = item.login.filter(|_| kind == 1);
+        let (username, password, totp, uris) = match login {
+            Some(login) => (
+                login.username,
+                login.password,
+                login.totp,
+                login
+                    .uris
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|uri| uri.uri)
+                    .collect(),
+            ),
+            None => (None, None, None, Vec::new()),
+        };
+        add_login(
+            &mut parsed,
+            Login {
+                name: item.name,
+                notes: item.notes,
+                folder,
+                username,
+                password,
+                totp,
+                uris,
+                fields,
+            },
+        );
+    }
+    Ok(parsed)
+}
+
+#[derive(Deserialize)]
+struct CsvRow {
+    #[serde(default)]
+    folder: String,
+    #[serde(rename = "type", default)]
+    kind: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    notes: String,
+    #[serde(default)]
+    fields: String,
+    #[serde(default)]
+    login_uri: String,
+    #[serde(default)]
+    login_username: String,
+    #[serde(default)]
+    login_password: String,
+    #[serde(default)]
+    login_totp: String,
+}
+
+/// Reads a Bitwarden CSV export. CSV exports only have logins and secure notes, and custom fields are all text.
+/// # Arguments
+///
+/// - `data` - a string slice that holds the exported CSV.
+///
+pub fn parse_csv(data: &str) -> Result<ParsedExport, BackendError> {
+    let mut reader = csv::Reader::from_reader(data.as_bytes());
+    let headers = reader
+        .headers()
+        .map_err(|e| invalid(format!("not a Bitwarden export: {}", e)))?;
+    if !headers.iter().any(|header| header == "login_password") {
+        return Err(invalid("not a Bitwarden export: the columns don't match"));
+    }
+
+    let mut parsed = ParsedExport::default();
+    for (i, row) in reader.deserialize::<CsvRow>().enumerate() {
+        let row = match row {
+            Ok(row) => row,
+            Err(e) => {
+                // the header is line 1
+                parsed.skip(&format!("line {}", i + 2), e.to_string());
+                continue;
+            }
+        };
+        if row.kind != "login" && row.kind != "note" {
+            parsed.skip(&row.name, format!("unsupported item type `{}`", row.kind));
+            continue;
+        }
+        // one `label: value` per line
+        let fields = row
+            .fields
+            .lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| {
+                let (label, value) = line.split_once(": ").unwrap_or((line, ""));
+                CustomField {
+                    id: 0,
+                    label: label.to_string(),
+                    field_type: CustomFieldType::Text,
+                    value: value.to_string(),
+                }
+            })
+            .collect();
+        add_login(
+            &mut parsed,
+            Login {
+                name: row.name,
+                notes: Some(row.notes),
+                folder: non_empty(Some(row.folder)),
+                username: Some(row.login_username),
+                password: Some(row.login_password),
+                totp: Some(row.login_totp),
+                uris: row.login_uri.split(',').map(str::to_string).collect(),
+                fields,
+            },
+        );
+    }
+    Ok(parsed)
+}
+
+#[cfg(test)]
+mod tests {
+    use aes_gcm::aes::Aes256;
+    use cbc::cipher::{block_padding::Pkcs7, BlockEncryptMut, KeyIvInit};
+    use data_encoding::BASE64;
+    use hmac::{Hmac, Mac};
+    use pbkdf2::pbkdf2_hmac;
+
+    use serde_json::json;
+    use sha2::Sha256;
+
+    use super::{hkdf_expand, parse_csv, parse_json, ExportKeys};
+    use crate::{
+        db_ops::crud_operations,
+        error::BackendError,
+        import::{self, NameCollision},
+        organize,
+        otp::Otp,
+        password::{CustomFieldType, PasswordField},
+        test_utils,
+    };
+
+    fn export() -> serde_json::Value {
+        json!({
+            "encrypted": false,
+            "folders": [
+                { "id": "f1", "name": "Work" },
+                { "id": "f2", "name": "Work/Servers" }
+            ],
+            "items": [
+                {
+                    "id": "1", "folderId": "f2", "type": 1, "name": "GitHub", "notes": "work account",
+                    "favorite": false,
+                    "fields": [
+                        { "name": "PIN", "value": "1234", "type": 1, "linkedId": null },
+                        { "name": "admin", "value": "true", "type": 2, "linkedId": null },
+                        { "name": "Username", "value": null, "type": 3, "linkedId": 100 }
+                    ],
+                    "login": {
+                        "uris": [
+                            { "match": null, "uri": "https://github.com" },
+                            { "match": null, "uri": "https://gist.github.com" }
+                        ],
+                        "username": "me", "password": "hunter2",
+                        "totp": "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ"
+                    },
+                    "collectionIds": null
+                },
+                {
+                    "id": "2", "folderId": null, "type": 2, "name": "wifi", "notes": "the password is on the router",
+                    "secureNote": { "type": 0 }
+                },
+                {
+                    "id": "3", "folderId": null, "type": 3, "name": "Visa",
+                    "card": { "number": "4111111111111111" }
+                },
+                {
+                    "id": "4", "folderId": "f1", "type": 1, "name": "Steam", "notes": null,
+                    "login": { "username": "gamer", "password": "pw", "totp": "steam://ABCDEFGH" }
+                }
+            ]
+        })
+    }
+
+    fn check(export: &import::ParsedExport) {
+        let names: Vec<_> = export
+            .entries
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["GitHub", "wifi", "Steam"]);
+
+        let github = &export.entries[0];
+        assert_eq!(github.username.as_deref(), Some("me"));
+        assert_eq!(github.password.as_deref(), Some("hunter2"));
+        assert_eq!(github.folder, vec!["Work", "Servers"]);
+        let otp = Otp::parse_uri(github.otp.as_deref().unwrap()).unwrap();
+        assert_eq!(otp.issuer.as_deref(), Some("GitHub"));
+        assert_eq!(otp.code_at(59).code, "287082");
+        let fields: Vec<_> = github
+            .custom_fields
+            .iter()
+            .map(|field| (field.label.as_str(), field.field_type, field.value.as_str()))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                ("URL", CustomFieldType::Url, "https://github.com"),
+                ("URL 2", CustomFieldType::Url, "https://gist.github.com"),
+                ("PIN", CustomFieldType::Hidden, "1234"),
+                ("admin", CustomFieldType::Boolean, "true"),
+            ]
+        );
+
+        assert_eq!(
+            export.entries[1].notes.as_deref(),
+            Some("the password is on the router")
+        );
+        assert_eq!(export.entries[2].otp, None);
+
+        let skipped: Vec<_> = export
+            .skipped
+            .iter()
+            .map(|item| item.name.as_str())
+            .collect();
+        assert_eq!(skipped, vec!["GitHub (Username)", "Visa", "Steam (TOTP)"]);
+    }
+
+    #[test]
+    fn json() {
+        check(&parse_json(&export().to_string(), None).unwrap());
+        assert!(matches!(
+            parse_json(&json!({ "encrypted": true, "data": "" }).to_string(), None),
+            Err(BackendError::InvalidImport(_))
+        ));
+        assert!(matches!(
+            parse_json("[]", None),
+            Err(BackendError::InvalidImport(_))
+        ));
+    }
+
+    #[test]
+    fn password_protected() {
+        // encrypted the way Bitwarden does it, with fewer iterations so the test is quick
+        let salt = "c2FsdHNhbHRzYWx0";
+        let mut key = [0u8; 32];
+        pbkdf2_hmac::<Sha256>(b"correct horse", salt.as_bytes(), 1000, &mut key);
+        let keys = ExportKeys {
+            encryption: hkdf_expand(&key, b"enc"),
+            mac: hkdf_expand(&key, b"mac"),
+        };
+        let encrypt = |data: &[u8]| {
+            let iv = [9u8; 16];
+            let ciphertext = cbc::Encryptor::<Aes256>::new(&keys.encryption.into(), &iv.into())
+                .encrypt_padded_vec_mut::<Pkcs7>(data);
+            let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&keys.mac).unwrap();
+            mac.update(&iv);
+            mac.update(&ciphertext);
+            format!(
+                "2.{}|{}|{}",
+                BASE64.encode(&iv),
+                BASE64.encode(&ciphertext),
+                BASE64.encode(&mac.finalize().into_bytes())
+            )
+        };
+        let protected = json!({
+            "encrypted": true,
+            "passwordProtected": true,
+            "salt": salt,
+            "kdfType": 0,
+            "kdfIterations": 1000,
+            "kdfMemory": null,
+            "kdfParallelism": null,
+            "encKeyValidation_DO_NOT_EDIT": encrypt(b"some random string"),
+            "data": encrypt(export().to_string().as_bytes()),
+        })
+        .to_string();
+
+        check(&parse_json(&protected, Some("correct horse")).unwrap());
+        assert!(matches!(
+            parse_json(&protected, None),
+            Err(BackendError::ImportPasswordRequired)
+        ));
+        assert!(matches!(
+            parse_json(&protected, Some("wrong")),
+            Err(BackendError::IncorrectImportPassword)
+        ));
+
+        // parameters that would overflow, or take forever, are refused before any work is done
+        for (kdf_type, iterations, memory) in [
+            (0, u32::MAX, None),
+            (1, 3, Some(u32::MAX)),
+            (1, 3, Some(2048)),
+            (1, u32::MAX, Some(64)),
+        ] {
+            let mut crafted: serde_json::Value = serde_json::from_str(&protected).unwrap();
+            crafted["kdfType"] = json!(kdf_type);
+            crafted["kdfIterations"] = json!(iterations);
+            crafted["kdfMemory"] = json!(memory);
+            crafted["kdfParallelism"] = json!(4);
+            assert!(matches!(
+                parse_json(&crafted.to_string(), Some("correct horse")),
+                Err(BackendError::InvalidImport(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn csv() {
+        let data = "folder,favorite,type,name,notes,fields,reprompt,login_uri,login_username,login_password,login_totp
+Work/Servers,1,login,GitHub,\"work, mostly\",\"PIN: 1234
+team: core\",0,\"https://github.com,https://gist.github.com\",me,hunter2,otpauth://totp/GitHub:me?secret=GEZDGNBVGY3TQOJQ
+,,note,wifi,on the router,,0,,,,
+,,card,Visa,,,0,,,,
+";
+        let export = parse_csv(data).unwrap();
+        assert_eq!(export.entries.len(), 2);
+        let github = &export.entries[0];
+        assert_eq!(github.notes.as_deref(), Some("work, mostly"));
+        assert_eq!(github.folder, vec!["Work", "Servers"]);
+        assert_eq!(
+            github.otp.as_deref(),
+            Some("otpauth://totp/GitHub:me?secret=GEZDGNBVGY3TQOJQ")
+        );
+        let fields: Vec<_> = github
+            .custom_fields
+            .iter()
+            .map(|field| (field.label.as_str(), field.value.as_str()))
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                ("URL", "https://github.com"),
+                ("URL 2", "https://gist.github.com"),
+                ("PIN", "1234"),
+                ("team", "core"),
+            ]
+        );
+        let wifi = &export.entries[1];
+        assert_eq!(wifi.username, None);
+        assert!(wifi.custom_fields.is_empty());
+        assert_eq!(export.skipped[0].name, "Visa");
+
+        assert!(matches!(
+            parse_csv("name,url,username,password\nGitHub,https://github.com,me,pw\n"),
+            Err(BackendError::InvalidImport(_))
+        ));
+    }
+
+    #[test]
+    fn import() {
+        let (connection, key) = test_utils::vault();
+        organize::create_folder(&connection, &key, "work", None).unwrap();
+        crud_operations::insert_data(&connection, "GitHub", &key, PasswordField::Password, "old")
+            .unwrap();
+
+        let data = export().to_string();
+        let report = import::add_entries(
+            &connection,
+            &key,
+            parse_json(&data, None).unwrap(),
+            NameCollision::Suffix,
+        )
+        .unwrap();
+        assert_eq!(report.imported, vec!["GitHub (2)", "wifi", "Steam"]);
+        assert_eq!(report.skipped.len(), 3);
+        let github = crud_operations::read_password_info(&connection, "GitHub (2)", &key)
+            .unwrap()
+            .unwrap();
+        assert_eq!(github.custom_fields.len(), 4);
+        // the existing folder is reused, the missing one is created in it
+        let folders = organize::list_folders(&connection, &key).unwrap();
+        assert_eq!(folders.len(), 2);
+        let servers = folders
+            .iter()
+            .find(|folder| folder.name == "Servers")
+            .unwrap();
+        assert_eq!(github.folder_id, Some(servers.id));
+        let work = folders.iter().find(|folder| folder.name == "work").unwrap();
+        assert_eq!(servers.parent_id, Some(work.id));
+
+        // merging writes over the existing passwords instead. the second time, the custom fields are already there
+        for _ in 0..2 {
+            let report = import::add_entries(
+                &connection,
+                &key,
+                parse_json(&data, None).unwrap(),
+                NameCollision::Merge,
+            )
+            .unwrap();
+            assert!(report.imported.is_empty());
+            assert_eq!(report.merged, vec!["GitHub", "wifi", "Steam"]);
+        }
+        let github = crud_operations::read_password_info(&connection, "GitHub", &key)
+            .unwrap()
+            .unwrap();
+        assert_eq!(github.password.as_deref(), Some("hunter2"));
+        assert_eq!(github.username.as_deref(), Some("me"));
+        assert_eq!(github.custom_fields.len(), 4);
+        assert_eq!(
+            crate::history::list_history(&connection, &key, "GitHub", None).unwrap()[0].value,
+            "old"
+        );
+        let steam = crud_operations::read_password_info(&connection, "Steam", &key)
+            .unwrap()
+            .unwrap();
+        assert_eq!(steam.custom_fields.len(), 0);
+    }
+}
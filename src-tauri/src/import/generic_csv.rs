@@ -0,0 +1,570 @@
+// CSV files from anywhere: browsers and password managers all export one, each with its own columns.
+// the user says what each column holds (or we recognize the headers of a known app, see `CsvPreset`),
+// checks a preview of the rows, then imports the ones without errors.
+
+use super::{otp_uri, ImportedEntry, ParsedExport};
+use crate::{
+    error::BackendError,
+    password::{CustomField, CustomFieldType},
+};
+
+/// What a column of a CSV file holds.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum CsvColumn {
+    Name,
+    Url,
+    Username,
+    Email,
+    Password,
+    Notes,
+    /// A TOTP secret, as an `otpauth://` URI or just the base32 secret.
+    Otp,
+    /// The folder the entry goes in. Nested folders are separated by `/` (`\` for LastPass).
+    Folder,
+    /// A custom text field, labeled with the column's header.
+    /// In passman's own exports the header also says the field's type, see `custom_field_header`.
+    Custom,
+    /// Not imported.
+    Skip,
+}
+
+/// An app whose CSV exports we recognize.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum CsvPreset {
+    Chrome,
+    Firefox,
+    Safari,
+    LastPass,
+    OnePassword,
+}
+
+impl CsvPreset {
+    /// Every preset, the most specific first, which is the order they're detected in.
+    pub const ALL: [CsvPreset; 5] = [
+        CsvPreset::LastPass,
+        CsvPreset::Firefox,
+        CsvPreset::OnePassword,
+        CsvPreset::Safari,
+        CsvPreset::Chrome,
+    ];
+
+    /// The columns of the app's exports, in order.
+    pub fn columns(&self) -> &'static [(&'static str, CsvColumn)] {
+        use CsvColumn::*;
+        match self {
+            CsvPreset::Chrome => &[
+                ("name", Name),
+                ("url", Url),
+                ("username", Username),
+                ("password", Password),
+                ("note", Notes),
+            ],
+            CsvPreset::Firefox => &[
+                ("url", Url),
+                ("username", Username),
+                ("password", Password),
+                ("httpRealm", Skip),
+                ("formActionOrigin", Skip),
+                ("guid", Skip),
+                ("timeCreated", Skip),
+                ("timeLastUsed", Skip),
+                ("timePasswordChanged", Skip),
+            ],
+            CsvPreset::Safari => &[
+                ("Title", Name),
+                ("URL", Url),
+                ("Username", Username),
+                ("Password", Password),
+                ("Notes", Notes),
+                ("OTPAuth", Otp),
+            ],
+            CsvPreset::LastPass => &[
+                ("url", Url),
+                ("username", Username),
+                ("password", Password),
+                ("totp", Otp),
+                ("extra", Notes),
+                ("name", Name),
+                ("grouping", Folder),
+                ("fav", Skip),
+            ],
+            CsvPreset::OnePassword => &[
+                ("Title", Name),
+                ("Url", Url),
+                ("Username", Username),
+                ("Password", Password),
+                ("OTPAuth", Otp),
+                ("Favorite", Skip),
+                ("Archived", Skip),
+                ("Tags", Skip),
+                ("Notes", Notes),
+            ],
+        }
+    }
+
+    // the headers that tell the app's exports apart, lowercase. older versions of an app may lack the others
+    fn signature(&self) -> &'static [&'static str] {
+        match self {
+            CsvPreset::Chrome => &["name", "url", "username", "password"],
+            CsvPreset::Firefox => &["url", "username", "password", "httprealm"],
+            CsvPreset::Safari => &["title", "url", "username", "password", "otpauth"],
+            CsvPreset::LastPass => &["url", "username", "password", "extra", "name", "grouping"],
+            CsvPreset::OnePassword => &["title", "url", "username", "password", "archived"],
+        }
+    }
+
+    /// Finds the app a CSV file is from by its headers, if it's one we know:
+    /// the file has to have the headers the app's exports have, and no others.
+    pub fn detect(headers: &[String]) -> Option<CsvPreset> {
+        let headers: Vec<_> = headers
+            .iter()
+            .map(|header| header.trim().to_lowercase())
+            .collect();
+        CsvPreset::ALL.into_iter().find(|preset| {
+            let known = |header: &String| {
+                preset
+                    .columns()
+                    .iter()
+                    .any(|(name, _)| name.eq_ignore_ascii_case(header))
+            };
+            preset
+                .signature()
+                .iter()
+                .all(|header| headers.iter().any(|h| h == header))
+                && headers.iter().all(known)
+        })
+    }
+}
+
+/// What separates nested folders in a folder column, for files from `preset` (or from passman, or anywhere else, if `None`).
+pub fn folder_separator(preset: Option<CsvPreset>) -> char {
+    match preset {
+        Some(CsvPreset::LastPass) => '\\',
+        _ => '/',
+    }
+}
+
+/// The columns of passman's own CSV exports, which `guess_column` maps back.
+pub const DEFAULT_COLUMNS: [(&str, CsvColumn); 8] = [
+    ("name", CsvColumn::Name),
+    ("url", CsvColumn::Url),
+    ("username", CsvColumn::Username),
+    ("email", CsvColumn::Email),
+    ("password", CsvColumn::Password),
+    ("notes", CsvColumn::Notes),
+    ("otp", CsvColumn::Otp),
+    ("folder", CsvColumn::Folder),
+];
+
+/// The characters a spreadsheet may take as the start of a formula, see `escape_cell`.
+const FORMULA_START: [char; 6] = ['=', '+', '-', '@', '\t', '\r'];
+
+/// Prefixes `'` to a value a spreadsheet would otherwise take as a formula, for passman's own CSV exports.
+/// A value that already starts with `'` gets one too, so `unescape_cell` can always take it off again.
+pub fn escape_cell(value: &str) -> String {
+    match value.starts_with(FORMULA_START) || value.starts_with('\'') {
+        true => format!("'{}", value),
+        false => value.to_string(),
+    }
+}
+
+/// Undoes `escape_cell`.
+pub fn unescape_cell(value: &str) -> &str {
+    value.strip_prefix('\'').unwrap_or(value)
+}
+
+/// The header of a column of passman's own CSV exports that holds custom fields labeled `label` of type `field_type`,
+/// e.g. `PIN [hidden]`.
+pub fn custom_field_header(label: &str, field_type: CustomFieldType) -> String {
+    format!("{} [{}]", label, field_type)
+}
+
+// the label and type of a custom field column of passman's own exports, see `custom_field_header`.
+// a header without a type is a text field's label
+fn custom_field_column(header: &str) -> (String, CustomFieldType) {
+    header
+        .strip_suffix(']')
+        .and_then(|rest| rest.rsplit_once(" ["))
+        .and_then(|(label, field_type)| Some((label.to_string(), field_type.parse().ok()?)))
+        .unwrap_or_else(|| (header.trim().to_string(), CustomFieldType::Text))
+}
+
+/// Guesses what a column holds from its header, for files from apps without a preset.
+pub fn guess_column(header: &str) -> CsvColumn {
+    match header.trim().to_lowercase().as_str() {
+        "name" | "title" => CsvColumn::Name,
+        "url" | "uri" | "website" | "login_uri" => CsvColumn::Url,
+        "username" | "user name" | "user" | "login" | "login_username" => CsvColumn::Username,
+        "email" | "e-mail" | "email address" => CsvColumn::Email,
+        "password" | "login_password" => CsvColumn::Password,
+        "notes" | "note" | "extra" | "comments" => CsvColumn::Notes,
+        "otp" | "totp" | "otpauth" | "login_totp" => CsvColumn::Otp,
+        "folder" | "group" | "grouping" | "category" => CsvColumn::Folder,
+        _ => CsvColumn::Custom,
+    }
+}
+
+/// A row of a CSV file, as it would be imported.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct CsvRow {
+    /// The line the row is on, counting the header as line 1.
+    pub line: usize,
+    pub entry: ImportedEntry,
+    /// Why the row can't be imported. Rows with errors are skipped.
+    pub errors: Vec<String>,
+}
+
+/// What importing a CSV file would do, for the user to check first.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct CsvPreview {
+    pub headers: Vec<String>,
+    /// The app the file was recognized as being from, if any.
+    pub preset: Option<CsvPreset>,
+    /// What each column holds, in the order of `headers`.
+    pub mapping: Vec<CsvColumn>,
+    pub rows: Vec<CsvRow>,
+}
+
+impl CsvPreview {
+    /// The rows to import, the ones with errors skipped.
+    pub fn into_export(self) -> ParsedExport {
+        let mut export = ParsedExport::default();
+        for row in self.rows {
+            if row.errors.is_empty() {
+                export.entries.push(row.entry);
+                continue;
+            }
+            let name = match row.entry.name.is_empty() {
+                true => format!("line {}", row.line),
+                false => row.entry.name,
+            };
+            export.skip(&name, row.errors.join(", "));
+        }
+        export
+    }
+}
+
+fn invalid(message: impl Into<String>) -> BackendError {
+    BackendError::InvalidImport(message.into())
+}
+
+// the site a URL is for, to name entries from exports that don't have names (e.g. Firefox's)
+fn host(url: &str) -> Option<&str> {
+    let rest = url.split_once("://").map_or(url, |(_, rest)| rest);
+    let host = rest.split(['/', '?', '#', ':']).next()?;
+    let host = host.strip_prefix("www.").unwrap_or(host);
+    (!host.is_empty()).then_some(host)
+}
+
+fn read_row(
+    headers: &[String],
+    mapping: &[CsvColumn],
+    record: &csv::StringRecord,
+    separator: char,
+    escaped: bool,
+) -> (ImportedEntry, Vec<String>) {
+    let mut entry = ImportedEntry::default();
+    let mut errors = Vec::new();
+    let mut otp = None;
+    let mut urls = Vec::new();
+    for ((header, column), value) in headers.iter().zip(mapping).zip(record) {
+        let value = match escaped {
+            true => unescape_cell(value),
+            false => value,
+        };
+        // whitespace only decides whether there's anything there: a password or a note is kept as it is,
+        // the columns that say where an entry goes or what it's for aren't
+        let trimmed = value.trim();
+        if trimmed.is_empty() {
+            continue;
+        }
+        let field = match column {
+            CsvColumn::Name if entry.name.is_empty() => {
+                entry.name = trimmed.to_string();
+                continue;
+            }
+            CsvColumn::Username => Some(&mut entry.username),
+            CsvColumn::Email => Some(&mut entry.email),
+            CsvColumn::Password => Some(&mut entry.password),
+            CsvColumn::Notes => Some(&mut entry.notes),
+            CsvColumn::Otp => Some(&mut otp),
+            // LastPass' secure notes have this as their URL
+            CsvColumn::Url if trimmed == "http://sn" => continue,
+            CsvColumn::Url => {
+                urls.push(trimmed.to_string());
+                continue;
+            }
+            CsvColumn::Folder if entry.folder.is_empty() => {
+                entry.folder = trimmed.split(separator).map(str::to_string).collect();
+                continue;
+            }
+            CsvColumn::Skip => continue,
+            // a second name or folder is kept, like any other column
+            CsvColumn::Name | CsvColumn::Folder | CsvColumn::Custom => None,
+        };
+        if let Some(field @ None) = field {
+            *field = Some(value.to_string());
+            continue;
+        }
+        // only the first column of a kind fills the field, the others become custom fields
+        let (label, field_type) = match escaped {
+            true => custom_field_column(header),
+            false => (header.trim().to_string(), CustomFieldType::Text),
+        };
+        entry.custom_fields.push(CustomField {
+            id: 0,
+            label,
+            field_type,
+            value: value.to_string(),
+        });
+    }
+    if entry.name.is_empty() {
+        match urls.first().and_then(|url| host(url)) {
+            Some(host) => entry.name = host.to_string(),
+            None => errors.push("it has no name".to_string()),
+        }
+    }
+    if let Some(otp) = otp {
+        match otp_uri(otp.trim(), Some(&entry.name), entry.username.as_deref()) {
+            Ok(uri) => entry.otp = Some(uri),
+            Err(e) => errors.push(format!("the TOTP secret is invalid: {}", e)),
+        }
+    }
+    let urls = urls.into_iter().enumerate().map(|(i, url)| CustomField {
+        id: 0,
+        label: match i {
+            0 => "URL".to_string(),
+            i => format!("URL {}", i + 1),
+        },
+        field_type: CustomFieldType::Url,
+        value: url,
+    });
+    entry.custom_fields.splice(0..0, urls);
+    (entry, errors)
+}
+
+/// Reads a CSV file into a preview of what importing it would do. Nothing is written.
+/// Fails with `BackendError::InvalidImport` if the file has no header, or the mapping doesn't fit it.
+/// # Arguments
+///
+/// - `data` - a string slice that holds the CSV, starting with a header.
+/// - `mapping` - what each column holds, in order. If `None`, the file's app is detected from its headers
+///   (see `CsvPreset::detect`), and otherwise each column is guessed from its header (see `guess_column`).
+///
+pub fn preview(data: &str, mapping: Option<Vec<CsvColumn>>) -> Result<CsvPreview, BackendError> {
+    // some apps start the file with a byte order mark
+    let data = data.trim_start_matches('\u{feff}');
+    let mut reader = csv::Reader::from_reader(data.as_bytes());
+    let headers: Vec<String> = reader
+        .headers()
+        .map_err(|e| invalid(format!("not a CSV file: {}", e)))?
+        .iter()
+        .map(str::to_string)
+        .collect();
+    if headers.iter().all(|header| header.trim().is_empty()) {
+        return Err(invalid("not a CSV file: the header is missing"));
+    }
+    let preset = CsvPreset::detect(&headers);
+    let mapping = match mapping {
+        Some(mapping) if mapping.len() != headers.len() => {
+            return Err(invalid(format!(
+                "the file has {} columns, but {} were mapped",
+                headers.len(),
+                mapping.len()
+            )))
+        }
+        Some(mapping) => mapping,
+        None => headers
+            .iter()
+            .map(|header| {
+                preset
+                    .and_then(|preset| {
+                        preset
+                            .columns()
+                            .iter()
+                            .find(|(name, _)| name.eq_ignore_ascii_case(header.trim()))
+                    })
+                    .map_or_else(|| guess_column(header), |(_, column)| *column)
+            })
+            .collect(),
+    };
+
+    let separator = folder_separator(preset);
+    // passman's own exports are escaped for spreadsheets. they start with its columns, followed by custom fields
+    let escaped = headers.len() >= DEFAULT_COLUMNS.len()
+        && headers
+            .iter()
+            .zip(DEFAULT_COLUMNS)
+            .all(|(header, (default, _))| header == default);
+    let mut rows = Vec::new();
+    for (i, record) in reader.records().enumerate() {
+        // the header is line 1
+        let line = i + 2;
+        let (entry, errors) = match record {
+            Ok(record) => read_row(&headers, &mapping, &record, separator, escaped),
+            Err(e) => (ImportedEntry::default(), vec![e.to_string()]),
+        };
+        rows.push(CsvRow {
+            line,
+            entry,
+            errors,
+        });
+    }
+    Ok(CsvPreview {
+        headers,
+        preset,
+        mapping,
+        rows,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{escape_cell, preview, unescape_cell, CsvColumn, CsvPreset};
+    use crate::{
+        db_ops::crud_operations,
+        error::BackendError,
+        import::{self, NameCollision},
+        organize,
+        password::CustomFieldType,
+        test_utils,
+    };
+
+    #[test]
+    fn presets() {
+        let files = [
+            (
+                CsvPreset::Chrome,
+                "name,url,username,password,note\nGitHub,https://github.com/login,me,hunter2,hi\n",
+            ),
+            (
+                CsvPreset::Firefox,
+                "\"url\",\"username\",\"password\",\"httpRealm\",\"formActionOrigin\",\"guid\",\"timeCreated\",\"timeLastUsed\",\"timePasswordChanged\"\n\
+                \"https://www.github.com\",\"me\",\"hunter2\",,\"https://github.com\",\"{1}\",\"1\",\"1\",\"1\"\n",
+            ),
+            (
+                CsvPreset::Safari,
+                "\u{feff}Title,URL,Username,Password,Notes,OTPAuth\nGitHub,https://github.com/,me,hunter2,,\n",
+            ),
+            (
+                CsvPreset::LastPass,
+                "url,username,password,totp,extra,name,grouping,fav\nhttps://github.com,me,hunter2,,,GitHub,Work\\Dev,0\n",
+            ),
+            (
+                CsvPreset::OnePassword,
+                "Title,Url,Username,Password,OTPAuth,Favorite,Archived,Tags,Notes\nGitHub,https://github.com,me,hunter2,,false,false,,\n",
+            ),
+        ];
+        for (expected, file) in files {
+            let preview = preview(file, None).unwrap();
+            assert_eq!(preview.preset, Some(expected));
+            let row = &preview.rows[0];
+            assert!(row.errors.is_empty(), "{:?}", row.errors);
+            // Firefox's logins are named after their site
+            assert!(row.entry.name.to_lowercase().starts_with("github"));
+            assert_eq!(row.entry.username.as_deref(), Some("me"));
+            assert_eq!(row.entry.password.as_deref(), Some("hunter2"));
+            assert_eq!(row.entry.custom_fields[0].field_type, CustomFieldType::Url);
+            // nothing but the URL
+            assert_eq!(row.entry.custom_fields.len(), 1);
+        }
+        let lastpass = preview(files[3].1, None).unwrap();
+        assert_eq!(lastpass.rows[0].entry.folder, vec!["Work", "Dev"]);
+        let chrome = preview(files[0].1, None).unwrap();
+        assert_eq!(chrome.rows[0].entry.notes.as_deref(), Some("hi"));
+    }
+
+    #[test]
+    fn spreadsheet_escaping() {
+        for (value, escaped) in [
+            ("=1+1", "'=1+1"),
+            ("-5", "'-5"),
+            ("@SUM(A1)", "'@SUM(A1)"),
+            ("\tx", "'\tx"),
+            ("'quoted", "''quoted"),
+            ("hunter2", "hunter2"),
+        ] {
+            assert_eq!(escape_cell(value), escaped);
+            assert_eq!(unescape_cell(&escape_cell(value)), value);
+        }
+    }
+
+    #[test]
+    fn whitespace() {
+        let file =
+            "name,password,notes,folder\n  GitHub  ,  hunter2 ,\" two\nlines \", Work \n   ,,,\n";
+        let preview = preview(file, None).unwrap();
+        assert_eq!(preview.rows.len(), 2);
+        let entry = &preview.rows[0].entry;
+        assert_eq!(entry.name, "GitHub");
+        assert_eq!(entry.password.as_deref(), Some("  hunter2 "));
+        assert_eq!(entry.notes.as_deref(), Some(" two\nlines "));
+        assert_eq!(entry.folder, vec!["Work"]);
+        // a row of blanks is empty
+        assert_eq!(preview.rows[1].errors, vec!["it has no name"]);
+    }
+
+    #[test]
+    fn mapping() {
+        let file = "Site,Login,Secret,Mail,Security question,2FA\n\
+            GitHub,me,hunter2,me@example.com,Pet?,GEZDGNBVGY3TQOJQ\n\
+            ,nobody,,,,\n\
+            Steam,me,,,,not base32!\n\
+            too,few\n";
+        // the headers are guessed where they can be
+        let guessed = preview(file, None).unwrap();
+        assert_eq!(guessed.preset, None);
+        assert_eq!(guessed.mapping[1], CsvColumn::Username);
+        assert_eq!(guessed.mapping[2], CsvColumn::Custom);
+
+        let mapping = vec![
+            CsvColumn::Name,
+            CsvColumn::Username,
+            CsvColumn::Password,
+            CsvColumn::Email,
+            CsvColumn::Custom,
+            CsvColumn::Otp,
+        ];
+        let preview = preview(file, Some(mapping.clone())).unwrap();
+        let github = &preview.rows[0];
+        assert!(github.errors.is_empty());
+        assert_eq!(github.entry.email.as_deref(), Some("me@example.com"));
+        assert_eq!(github.entry.custom_fields[0].label, "Security question");
+        assert!(github
+            .entry
+            .otp
+            .as_deref()
+            .unwrap()
+            .starts_with("otpauth://totp/GitHub:me?"));
+        // per-row errors, with the line they're on
+        assert_eq!(preview.rows[1].line, 3);
+        assert_eq!(preview.rows[1].errors, vec!["it has no name"]);
+        assert_eq!(preview.rows[2].errors.len(), 1);
+        assert_eq!(preview.rows[3].errors.len(), 1);
+
+        assert!(matches!(
+            super::preview(file, Some(mapping[1..].to_vec())),
+            Err(BackendError::InvalidImport(_))
+        ));
+
+        // only the rows without errors are imported, in one go
+        let (connection, key) = test_utils::vault();
+        let report = import::add_entries(
+            &connection,
+            &key,
+            preview.into_export(),
+            NameCollision::Suffix,
+        )
+        .unwrap();
+        assert_eq!(report.imported, vec!["GitHub"]);
+        let skipped: Vec<_> = report.skipped.iter().map(|item| &item.name).collect();
+        assert_eq!(skipped, vec!["line 3", "Steam", "line 5"]);
+        let github = crud_operations::read_password_info(&connection, "GitHub", &key)
+            .unwrap()
+            .unwrap();
+        assert_eq!(github.password.as_deref(), Some("hunter2"));
+        assert!(organize::list_folders(&connection, &key)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,432 @@
+// KeePass and KeePassXC databases (KDBX 4), see `kdbx` for the file format.
+// an entry's standard fields map to a password's, the other fields become custom fields, and groups become folders.
+// attachments become custom fields of their own type, see `CustomFieldType::Attachment`.
+
+use std::str::FromStr;
+
+use data_encoding::BASE64;
+
+use super::{otp_uri, ImportedEntry, ParsedExport, PreviousValue};
+use crate::{
+    error::BackendError,
+    kdbx::{self, Entry, Group},
+    otp::{decode_secret, Otp, OtpAlgorithm, OtpKind},
+    password::{CustomField, CustomFieldType, PasswordField},
+};
+
+/// The field of a KeePass entry each of a password's fields goes in, and whether KeePass should protect it.
+/// KeePass has no email field, so it's a custom one.
+pub fn string_key(field: &PasswordField) -> (&'static str, bool) {
+    match field {
+        PasswordField::Email => ("Email", false),
+        PasswordField::Username => ("UserName", false),
+        PasswordField::Password => ("Password", true),
+        PasswordField::Notes => ("Notes", false),
+        PasswordField::Otp => ("otp", true),
+    }
+}
+
+const FIELDS: [PasswordField; 5] = [
+    PasswordField::Email,
+    PasswordField::Username,
+    PasswordField::Password,
+    PasswordField::Notes,
+    PasswordField::Otp,
+];
+
+// KeePass 2's own TOTP fields. KeePassXC uses `otp`, holding a URI
+const TIME_OTP_SECRET: &str = "TimeOtp-Secret-Base32";
+const TIME_OTP_LENGTH: &str = "TimeOtp-Length";
+const TIME_OTP_PERIOD: &str = "TimeOtp-Period";
+const TIME_OTP_ALGORITHM: &str = "TimeOtp-Algorithm";
+
+fn non_empty(text: Option<&str>) -> Option<String> {
+    text.filter(|text| !text.trim().is_empty())
+        .map(str::to_string)
+}
+
+fn number<T: FromStr>(entry: &Entry, key: &str, default: T) -> Result<T, BackendError> {
+    match entry.get(key) {
+        Some(value) => value
+            .trim()
+            .parse()
+            .map_err(|_| BackendError::InvalidOtp(format!("{} isn't a number", key))),
+        None => Ok(default),
+    }
+}
+
+fn time_otp(entry: &Entry, secret: &str) -> Result<String, BackendError> {
+    let otp = Otp {
+        kind: OtpKind::Totp {
+            period: number(entry, TIME_OTP_PERIOD, 30)?,
+        },
+        secret: decode_secret(secret)?,
+        algorithm: match entry.get(TIME_OTP_ALGORITHM).map(str::trim) {
+            None | Some("HMAC-SHA-1") => OtpAlgorithm::Sha1,
+            Some("HMAC-SHA-256") => OtpAlgorithm::Sha256,
+            Some("HMAC-SHA-512") => OtpAlgorithm::Sha512,
+            Some(algorithm) => {
+                return Err(BackendError::InvalidOtp(format!(
+                    "unsupported algorithm {}",
+                    algorithm
+                )))
+            }
+        },
+        digits: number(entry, TIME_OTP_LENGTH, 6)?,
+        issuer: non_empty(entry.get("Title")),
+        account: non_empty(entry.get("UserName")),
+    };
+    otp.validate()?;
+    Ok(otp.to_uri())
+}
+
+// the entry's TOTP secret as a URI, from whichever of the two ways it's stored
+fn otp(entry: &Entry) -> Option<Result<String, BackendError>> {
+    if let Some(uri) = non_empty(entry.get("otp")) {
+        return Some(otp_uri(&uri, entry.get("Title"), entry.get("UserName")));
+    }
+    non_empty(entry.get(TIME_OTP_SECRET)).map(|secret| time_otp(entry, &secret))
+}
+
+fn is_email(key: &str) -> bool {
+    key.eq_ignore_ascii_case("email") || key.eq_ignore_ascii_case("e-mail")
+}
+
+// the values of the fields a password has, in the order of `FIELDS`
+fn field_values(entry: &Entry, otp: Option<String>) -> [Option<String>; 5] {
+    let email = entry
+        .strings
+        .iter()
+        .find(|string| is_email(&string.key))
+        .and_then(|string| non_empty(Some(&string.value)));
+    [
+        email,
+        non_empty(entry.get("UserName")),
+        non_empty(entry.get("Password")),
+        non_empty(entry.get("Notes")),
+        otp,
+    ]
+}
+
+// what changed between each version of the entry and the next
+fn history(entry: &Entry, current: &[Option<String>; 5]) -> Vec<PreviousValue> {
+    let mut versions: Vec<_> = entry
+        .history
+        .iter()
+        .map(|version| {
+            let otp = otp(version).and_then(Result::ok);
+            (field_values(version, otp), version.times.modified)
+        })
+        .collect();
+    versions.push((current.clone(), entry.times.modified));
+    let mut changes = Vec::new();
+    for pair in versions.windows(2) {
+        let ((older, older_modified), (newer, newer_modified)) = (&pair[0], &pair[1]);
+        // the version was replaced when the next one was saved
+        let replaced_at = newer_modified.or(*older_modified).unwrap_or_default();
+        for (field, (old, new)) in FIELDS.iter().zip(older.iter().zip(newer)) {
+            if let Some(old) = old.as_ref().filter(|old| Some(*old) != new.as_ref()) {
+                changes.push(PreviousValue {
+                    field: field.clone(),
+                    value: old.clone(),
+                    replaced_at,
+                });
+            }
+        }
+    }
+    changes
+}
+
+fn add_entry(export: &mut ParsedExport, entry: &Entry, folder: &[String]) {
+    let name = entry.get("Title").unwrap_or_default().to_string();
+    let (otp, otp_keys): (_, &[&str]) = match otp(entry) {
+        Some(Ok(uri)) => (
+            Some(uri),
+            &[
+                "otp",
+                TIME_OTP_SECRET,
+                TIME_OTP_LENGTH,
+                TIME_OTP_PERIOD,
+                TIME_OTP_ALGORITHM,
+            ],
+        ),
+        // the rest of the entry is still worth having, and the secret stays in a custom field
+        Some(Err(e)) => {
+            export.skip(
+                &format!("{} (TOTP)", name),
+                format!("the TOTP secret couldn't be imported: {}", e),
+            );
+            (None, &[])
+        }
+        None => (None, &[]),
+    };
+    let values = field_values(entry, otp);
+    let history = history(entry, &values);
+    let [email, username, password, notes, otp] = values;
+
+    let mut custom_fields = Vec::new();
+    let mut email_taken = email.is_some();
+    for string in &entry.strings {
+        let key = string.key.as_str();
+        if matches!(key, "Title" | "UserName" | "Password" | "Notes")
+            || otp_keys.contains(&key)
+            || string.value.is_empty()
+        {
+            continue;
+        }
+        // only the first email address goes in the email field
+        if is_email(key) && email_taken {
+            email_taken = false;
+            continue;
+        }
+        custom_fields.push(CustomField {
+            id: 0,
+            label: key.to_string(),
+            field_type: match (key, string.protected) {
+                ("URL", _) => CustomFieldType::Url,
+                (_, true) => CustomFieldType::Hidden,
+                (_, false) => CustomFieldType::Text,
+            },
+            value: string.value.clone(),
+        });
+    }
+    for attachment in &entry.attachments {
+        custom_fields.push(CustomField {
+            id: 0,
+            label: attachment.name.clone(),
+            field_type: CustomFieldType::Attachment,
+            value: BASE64.encode(&attachment.data),
+        });
+    }
+
+    export.entries.push(ImportedEntry {
+        name,
+        email,
+        username,
+        password,
+        notes,
+        otp,
+        custom_fields,
+        folder: folder.to_vec(),
+        history,
+    });
+}
+
+fn add_group(
+    export: &mut ParsedExport,
+    group: &Group,
+    folder: &mut Vec<String>,
+    recycle_bin: Option<[u8; 16]>,
+) {
+    if Some(group.uuid) == recycle_bin {
+        for entry in all_entries(group) {
+            export.skip(
+                entry.get("Title").unwrap_or_default(),
+                "it's in the recycle bin",
+            );
+        }
+        return;
+    }
+    for entry in &group.entries {
+        add_entry(export, entry, folder);
+    }
+    for child in &group.groups {
+        folder.push(child.name.clone());
+        add_group(export, child, folder, recycle_bin);
+        folder.pop();
+    }
+}
+
+fn all_entries(group: &Group) -> Vec<&Entry> {
+    group
+        .entries
+        .iter()
+        .chain(group.groups.iter().flat_map(all_entries))
+        .collect()
+}
+
+/// Reads a KeePass database. Entries in the recycle bin are skipped.
+/// Fails with `BackendError::IncorrectImportPassword` if `password` is wrong,
+/// or with `BackendError::InvalidImport` if the file isn't a KDBX 4 database.
+/// # Arguments
+///
+/// - `data` - the contents of the `.kdbx` file.
+/// - `password` - a string slice that holds the database's password.
+///
+pub fn parse(data: &[u8], password: &str) -> Result<ParsedExport, BackendError> {
+    let database = kdbx::read(data, password)?;
+    let mut export = ParsedExport::default();
+    // the root group is the database itself, not a folder
+    add_group(
+        &mut export,
+        &database.root,
+        &mut Vec::new(),
+        database.recycle_bin,
+    );
+    Ok(export)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::parse;
+    use crate::{
+        import::PreviousValue,
+        kdbx::{self, Attachment, Database, Entry, EntryString, Group, KdbxSettings, Kdf, Times},
+        password::{CustomFieldType, PasswordField},
+    };
+
+    fn string(key: &str, value: &str) -> EntryString {
+        EntryString {
+            key: key.to_string(),
+            value: value.to_string(),
+            protected: key == "Password",
+        }
+    }
+
+    #[test]
+    fn keepass() {
+        let entry = Entry {
+            times: Times {
+                modified: Some(300),
+                ..Default::default()
+            },
+            strings: vec![
+                string("Title", "GitHub"),
+                string("UserName", "me"),
+                string("Password", "new"),
+                string("URL", "https://github.com"),
+                string("E-mail", "me@example.com"),
+                string(super::TIME_OTP_SECRET, "GEZDGNBVGY3TQOJQ"),
+                string(super::TIME_OTP_ALGORITHM, "HMAC-SHA-256"),
+                string("PIN", "1234"),
+            ],
+            attachments: vec![Attachment {
+                name: "codes.txt".to_string(),
+                data: b"1234".to_vec(),
+            }],
+            history: vec![
+                Entry {
+                    times: Times {
+                        modified: Some(100),
+                        ..Default::default()
+                    },
+                    strings: vec![string("UserName", "me"), string("Password", "oldest")],
+                    ..Default::default()
+                },
+                Entry {
+                    times: Times {
+                        modified: Some(200),
+                        ..Default::default()
+                    },
+                    strings: vec![string("UserName", "me"), string("Password", "old")],
+                    ..Default::default()
+                },
+            ],
+            ..Default::default()
+        };
+        let recycle_bin = Group {
+            uuid: [9; 16],
+            name: "Recycle Bin".to_string(),
+            entries: vec![Entry {
+                strings: vec![string("Title", "deleted")],
+                ..Default::default()
+            }],
+            ..Default::default()
+        };
+        let database = Database {
+            name: "test".to_string(),
+            root: Group {
+                name: "Root".to_string(),
+                groups: vec![
+                    Group {
+                        uuid: [1; 16],
+                        name: "Work".to_string(),
+                        entries: vec![entry],
+                        ..Default::default()
+                    },
+                    recycle_bin,
+                ],
+                ..Default::default()
+            },
+            recycle_bin: Some([9; 16]),
+        };
+        let settings = KdbxSettings {
+            kdf: Kdf::AesKdf { rounds: 10 },
+            ..Default::default()
+        };
+        let file = kdbx::write(&database, "secret", &settings).unwrap();
+        let export = parse(&file, "secret").unwrap();
+
+        assert_eq!(export.entries.len(), 1);
+        assert_eq!(export.skipped[0].name, "deleted");
+        let entry = &export.entries[0];
+        assert_eq!(entry.name, "GitHub");
+        assert_eq!(entry.folder, vec!["Work"]);
+        assert_eq!(entry.email.as_deref(), Some("me@example.com"));
+        assert_eq!(entry.password.as_deref(), Some("new"));
+        assert_eq!(
+            entry.otp.as_deref(),
+            Some("otpauth://totp/GitHub:me?secret=GEZDGNBVGY3TQOJQ&issuer=GitHub&algorithm=SHA256&digits=6&period=30")
+        );
+        let fields: Vec<_> = entry
+            .custom_fields
+            .iter()
+            .map(|field| {
+                (
+                    field.label.as_str(),
+                    &field.field_type,
+                    field.value.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            fields,
+            vec![
+                ("URL", &CustomFieldType::Url, "https://github.com"),
+                ("PIN", &CustomFieldType::Text, "1234"),
+                ("codes.txt", &CustomFieldType::Attachment, "MTIzNA=="),
+            ]
+        );
+        // the username never changed
+        assert_eq!(
+            entry.history,
+            vec![
+                PreviousValue {
+                    field: PasswordField::Password,
+                    value: "oldest".to_string(),
+                    replaced_at: 200,
+                },
+                PreviousValue {
+                    field: PasswordField::Password,
+                    value: "old".to_string(),
+                    replaced_at: 300,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn keepassxc() {
+        // see `tests/fixtures/make_fixtures.py`
+        let file = include_bytes!("../../tests/fixtures/keepassxc-2.7.kdbx");
+        let export = parse(file, "passman").unwrap();
+
+        let names: Vec<_> = export
+            .entries
+            .iter()
+            .map(|entry| entry.name.as_str())
+            .collect();
+        assert_eq!(names, vec!["Email", "Bank"]);
+        assert_eq!(export.skipped[0].name, "Old card");
+        let mail = &export.entries[0];
+        assert_eq!(mail.username.as_deref(), Some("me@example.com"));
+        assert!(mail.otp.is_some());
+        assert!(mail
+            .custom_fields
+            .iter()
+            .any(|field| field.label == "PIN" && field.value == "0000"));
+        let bank = &export.entries[1];
+        assert_eq!(bank.folder, vec!["Banking"]);
+        assert_eq!(bank.history[0].value, "hunter2");
+    }
+}
@@ -0,0 +1,1020 @@
+// KeePass databases (KDBX 4), so passwords can move to and from KeePass and KeePassXC.
+// this is only the file format, `import::keepass` and `export::to_kdbx` map it to and from passwords.
+// the format is described at https://keepass.info/help/kb/kdbx_4.html
+//
+// a file is: an unencrypted header (ciphers, seeds, KDF parameters), its SHA-256 and HMAC,
+// then the encrypted (and usually gzipped) payload, split into HMAC'd blocks.
+// the payload is an inner header (the attachments, and the key of the stream that protects sensitive values) followed by the XML.
+
+mod xml;
+
+use std::io::{Read, Write};
+
+use aes_gcm::aes::Aes256;
+use cbc::cipher::{
+    block_padding::Pkcs7, generic_array::GenericArray, BlockDecryptMut, BlockEncrypt,
+    BlockEncryptMut, KeyInit, KeyIvInit, StreamCipher,
+};
+use flate2::{read::GzDecoder, write::GzEncoder, Compression};
+use hex_literal::hex;
+use hmac::{Hmac, Mac};
+use rand::{rngs::OsRng, RngCore};
+use sha2::{Digest, Sha256, Sha512};
+
+use crate::{error::BackendError, import::MAX_ARGON2_MEMORY_KIB};
+
+/// A KeePass database.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Database {
+    pub name: String,
+    pub root: Group,
+    /// The group deleted entries are moved to, if the recycle bin is on.
+    pub recycle_bin: Option<[u8; 16]>,
+}
+
+/// A group (folder) of entries.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Group {
+    pub uuid: [u8; 16],
+    pub name: String,
+    pub notes: String,
+    pub times: Times,
+    pub groups: Vec<Group>,
+    pub entries: Vec<Entry>,
+}
+
+/// An entry, KeePass' password.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Entry {
+    pub uuid: [u8; 16],
+    pub times: Times,
+    /// Every field, the standard ones (`Title`, `UserName`, `Password`, `URL`, `Notes`) included, in order.
+    pub strings: Vec<EntryString>,
+    pub attachments: Vec<Attachment>,
+    /// Previous versions of the entry, oldest first. They don't have a history of their own.
+    pub history: Vec<Entry>,
+}
+
+impl Entry {
+    /// The value of a field, if the entry has it.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.strings
+            .iter()
+            .find(|string| string.key == key)
+            .map(|string| string.value.as_str())
+    }
+}
+
+/// A field of an entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EntryString {
+    pub key: String,
+    pub value: String,
+    /// Whether the value is sensitive. KeePass encrypts these a second time in the file, and hides them.
+    pub protected: bool,
+}
+
+/// A file attached to an entry.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Attachment {
+    pub name: String,
+    pub data: Vec<u8>,
+}
+
+/// When something was created, changed and used, in seconds since the Unix epoch.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Times {
+    pub created: Option<i64>,
+    pub modified: Option<i64>,
+    pub accessed: Option<i64>,
+}
+
+/// The cipher the payload is encrypted with.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Cipher {
+    Aes256,
+    #[default]
+    ChaCha20,
+}
+
+/// How the key is derived from the password.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum Kdf {
+    /// The original KDF: AES, over and over.
+    AesKdf { rounds: u64 },
+    Argon2d {
+        memory_kib: u32,
+        iterations: u32,
+        parallelism: u32,
+    },
+    Argon2id {
+        memory_kib: u32,
+        iterations: u32,
+        parallelism: u32,
+    },
+}
+
+impl Default for Kdf {
+    // KeePassXC's defaults
+    fn default() -> Self {
+        Kdf::Argon2id {
+            memory_kib: 64 * 1024,
+            iterations: 10,
+            parallelism: 2,
+        }
+    }
+}
+
+/// How a database is encrypted.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub struct KdbxSettings {
+    pub cipher: Cipher,
+    pub kdf: Kdf,
+}
+
+fn invalid(message: impl Into<String>) -> BackendError {
+    BackendError::InvalidImport(format!("not a KeePass database: {}", message.into()))
+}
+
+const SIGNATURE_1: u32 = 0x9AA2_D903;
+const SIGNATURE_2: u32 = 0xB54B_FB67;
+// the minor version comes first
+const VERSION: u32 = 0x0004_0000;
+
+const AES256_UUID: [u8; 16] = hex!("31c1f2e6bf714350be5805216afc5aff");
+const CHACHA20_UUID: [u8; 16] = hex!("d6038a2b8b6f4cb5a524339a31dbb59a");
+const AES_KDF_UUID: [u8; 16] = hex!("c9d9f39a628a4460bf740d08c18a4fea");
+const ARGON2D_UUID: [u8; 16] = hex!("ef636ddf8c29444b91f7a9a403e30a0c");
+const ARGON2ID_UUID: [u8; 16] = hex!("9e298b1956db4773b23dfc3ec6f0a1e6");
+
+// outer header fields
+const END_OF_HEADER: u8 = 0;
+const CIPHER_ID: u8 = 2;
+const COMPRESSION_FLAGS: u8 = 3;
+const MASTER_SEED: u8 = 4;
+const ENCRYPTION_IV: u8 = 7;
+const KDF_PARAMETERS: u8 = 11;
+
+// inner header fields
+const INNER_STREAM_ID: u8 = 1;
+const INNER_STREAM_KEY: u8 = 2;
+const BINARY: u8 = 3;
+
+const SALSA20_STREAM: u32 = 2;
+const CHACHA20_STREAM: u32 = 3;
+
+const BLOCK_SIZE: usize = 1024 * 1024;
+
+// the KDF runs before the password can be checked, so a file can't be allowed to ask for more than this
+const MAX_AES_KDF_ROUNDS: u64 = 1_000_000_000;
+// KeePassXC picks the iterations by timing a second of work, which stays well under this
+const MAX_ARGON2_ITERATIONS: u32 = 100;
+const MAX_ARGON2_PARALLELISM: u32 = 64;
+// the most a compressed payload may decompress to
+const MAX_PAYLOAD_SIZE: u64 = 512 * 1024 * 1024;
+
+// reads a file front to back
+struct Bytes<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Bytes<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Bytes { data, position: 0 }
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8], BackendError> {
+        let end = self
+            .position
+            .checked_add(length)
+            .filter(|end| *end <= self.data.len())
+            .ok_or_else(|| invalid("the file is truncated"))?;
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, BackendError> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, BackendError> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    // a field is a type, a length, and that many bytes
+    fn field(&mut self) -> Result<(u8, &'a [u8]), BackendError> {
+        let id = self.u8()?;
+        let length = self.u32()? as usize;
+        Ok((id, self.take(length)?))
+    }
+}
+
+fn write_field(out: &mut Vec<u8>, id: u8, data: &[u8]) {
+    out.push(id);
+    out.extend_from_slice(&(data.len() as u32).to_le_bytes());
+    out.extend_from_slice(data);
+}
+
+// the KDF parameters are a "variant dictionary", a little typed key-value format
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Variant {
+    U32(u32),
+    U64(u64),
+    Bool(bool),
+    I32(i32),
+    I64(i64),
+    String(String),
+    Bytes(Vec<u8>),
+}
+
+const VARIANT_DICTIONARY_VERSION: u16 = 0x0100;
+
+fn read_variants(data: &[u8]) -> Result<Vec<(String, Variant)>, BackendError> {
+    let mut bytes = Bytes::new(data);
+    let version = u16::from_le_bytes(bytes.take(2)?.try_into().unwrap());
+    // only the major version matters
+    if version >> 8 != VARIANT_DICTIONARY_VERSION >> 8 {
+        return Err(invalid("unsupported KDF parameters"));
+    }
+    let mut variants = Vec::new();
+    loop {
+        let kind = bytes.u8()?;
+        if kind == 0 {
+            return Ok(variants);
+        }
+        let name_length = bytes.u32()? as usize;
+        let name = String::from_utf8_lossy(bytes.take(name_length)?).into_owned();
+        let value_length = bytes.u32()? as usize;
+        let value = bytes.take(value_length)?;
+        let number = |length: usize| {
+            (value.len() == length)
+                .then_some(value)
+                .ok_or_else(|| invalid("malformed KDF parameters"))
+        };
+        let value = match kind {
+            0x04 => Variant::U32(u32::from_le_bytes(number(4)?.try_into().unwrap())),
+            0x05 => Variant::U64(u64::from_le_bytes(number(8)?.try_into().unwrap())),
+            0x08 => Variant::Bool(number(1)?[0] != 0),
+            0x0C => Variant::I32(i32::from_le_bytes(number(4)?.try_into().unwrap())),
+            0x0D => Variant::I64(i64::from_le_bytes(number(8)?.try_into().unwrap())),
+            0x18 => Variant::String(String::from_utf8_lossy(value).into_owned()),
+            0x42 => Variant::Bytes(value.to_vec()),
+            _ => return Err(invalid("malformed KDF parameters")),
+        };
+        variants.push((name, value));
+    }
+}
+
+fn write_variants(variants: &[(&str, Variant)]) -> Vec<u8> {
+    let mut out = VARIANT_DICTIONARY_VERSION.to_le_bytes().to_vec();
+    for (name, value) in variants {
+        let (kind, value) = match value {
+            Variant::U32(value) => (0x04, value.to_le_bytes().to_vec()),
+            Variant::U64(value) => (0x05, value.to_le_bytes().to_vec()),
+            Variant::Bool(value) => (0x08, vec![*value as u8]),
+            Variant::I32(value) => (0x0C, value.to_le_bytes().to_vec()),
+            Variant::I64(value) => (0x0D, value.to_le_bytes().to_vec()),
+            Variant::String(value) => (0x18, value.as_bytes().to_vec()),
+            Variant::Bytes(value) => (0x42, value.clone()),
+        };
+        out.push(kind);
+        out.extend_from_slice(&(name.len() as u32).to_le_bytes());
+        out.extend_from_slice(name.as_bytes());
+        out.extend_from_slice(&(value.len() as u32).to_le_bytes());
+        out.extend_from_slice(&value);
+    }
+    out.push(0);
+    out
+}
+
+// the KDF, and the seed (or salt) it's used with
+fn read_kdf(data: &[u8]) -> Result<(Kdf, Vec<u8>), BackendError> {
+    let variants = read_variants(data)?;
+    let get = |name: &str| {
+        variants
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value)
+            .ok_or_else(|| invalid(format!("the KDF parameter `{}` is missing", name)))
+    };
+    let number = |name: &str| match get(name)? {
+        Variant::U32(value) => Ok(*value as u64),
+        Variant::U64(value) => Ok(*value),
+        _ => Err(invalid(format!(
+            "the KDF parameter `{}` isn't a number",
+            name
+        ))),
+    };
+    let bytes = |name: &str| match get(name)? {
+        Variant::Bytes(value) => Ok(value.clone()),
+        _ => Err(invalid(format!("the KDF parameter `{}` isn't bytes", name))),
+    };
+    let uuid = bytes("$UUID")?;
+    let small_number = |name: &str| {
+        u32::try_from(number(name)?)
+            .map_err(|_| invalid(format!("the KDF parameter `{}` is too large", name)))
+    };
+    let argon2 = || -> Result<_, BackendError> {
+        // the file has the memory in bytes
+        let memory_kib = u32::try_from(number("M")? / 1024)
+            .ok()
+            .filter(|memory_kib| *memory_kib <= MAX_ARGON2_MEMORY_KIB)
+            .ok_or_else(|| invalid("the KDF asks for too much memory"))?;
+        let (iterations, parallelism) = (small_number("I")?, small_number("P")?);
+        if iterations > MAX_ARGON2_ITERATIONS || parallelism > MAX_ARGON2_PARALLELISM {
+            return Err(invalid("the KDF asks for too much work"));
+        }
+        Ok((memory_kib, iterations, parallelism))
+    };
+    let kdf = match uuid.as_slice() {
+        id if id == AES_KDF_UUID => {
+            let rounds = number("R")?;
+            if rounds > MAX_AES_KDF_ROUNDS {
+                return Err(invalid("the KDF asks for too many rounds"));
+            }
+            return Ok((Kdf::AesKdf { rounds }, bytes("S")?));
+        }
+        id if id == ARGON2D_UUID => {
+            let (memory_kib, iterations, parallelism) = argon2()?;
+            Kdf::Argon2d {
+                memory_kib,
+                iterations,
+                parallelism,
+            }
+        }
+        id if id == ARGON2ID_UUID => {
+            let (memory_kib, iterations, parallelism) = argon2()?;
+            Kdf::Argon2id {
+                memory_kib,
+                iterations,
+                parallelism,
+            }
+        }
+        _ => return Err(BackendError::UnsupportedKdf(hex::encode(uuid))),
+    };
+    if number("V")? != 0x13 {
+        return Err(BackendError::UnsupportedKdf(
+            "Argon2 before version 1.3".to_string(),
+        ));
+    }
+    Ok((kdf, bytes("S")?))
+}
+
+fn write_kdf(kdf: &Kdf, seed: &[u8]) -> Vec<u8> {
+    let argon2 = |uuid: [u8; 16], memory_kib: u32, iterations: u32, parallelism: u32| {
+        write_variants(&[
+            ("$UUID", Variant::Bytes(uuid.to_vec())),
+            ("S", Variant::Bytes(seed.to_vec())),
+            ("P", Variant::U32(parallelism)),
+            ("M", Variant::U64(memory_kib as u64 * 1024)),
+            ("I", Variant::U64(iterations as u64)),
+            ("V", Variant::U32(0x13)),
+        ])
+    };
+    match *kdf {
+        Kdf::AesKdf { rounds } => write_variants(&[
+            ("$UUID", Variant::Bytes(AES_KDF_UUID.to_vec())),
+            ("R", Variant::U64(rounds)),
+            ("S", Variant::Bytes(seed.to_vec())),
+        ]),
+        Kdf::Argon2d {
+            memory_kib,
+            iterations,
+            parallelism,
+        } => argon2(ARGON2D_UUID, memory_kib, iterations, parallelism),
+        Kdf::Argon2id {
+            memory_kib,
+            iterations,
+            parallelism,
+        } => argon2(ARGON2ID_UUID, memory_kib, iterations, parallelism),
+    }
+}
+
+// the "composite key" is made of every key the database is protected with. we only support a password
+fn transform_key(password: &str, kdf: &Kdf, seed: &[u8]) -> Result<[u8; 32], BackendError> {
+    let composite: [u8; 32] = Sha256::digest(Sha256::digest(password.as_bytes())).into();
+    let argon2 = |algorithm, memory_kib, iterations, parallelism| {
+        let params = argon2::Params::new(memory_kib, iterations, parallelism, Some(32))
+            .map_err(|e| invalid(format!("invalid Argon2 parameters: {}", e)))?;
+        let mut key = [0u8; 32];
+        argon2::Argon2::new(algorithm, argon2::Version::V0x13, params)
+            .hash_password_into(&composite, seed, &mut key)?;
+        Ok(key)
+    };
+    match *kdf {
+        Kdf::AesKdf { rounds } => {
+            let cipher =
+                Aes256::new_from_slice(seed).map_err(|_| invalid("the KDF seed isn't 32 bytes"))?;
+            let mut blocks = [
+                GenericArray::clone_from_slice(&composite[..16]),
+                GenericArray::clone_from_slice(&composite[16..]),
+            ];
+            for _ in 0..rounds {
+                cipher.encrypt_blocks(&mut blocks);
+            }
+            Ok(Sha256::digest([blocks[0], blocks[1]].concat()).into())
+        }
+        Kdf::Argon2d {
+            memory_kib,
+            iterations,
+            parallelism,
+        } => argon2(
+            argon2::Algorithm::Argon2d,
+            memory_kib,
+            iterations,
+            parallelism,
+        ),
+        Kdf::Argon2id {
+            memory_kib,
+            iterations,
+            parallelism,
+        } => argon2(
+            argon2::Algorithm::Argon2id,
+            memory_kib,
+            iterations,
+            parallelism,
+        ),
+    }
+}
+
+// each block (and the header, as block u64::MAX) has its own HMAC key
+fn block_hmac(hmac_key: &[u8], index: u64, parts: &[&[u8]]) -> Hmac<Sha256> {
+    let block_key = Sha512::new()
+        .chain_update(index.to_le_bytes())
+        .chain_update(hmac_key)
+        .finalize();
+    let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(&block_key).unwrap();
+    for part in parts {
+        mac.update(part);
+    }
+    mac
+}
+
+/// The stream cipher sensitive values (see `EntryString::protected`) are encrypted with inside the XML.
+/// Every protected value takes the next bytes of the keystream, in the order they appear in the document.
+pub(crate) enum InnerStream {
+    ChaCha20(chacha20::ChaCha20),
+    Salsa20(salsa20::Salsa20),
+}
+
+impl InnerStream {
+    fn new(id: u32, key: &[u8]) -> Result<Self, BackendError> {
+        match id {
+            CHACHA20_STREAM => {
+                let hash = Sha512::digest(key);
+                Ok(InnerStream::ChaCha20(chacha20::ChaCha20::new(
+                    GenericArray::from_slice(&hash[..32]),
+                    GenericArray::from_slice(&hash[32..44]),
+                )))
+            }
+            SALSA20_STREAM => Ok(InnerStream::Salsa20(salsa20::Salsa20::new(
+                GenericArray::from_slice(&Sha256::digest(key)),
+                GenericArray::from_slice(&hex!("e830094b97205d2a")),
+            ))),
+            _ => Err(invalid(format!("unsupported inner stream {}", id))),
+        }
+    }
+
+    fn apply(&mut self, data: &mut [u8]) {
+        match self {
+            InnerStream::ChaCha20(cipher) => cipher.apply_keystream(data),
+            InnerStream::Salsa20(cipher) => cipher.apply_keystream(data),
+        }
+    }
+}
+
+/// Reads and decrypts a KDBX 4 database. Only databases protected by a password alone (no key file) can be read.
+/// Fails with `BackendError::IncorrectImportPassword` if the password is wrong,
+/// or with `BackendError::InvalidImport` if the file isn't a KDBX 4 database, or is corrupted.
+/// # Arguments
+///
+/// - `data` - the contents of the file.
+/// - `password` - the database's password.
+///
+pub fn read(data: &[u8], password: &str) -> Result<Database, BackendError> {
+    let mut bytes = Bytes::new(data);
+    if bytes.u32()? != SIGNATURE_1 || bytes.u32()? != SIGNATURE_2 {
+        return Err(invalid("wrong signature"));
+    }
+    let version = bytes.u32()?;
+    if version >> 16 != VERSION >> 16 {
+        return Err(invalid(format!(
+            "only version 4 is supported, this is version {}",
+            version >> 16
+        )));
+    }
+
+    let mut cipher = None;
+    let mut compressed = false;
+    let mut master_seed = None;
+    let mut iv = None;
+    let mut kdf = None;
+    loop {
+        let (id, value) = bytes.field()?;
+        match id {
+            END_OF_HEADER => break,
+            CIPHER_ID => {
+                cipher = Some(match value {
+                    id if id == AES256_UUID => Cipher::Aes256,
+                    id if id == CHACHA20_UUID => Cipher::ChaCha20,
+                    _ => return Err(invalid("unsupported cipher")),
+                })
+            }
+            COMPRESSION_FLAGS => compressed = value.first().is_some_and(|flags| *flags != 0),
+            MASTER_SEED => master_seed = Some(value),
+            ENCRYPTION_IV => iv = Some(value),
+            KDF_PARAMETERS => kdf = Some(read_kdf(value)?),
+            // e.g. public custom data
+            _ => {}
+        }
+    }
+    let header = &data[..bytes.position];
+    let cipher = cipher.ok_or_else(|| invalid("the cipher is missing"))?;
+    let master_seed = master_seed.ok_or_else(|| invalid("the master seed is missing"))?;
+    let iv = iv.ok_or_else(|| invalid("the IV is missing"))?;
+    let (kdf, seed) = kdf.ok_or_else(|| invalid("the KDF parameters are missing"))?;
+
+    if bytes.take(32)? != Sha256::digest(header).as_slice() {
+        return Err(invalid("the header is corrupted"));
+    }
+    let header_hmac = bytes.take(32)?;
+    let transformed = transform_key(password, &kdf, &seed)?;
+    let hmac_key = Sha512::new()
+        .chain_update(master_seed)
+        .chain_update(transformed)
+        .chain_update([1])
+        .finalize();
+    // this is where a wrong password shows
+    block_hmac(&hmac_key, u64::MAX, &[header])
+        .verify_slice(header_hmac)
+        .map_err(|_| BackendError::IncorrectImportPassword)?;
+
+    let mut payload = Vec::new();
+    for index in 0.. {
+        let hmac = bytes.take(32)?;
+        let length = bytes.take(4)?;
+        let block = bytes.take(u32::from_le_bytes(length.try_into().unwrap()) as usize)?;
+        block_hmac(&hmac_key, index, &[&index.to_le_bytes(), length, block])
+            .verify_slice(hmac)
+            .map_err(|_| invalid(format!("block {} is corrupted", index)))?;
+        if block.is_empty() {
+            break;
+        }
+        payload.extend_from_slice(block);
+    }
+
+    let key = Sha256::new()
+        .chain_update(master_seed)
+        .chain_update(transformed)
+        .finalize();
+    let payload = match cipher {
+        Cipher::Aes256 => cbc::Decryptor::<Aes256>::new_from_slices(&key, iv)
+            .map_err(|_| invalid("the IV isn't 16 bytes"))?
+            .decrypt_padded_vec_mut::<Pkcs7>(&payload)
+            .map_err(|_| invalid("the payload is corrupted"))?,
+        Cipher::ChaCha20 => {
+            let mut cipher = chacha20::ChaCha20::new_from_slices(&key, iv)
+                .map_err(|_| invalid("the IV isn't 12 bytes"))?;
+            cipher.apply_keystream(&mut payload);
+            payload
+        }
+    };
+    let payload = match compressed {
+        true => {
+            let mut decompressed = Vec::new();
+            // one byte over the limit is enough to tell it's too large
+            GzDecoder::new(payload.as_slice())
+                .take(MAX_PAYLOAD_SIZE + 1)
+                .read_to_end(&mut decompressed)
+                .map_err(|_| invalid("the payload is corrupted"))?;
+            if decompressed.len() as u64 > MAX_PAYLOAD_SIZE {
+                return Err(invalid("the payload is too large"));
+            }
+            decompressed
+        }
+        false => payload,
+    };
+
+    let mut bytes = Bytes::new(&payload);
+    let mut stream = None;
+    let mut stream_key = None;
+    let mut attachments = Vec::new();
+    loop {
+        let (id, value) = bytes.field()?;
+        match id {
+            END_OF_HEADER => break,
+            INNER_STREAM_ID if value.len() == 4 => {
+                stream = Some(u32::from_le_bytes(value.try_into().unwrap()))
+            }
+            INNER_STREAM_KEY => stream_key = Some(value),
+            // the first byte is flags, whether it's protected doesn't change anything here
+            BINARY if !value.is_empty() => attachments.push(value[1..].to_vec()),
+            _ => {}
+        }
+    }
+    let mut stream = InnerStream::new(
+        stream.ok_or_else(|| invalid("the inner stream is missing"))?,
+        stream_key.ok_or_else(|| invalid("the inner stream key is missing"))?,
+    )?;
+    xml::read(&payload[bytes.position..], &mut stream, &attachments)
+}
+
+/// Encrypts and writes a database as KDBX 4, gzipped.
+/// # Arguments
+///
+/// - `database` - the database to write.
+/// - `password` - the password to protect it with.
+/// - `settings` - the cipher and KDF to use.
+///
+pub fn write(
+    database: &Database,
+    password: &str,
+    settings: &KdbxSettings,
+) -> Result<Vec<u8>, BackendError> {
+    let random = |length: usize| {
+        let mut bytes = vec![0u8; length];
+        OsRng.fill_bytes(&mut bytes);
+        bytes
+    };
+    let master_seed = random(32);
+    let seed = random(32);
+    let (cipher_id, iv) = match settings.cipher {
+        Cipher::Aes256 => (AES256_UUID, random(16)),
+        Cipher::ChaCha20 => (CHACHA20_UUID, random(12)),
+    };
+
+    let mut out = Vec::new();
+    out.extend_from_slice(&SIGNATURE_1.to_le_bytes());
+    out.extend_from_slice(&SIGNATURE_2.to_le_bytes());
+    out.extend_from_slice(&VERSION.to_le_bytes());
+    write_field(&mut out, CIPHER_ID, &cipher_id);
+    write_field(&mut out, COMPRESSION_FLAGS, &1u32.to_le_bytes());
+    write_field(&mut out, MASTER_SEED, &master_seed);
+    write_field(&mut out, ENCRYPTION_IV, &iv);
+    write_field(&mut out, KDF_PARAMETERS, &write_kdf(&settings.kdf, &seed));
+    write_field(&mut out, END_OF_HEADER, b"\r\n\r\n");
+
+    let transformed = transform_key(password, &settings.kdf, &seed)?;
+    let hmac_key = Sha512::new()
+        .chain_update(&master_seed)
+        .chain_update(transformed)
+        .chain_update([1])
+        .finalize();
+    let header_hash = Sha256::digest(&out);
+    let header_hmac = block_hmac(&hmac_key, u64::MAX, &[&out])
+        .finalize()
+        .into_bytes();
+    out.extend_from_slice(&header_hash);
+    out.extend_from_slice(&header_hmac);
+
+    let stream_key = random(64);
+    let mut stream = InnerStream::new(CHACHA20_STREAM, &stream_key)?;
+    let (document, attachments) = xml::write(database, &mut stream)?;
+    let mut payload = Vec::new();
+    write_field(
+        &mut payload,
+        INNER_STREAM_ID,
+        &CHACHA20_STREAM.to_le_bytes(),
+    );
+    write_field(&mut payload, INNER_STREAM_KEY, &stream_key);
+    for attachment in attachments {
+        write_field(
+            &mut payload,
+            BINARY,
+            &[&[0u8], attachment.as_slice()].concat(),
+        );
+    }
+    write_field(&mut payload, END_OF_HEADER, &[]);
+    payload.extend_from_slice(&document);
+
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(&payload)
+        .and_then(|_| encoder.finish())
+        .map(|compressed| payload = compressed)
+        .map_err(|e| invalid(format!("compressing failed: {}", e)))?;
+
+    let key = Sha256::new()
+        .chain_update(&master_seed)
+        .chain_update(transformed)
+        .finalize();
+    let payload = match settings.cipher {
+        Cipher::Aes256 => cbc::Encryptor::<Aes256>::new_from_slices(&key, &iv)
+            .unwrap()
+            .encrypt_padded_vec_mut::<Pkcs7>(&payload),
+        Cipher::ChaCha20 => {
+            let mut cipher = chacha20::ChaCha20::new_from_slices(&key, &iv).unwrap();
+            cipher.apply_keystream(&mut payload);
+            payload
+        }
+    };
+
+    // the last block is an empty one
+    let blocks = payload.chunks(BLOCK_SIZE).chain(std::iter::once(&[][..]));
+    for (index, block) in blocks.enumerate() {
+        let index = index as u64;
+        let length = (block.len() as u32).to_le_bytes();
+        let hmac = block_hmac(&hmac_key, index, &[&index.to_le_bytes(), &length, block]);
+        out.extend_from_slice(&hmac.finalize().into_bytes());
+        out.extend_from_slice(&length);
+        out.extend_from_slice(block);
+    }
+    Ok(out)
+}
+
+/// A random UUID for a new group or entry.
+pub fn new_uuid() -> [u8; 16] {
+    let mut uuid = [0u8; 16];
+    OsRng.fill_bytes(&mut uuid);
+    uuid
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        new_uuid, read, write, Attachment, Cipher, Database, Entry, EntryString, Group,
+        KdbxSettings, Kdf, Times,
+    };
+    use crate::error::BackendError;
+
+    // written by `tests/fixtures/make_fixtures.py`, which follows the format description independently of this code.
+    // both have the same contents, see `check_fixture`, and the password "passman"
+    const AES_FIXTURE: &[u8] = include_bytes!("../tests/fixtures/aes-kdf-aes256.kdbx");
+    const ARGON2_FIXTURE: &[u8] = include_bytes!("../tests/fixtures/argon2id-chacha20.kdbx");
+    // laid out the way KeePassXC 2.7 saves a database (KDBX 4.1, Argon2d, its full Meta), by the same script
+    const KEEPASSXC_FIXTURE: &[u8] = include_bytes!("../tests/fixtures/keepassxc-2.7.kdbx");
+
+    fn string(key: &str, value: &str, protected: bool) -> EntryString {
+        EntryString {
+            key: key.to_string(),
+            value: value.to_string(),
+            protected,
+        }
+    }
+
+    fn check_fixture(database: &Database) {
+        assert_eq!(database.name, "fixture");
+        let root = &database.root;
+        assert_eq!(root.name, "Root");
+        assert_eq!(root.groups.len(), 1);
+        let work = &root.groups[0];
+        assert_eq!(work.name, "Work");
+
+        let entry = &work.entries[0];
+        assert_eq!(entry.get("Title"), Some("GitHub"));
+        assert_eq!(entry.get("UserName"), Some("me@example.com"));
+        assert_eq!(entry.get("Password"), Some("correct horse battery staple"));
+        assert_eq!(entry.get("URL"), Some("https://github.com"));
+        assert_eq!(entry.get("Notes"), Some("work account\nsecond line"));
+        assert_eq!(
+            entry.get("otp"),
+            Some("otpauth://totp/GitHub:me?secret=GEZDGNBVGY3TQOJQ&issuer=GitHub")
+        );
+        assert_eq!(entry.get("Recovery code"), Some("1234-5678"));
+        assert!(
+            entry
+                .strings
+                .iter()
+                .find(|s| s.key == "Password")
+                .unwrap()
+                .protected
+        );
+        assert_eq!(
+            entry.attachments,
+            vec![Attachment {
+                name: "hello.txt".to_string(),
+                data: b"hello world".to_vec(),
+            }]
+        );
+        assert_eq!(entry.times.created, Some(1_600_000_000));
+        assert_eq!(entry.times.modified, Some(1_700_000_000));
+        assert_eq!(entry.history.len(), 1);
+        assert_eq!(entry.history[0].get("Password"), Some("hunter2"));
+        assert_eq!(entry.history[0].times.modified, Some(1_650_000_000));
+
+        let entry = &root.entries[0];
+        assert_eq!(entry.get("Title"), Some("wifi"));
+        assert_eq!(entry.get("Password"), Some("p4ssw0rd <&>"));
+    }
+
+    #[test]
+    fn fixtures() {
+        check_fixture(&read(AES_FIXTURE, "passman").unwrap());
+        check_fixture(&read(ARGON2_FIXTURE, "passman").unwrap());
+        assert!(matches!(
+            read(AES_FIXTURE, "wrong"),
+            Err(BackendError::IncorrectImportPassword)
+        ));
+        assert!(matches!(
+            read(b"not a database", "passman"),
+            Err(BackendError::InvalidImport(_))
+        ));
+        // flipping a byte of the payload is caught by the block's HMAC
+        let mut corrupted = ARGON2_FIXTURE.to_vec();
+        let last = corrupted.len() - 50;
+        corrupted[last] ^= 1;
+        assert!(matches!(
+            read(&corrupted, "passman"),
+            Err(BackendError::InvalidImport(_))
+        ));
+    }
+
+    #[test]
+    fn keepassxc_fixture() {
+        let database = read(KEEPASSXC_FIXTURE, "passman").unwrap();
+        assert_eq!(database.name, "Passwords");
+        let root = &database.root;
+        assert_eq!(
+            root.groups
+                .iter()
+                .map(|g| g.name.as_str())
+                .collect::<Vec<_>>(),
+            vec!["Banking", "Recycle Bin"]
+        );
+        assert_eq!(database.recycle_bin, Some(root.groups[1].uuid));
+
+        // protected values are decrypted in document order, a custom one and the OTP included
+        let mail = &root.entries[0];
+        assert_eq!(mail.get("Title"), Some("Email"));
+        assert_eq!(mail.get("Password"), Some("p4ssw0rd <&>"));
+        assert_eq!(mail.get("PIN"), Some("0000"));
+        assert_eq!(mail.get("Notes"), Some(""));
+        assert!(mail
+            .get("otp")
+            .unwrap()
+            .starts_with("otpauth://totp/Email:"));
+
+        let bank = &root.groups[0].entries[0];
+        assert_eq!(bank.get("Password"), Some("correct horse battery staple"));
+        assert_eq!(bank.get("Notes"), Some("card in the drawer\nsecond line"));
+        assert_eq!(bank.attachments[0].name, "statement.txt");
+        assert_eq!(bank.attachments[0].data, b"hello world");
+        assert_eq!(bank.times.modified, Some(1_700_000_000));
+        assert_eq!(bank.history[0].get("Password"), Some("hunter2"));
+        assert_eq!(root.groups[1].entries[0].get("Password"), Some("old pin"));
+    }
+
+    // sets a UInt64 (or UInt32) KDF parameter of a fixture, i.e. its name and value in the header's variant dictionary
+    fn with_kdf_parameter(fixture: &[u8], name: &str, value: u64) -> Vec<u8> {
+        let find = |kind: u8, size: u32| {
+            let mut field = vec![kind];
+            field.extend_from_slice(&(name.len() as u32).to_le_bytes());
+            field.extend_from_slice(name.as_bytes());
+            field.extend_from_slice(&size.to_le_bytes());
+            fixture
+                .windows(field.len())
+                .position(|window| window == field)
+                .map(|at| at + field.len())
+        };
+        let mut patched = fixture.to_vec();
+        match find(0x05, 8) {
+            Some(at) => patched[at..at + 8].copy_from_slice(&value.to_le_bytes()),
+            None => {
+                let at = find(0x04, 4).unwrap();
+                patched[at..at + 4].copy_from_slice(&u32::try_from(value).unwrap().to_le_bytes());
+            }
+        }
+        patched
+    }
+
+    #[test]
+    fn kdf_limits() {
+        // these are refused before any work is done, rather than hanging (or running out of memory)
+        for (fixture, name, value) in [
+            (AES_FIXTURE, "R", u64::MAX),
+            (ARGON2_FIXTURE, "M", u64::MAX),
+            (ARGON2_FIXTURE, "I", u32::MAX as u64),
+            (ARGON2_FIXTURE, "I", 101),
+            (ARGON2_FIXTURE, "P", 65),
+        ] {
+            let patched = with_kdf_parameter(fixture, name, value);
+            assert!(matches!(
+                read(&patched, "passman"),
+                Err(BackendError::InvalidImport(message)) if message.contains("too")
+            ));
+        }
+        // 4 TiB truncated to 32 bits would have been 0
+        let patched = with_kdf_parameter(ARGON2_FIXTURE, "M", 1 << 42);
+        assert!(matches!(
+            read(&patched, "passman"),
+            Err(BackendError::InvalidImport(_))
+        ));
+    }
+
+    #[test]
+    fn nesting_limit() {
+        // a chain of groups, each inside the last
+        let nested = |depth| {
+            let mut group = Group::default();
+            for _ in 0..depth {
+                group = Group {
+                    groups: vec![group],
+                    ..Default::default()
+                };
+            }
+            Database {
+                root: group,
+                ..Default::default()
+            }
+        };
+        let settings = KdbxSettings {
+            cipher: Cipher::Aes256,
+            kdf: Kdf::AesKdf { rounds: 10 },
+        };
+        let file = write(&nested(100), "secret", &settings).unwrap();
+        assert_eq!(read(&file, "secret").unwrap(), nested(100));
+        // this deep, reading it is refused before anything recurses
+        let file = write(&nested(300), "secret", &settings).unwrap();
+        assert!(matches!(
+            read(&file, "secret"),
+            Err(BackendError::InvalidImport(message)) if message.contains("nested")
+        ));
+    }
+
+    #[test]
+    fn round_trip() {
+        let entry = Entry {
+            uuid: new_uuid(),
+            times: Times {
+                created: Some(1_600_000_000),
+                modified: Some(1_700_000_000),
+                accessed: None,
+            },
+            strings: vec![
+                string("Title", "GitHub", false),
+                string("UserName", "me", false),
+                string("Password", "hunter2", true),
+                string("Notes", "  whitespace\n\tmatters  ", false),
+                string("PIN", "1234", true),
+            ],
+            attachments: vec![Attachment {
+                name: "key.bin".to_string(),
+                data: (0..=255).collect(),
+            }],
+            history: vec![Entry {
+                uuid: [1; 16],
+                strings: vec![string("Password", "old", true)],
+                ..Default::default()
+            }],
+        };
+        let database = Database {
+            name: "vault".to_string(),
+            root: Group {
+                uuid: new_uuid(),
+                name: "Root".to_string(),
+                groups: vec![Group {
+                    uuid: new_uuid(),
+                    name: "Work & play".to_string(),
+                    notes: "<notes>".to_string(),
+                    entries: vec![entry.clone()],
+                    ..Default::default()
+                }],
+                entries: vec![entry],
+                ..Default::default()
+            },
+            recycle_bin: None,
+        };
+
+        // with small KDF costs, so the test is quick
+        for settings in [
+            KdbxSettings {
+                cipher: Cipher::Aes256,
+                kdf: Kdf::AesKdf { rounds: 1000 },
+            },
+            KdbxSettings {
+                cipher: Cipher::ChaCha20,
+                kdf: Kdf::Argon2id {
+                    memory_kib: 1024,
+                    iterations: 2,
+                    parallelism: 2,
+                },
+            },
+            KdbxSettings {
+                cipher: Cipher::ChaCha20,
+                kdf: Kdf::Argon2d {
+                    memory_kib: 1024,
+                    iterations: 2,
+                    parallelism: 1,
+                },
+            },
+        ] {
+            let file = write(&database, "secret", &settings).unwrap();
+            assert_eq!(read(&file, "secret").unwrap(), database);
+            assert!(matches!(
+                read(&file, "Secret"),
+                Err(BackendError::IncorrectImportPassword)
+            ));
+        }
+
+        // and the fixtures survive being written back out
+        let fixture = read(ARGON2_FIXTURE, "passman").unwrap();
+        let settings = KdbxSettings {
+            cipher: Cipher::Aes256,
+            kdf: Kdf::AesKdf { rounds: 10 },
+        };
+        let file = write(&fixture, "passman", &settings).unwrap();
+        check_fixture(&read(&file, "passman").unwrap());
+    }
+}
@@ -0,0 +1,441 @@
+// the XML inside a KDBX file. it's parsed into a small tree of `Node`s first, and mapped to a `Database` from there,
+// so protected values can be decrypted in document order before anything looks at them
+
+use data_encoding::BASE64;
+use quick_xml::{
+    events::{BytesDecl, BytesEnd, BytesStart, BytesText, Event},
+    Reader, Writer,
+};
+
+use super::{invalid, Attachment, Database, Entry, EntryString, Group, InnerStream, Times};
+use crate::error::BackendError;
+
+#[derive(Debug, Clone, Default)]
+struct Node {
+    name: String,
+    attributes: Vec<(String, String)>,
+    text: String,
+    children: Vec<Node>,
+}
+
+impl Node {
+    fn new(name: &str) -> Self {
+        Node {
+            name: name.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn text(name: &str, text: impl Into<String>) -> Self {
+        Node {
+            text: text.into(),
+            ..Node::new(name)
+        }
+    }
+
+    fn with(mut self, children: impl IntoIterator<Item = Node>) -> Self {
+        self.children.extend(children);
+        self
+    }
+
+    fn child(&self, name: &str) -> Option<&Node> {
+        self.children.iter().find(|child| child.name == name)
+    }
+
+    fn children<'a>(&'a self, name: &'a str) -> impl Iterator<Item = &'a Node> {
+        self.children.iter().filter(move |child| child.name == name)
+    }
+
+    fn child_text(&self, name: &str) -> String {
+        self.child(name)
+            .map(|child| child.text.clone())
+            .unwrap_or_default()
+    }
+
+    fn attribute(&self, name: &str) -> Option<&str> {
+        self.attributes
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+// the tree is walked recursively (`unprotect`, `read_group`, `read_entry`), so a document nested deeper than this
+// could overflow the stack. real ones are a few levels deeper than their folders
+const MAX_DEPTH: usize = 256;
+
+fn parse(document: &[u8]) -> Result<Node, BackendError> {
+    let xml_error = |e: quick_xml::Error| invalid(format!("the XML is malformed: {}", e));
+    let element = |start: &BytesStart| -> Result<Node, BackendError> {
+        let mut node = Node::new(&String::from_utf8_lossy(start.name().as_ref()));
+        for attribute in start.attributes() {
+            let attribute = attribute.map_err(|e| xml_error(e.into()))?;
+            node.attributes.push((
+                String::from_utf8_lossy(attribute.key.as_ref()).into_owned(),
+                attribute.unescape_value().map_err(xml_error)?.into_owned(),
+            ));
+        }
+        Ok(node)
+    };
+
+    let mut reader = Reader::from_reader(document);
+    let mut buffer = Vec::new();
+    // the elements that are open, innermost last. the bottom one collects the document element
+    let mut stack = vec![Node::default()];
+    loop {
+        match reader.read_event_into(&mut buffer).map_err(xml_error)? {
+            Event::Start(start) => {
+                // the bottom of the stack isn't an element
+                if stack.len() > MAX_DEPTH {
+                    return Err(invalid("the XML is nested too deeply"));
+                }
+                stack.push(element(&start)?);
+            }
+            Event::Empty(start) => {
+                let node = element(&start)?;
+                stack.last_mut().unwrap().children.push(node);
+            }
+            Event::End(_) => {
+                let node = stack.pop().unwrap();
+                stack
+                    .last_mut()
+                    .ok_or_else(|| invalid("the XML is malformed"))?
+                    .children
+                    .push(node);
+            }
+            Event::Text(text) => {
+                let text = text.unescape().map_err(xml_error)?;
+                stack.last_mut().unwrap().text.push_str(&text);
+            }
+            Event::CData(data) => {
+                let data = data.into_inner();
+                stack
+                    .last_mut()
+                    .unwrap()
+                    .text
+                    .push_str(&String::from_utf8_lossy(&data));
+            }
+            Event::Eof => break,
+            // declarations, comments...
+            _ => {}
+        }
+        buffer.clear();
+    }
+    if stack.len() != 1 {
+        return Err(invalid("the XML is malformed"));
+    }
+    stack
+        .pop()
+        .unwrap()
+        .children
+        .into_iter()
+        .find(|node| node.name == "KeePassFile")
+        .ok_or_else(|| invalid("the XML isn't a KeePass file"))
+}
+
+fn serialize(root: &Node) -> Result<Vec<u8>, BackendError> {
+    fn write_node(writer: &mut Writer<Vec<u8>>, node: &Node) -> Result<(), quick_xml::Error> {
+        let start = BytesStart::new(node.name.as_str()).with_attributes(
+            node.attributes
+                .iter()
+                .map(|(key, value)| (key.as_str(), value.as_str())),
+        );
+        if node.children.is_empty() && node.text.is_empty() {
+            return writer.write_event(Event::Empty(start));
+        }
+        writer.write_event(Event::Start(start))?;
+        if !node.text.is_empty() {
+            writer.write_event(Event::Text(BytesText::new(&node.text)))?;
+        }
+        for child in &node.children {
+            write_node(writer, child)?;
+        }
+        writer.write_event(Event::End(BytesEnd::new(node.name.as_str())))
+    }
+
+    let mut writer = Writer::new_with_indent(Vec::new(), b'\t', 1);
+    writer
+        .write_event(Event::Decl(BytesDecl::new(
+            "1.0",
+            Some("utf-8"),
+            Some("yes"),
+        )))
+        .and_then(|_| write_node(&mut writer, root))
+        .map_err(|e| invalid(format!("writing the XML failed: {}", e)))?;
+    Ok(writer.into_inner())
+}
+
+fn is_protected(node: &Node) -> bool {
+    node.name == "Value" && node.attribute("Protected") == Some("True")
+}
+
+// decrypts every protected value, in document order
+fn unprotect(node: &mut Node, stream: &mut InnerStream) -> Result<(), BackendError> {
+    if is_protected(node) {
+        let mut value = BASE64
+            .decode(node.text.trim().as_bytes())
+            .map_err(|_| invalid("a protected value isn't valid base64"))?;
+        stream.apply(&mut value);
+        node.text =
+            String::from_utf8(value).map_err(|_| invalid("a protected value isn't text"))?;
+    }
+    for child in &mut node.children {
+        unprotect(child, stream)?;
+    }
+    Ok(())
+}
+
+// the reverse of `unprotect`
+fn protect(node: &mut Node, stream: &mut InnerStream) {
+    if is_protected(node) {
+        let mut value = std::mem::take(&mut node.text).into_bytes();
+        stream.apply(&mut value);
+        node.text = BASE64.encode(&value);
+    }
+    for child in &mut node.children {
+        protect(child, stream);
+    }
+}
+
+// KDBX 4 stores times as base64, little endian seconds since 0001-01-01
+const SECONDS_TO_UNIX_EPOCH: i64 = 62_135_596_800;
+
+fn read_time(node: Option<&Node>) -> Option<i64> {
+    let bytes = BASE64.decode(node?.text.trim().as_bytes()).ok()?;
+    let seconds = i64::from_le_bytes(bytes.try_into().ok()?);
+    Some(seconds - SECONDS_TO_UNIX_EPOCH)
+}
+
+fn write_time(name: &str, time: i64) -> Node {
+    Node::text(
+        name,
+        BASE64.encode(&(time + SECONDS_TO_UNIX_EPOCH).to_le_bytes()),
+    )
+}
+
+fn decode_uuid(text: &str) -> [u8; 16] {
+    BASE64
+        .decode(text.trim().as_bytes())
+        .ok()
+        .and_then(|uuid| uuid.try_into().ok())
+        .unwrap_or_default()
+}
+
+fn read_uuid(node: &Node) -> [u8; 16] {
+    decode_uuid(&node.child_text("UUID"))
+}
+
+fn read_times(node: &Node) -> Times {
+    let times = node.child("Times");
+    let time = |name| read_time(times?.child(name));
+    Times {
+        created: time("CreationTime"),
+        modified: time("LastModificationTime"),
+        accessed: time("LastAccessTime"),
+    }
+}
+
+fn write_times(times: &Times) -> Node {
+    let mut node = Node::new("Times");
+    for (name, time) in [
+        ("CreationTime", times.created),
+        ("LastModificationTime", times.modified),
+        ("LastAccessTime", times.accessed),
+    ] {
+        if let Some(time) = time {
+            node.children.push(write_time(name, time));
+        }
+    }
+    node.with([
+        Node::text("Expires", "False"),
+        Node::text("UsageCount", "0"),
+    ])
+}
+
+fn read_entry(node: &Node, binaries: &[Vec<u8>]) -> Result<Entry, BackendError> {
+    let strings = node
+        .children("String")
+        .map(|string| EntryString {
+            key: string.child_text("Key"),
+            value: string.child_text("Value"),
+            protected: string.child("Value").is_some_and(is_protected),
+        })
+        .collect();
+    let attachments = node
+        .children("Binary")
+        .map(|binary| {
+            let reference = binary
+                .child("Value")
+                .and_then(|value| value.attribute("Ref"))
+                .and_then(|reference| reference.parse::<usize>().ok());
+            let data = reference
+                .and_then(|reference| binaries.get(reference))
+                .ok_or_else(|| invalid("an attachment is missing"))?;
+            Ok(Attachment {
+                name: binary.child_text("Key"),
+                data: data.clone(),
+            })
+        })
+        .collect::<Result<Vec<_>, BackendError>>()?;
+    let history = node
+        .child("History")
+        .map(|history| {
+            history
+                .children("Entry")
+                .map(|entry| read_entry(entry, binaries))
+                .collect::<Result<_, _>>()
+        })
+        .transpose()?
+        .unwrap_or_default();
+    Ok(Entry {
+        uuid: read_uuid(node),
+        times: read_times(node),
+        strings,
+        attachments,
+        history,
+    })
+}
+
+// `attachments` collects the contents of every attachment, which go in the inner header
+fn write_entry(entry: &Entry, attachments: &mut Vec<Vec<u8>>) -> Node {
+    let mut node = Node::new("Entry").with([
+        Node::text("UUID", BASE64.encode(&entry.uuid)),
+        write_times(&entry.times),
+    ]);
+    for string in &entry.strings {
+        let mut value = Node::text("Value", string.value.clone());
+        if string.protected {
+            value
+                .attributes
+                .push(("Protected".to_string(), "True".to_string()));
+        }
+        node.children
+            .push(Node::new("String").with([Node::text("Key", string.key.clone()), value]));
+    }
+    for attachment in &entry.attachments {
+        // the same file attached twice is only stored once
+        let reference = match attachments.iter().position(|data| *data == attachment.data) {
+            Some(reference) => reference,
+            None => {
+                attachments.push(attachment.data.clone());
+                attachments.len() - 1
+            }
+        };
+        let mut value = Node::new("Value");
+        value
+            .attributes
+            .push(("Ref".to_string(), reference.to_string()));
+        node.children
+            .push(Node::new("Binary").with([Node::text("Key", attachment.name.clone()), value]));
+    }
+    if !entry.history.is_empty() {
+        let history = entry
+            .history
+            .iter()
+            .map(|entry| write_entry(entry, attachments))
+            .collect::<Vec<_>>();
+        node.children.push(Node::new("History").with(history));
+    }
+    node
+}
+
+fn read_group(node: &Node, binaries: &[Vec<u8>]) -> Result<Group, BackendError> {
+    Ok(Group {
+        uuid: read_uuid(node),
+        name: node.child_text("Name"),
+        notes: node.child_text("Notes"),
+        times: read_times(node),
+        groups: node
+            .children("Group")
+            .map(|group| read_group(group, binaries))
+            .collect::<Result<_, _>>()?,
+        entries: node
+            .children("Entry")
+            .map(|entry| read_entry(entry, binaries))
+            .collect::<Result<_, _>>()?,
+    })
+}
+
+fn write_group(group: &Group, attachments: &mut Vec<Vec<u8>>) -> Node {
+    let mut node = Node::new("Group").with([
+        Node::text("UUID", BASE64.encode(&group.uuid)),
+        Node::text("Name", group.name.clone()),
+        Node::text("Notes", group.notes.clone()),
+        write_times(&group.times),
+        Node::text("IsExpanded", "True"),
+    ]);
+    for entry in &group.entries {
+        node.children.push(write_entry(entry, attachments));
+    }
+    for group in &group.groups {
+        node.children.push(write_group(group, attachments));
+    }
+    node
+}
+
+/// Reads the XML of a database, decrypting its protected values with `stream`.
+/// `binaries` are the contents of the attachments from the inner header, which entries refer to by index.
+pub(super) fn read(
+    document: &[u8],
+    stream: &mut InnerStream,
+    binaries: &[Vec<u8>],
+) -> Result<Database, BackendError> {
+    let mut file = parse(document)?;
+    unprotect(&mut file, stream)?;
+    let meta = file.child("Meta");
+    let root = file
+        .child("Root")
+        .and_then(|root| root.child("Group"))
+        .ok_or_else(|| invalid("the root group is missing"))?;
+    let recycle_bin = meta.and_then(|meta| {
+        let enabled = meta.child_text("RecycleBinEnabled") != "False";
+        let uuid = decode_uuid(&meta.child_text("RecycleBinUUID"));
+        (enabled && uuid != [0; 16]).then_some(uuid)
+    });
+    Ok(Database {
+        name: meta
+            .map(|meta| meta.child_text("DatabaseName"))
+            .unwrap_or_default(),
+        root: read_group(root, binaries)?,
+        recycle_bin,
+    })
+}
+
+/// Writes the XML of a database, encrypting its protected values with `stream`.
+/// Returns the document, and the contents of the attachments, which go in the inner header.
+pub(super) fn write(
+    database: &Database,
+    stream: &mut InnerStream,
+) -> Result<(Vec<u8>, Vec<Vec<u8>>), BackendError> {
+    let mut attachments = Vec::new();
+    let memory_protection = Node::new("MemoryProtection").with([
+        Node::text("ProtectTitle", "False"),
+        Node::text("ProtectUserName", "False"),
+        Node::text("ProtectPassword", "True"),
+        Node::text("ProtectURL", "False"),
+        Node::text("ProtectNotes", "False"),
+    ]);
+    let meta = Node::new("Meta").with([
+        Node::text("Generator", "passman"),
+        Node::text("DatabaseName", database.name.clone()),
+        memory_protection,
+        Node::text(
+            "RecycleBinEnabled",
+            match database.recycle_bin {
+                Some(_) => "True",
+                None => "False",
+            },
+        ),
+        Node::text(
+            "RecycleBinUUID",
+            BASE64.encode(&database.recycle_bin.unwrap_or_default()),
+        ),
+    ]);
+    let root = Node::new("Root").with([
+        write_group(&database.root, &mut attachments),
+        Node::new("DeletedObjects"),
+    ]);
+    let mut file = Node::new("KeePassFile").with([meta, root]);
+    protect(&mut file, stream);
+    Ok((serialize(&file)?, attachments))
+}
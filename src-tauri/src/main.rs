@@ -1,11 +1,23 @@
 // Prevents additional console window on Windows in release, DO NOT REMOVE!!
 #![cfg_attr(not(debug_assertions), windows_subsystem = "windows")]
 
+pub mod clock;
+pub mod commands;
 pub mod crypto;
 pub mod db_ops;
 pub mod error;
+pub mod export;
+pub mod history;
+pub mod import;
+pub mod kdbx;
+pub mod migrations;
+pub mod organize;
+pub mod otp;
 pub mod password;
+pub mod search;
 pub mod state;
+#[cfg(test)]
+mod test_utils;
 
 use db_ops::tauri::init_database;
 use state::AppState;
@@ -13,9 +25,7 @@ use tauri::{Manager, State};
 
 fn main() {
     tauri::Builder::default()
-        .manage(AppState {
-            connection: Default::default(),
-        })
+        .manage(AppState::new())
         // setup function:
         // this is where we can do any database connection, setup, upgrades, etc.
         // m
@@ -29,9 +39,59 @@ fn main() {
             // setting the state's `connection` field to the one we just initialized.
 
             *app_state.connection.lock().unwrap() = Some(connection);
+
+            // lock the vault in the background once the session times out
+            state::watch_session(handle);
             Ok(())
         })
-        .invoke_handler(tauri::generate_handler![])
+        .invoke_handler(tauri::generate_handler![
+            commands::check_master_exists,
+            commands::register_master,
+            commands::unlock,
+            commands::lock,
+            commands::set_session_timeouts,
+            commands::change_master_password,
+            commands::list_entries,
+            commands::list_password_infos,
+            commands::search_entries,
+            commands::read_entry,
+            commands::mark_entry_used,
+            commands::otp_code,
+            commands::upsert_field,
+            commands::add_custom_field,
+            commands::update_custom_field,
+            commands::delete_custom_field,
+            commands::list_history,
+            commands::restore_history,
+            commands::get_history_retention,
+            commands::set_history_retention,
+            commands::rename_entry,
+            commands::delete_entry,
+            commands::restore_entry,
+            commands::delete_entry_permanently,
+            commands::purge_trash,
+            commands::get_trash_retention,
+            commands::set_trash_retention,
+            commands::create_folder,
+            commands::rename_folder,
+            commands::move_folder,
+            commands::delete_folder,
+            commands::list_folders,
+            commands::move_entry,
+            commands::tag_entry,
+            commands::untag_entry,
+            commands::rename_tag,
+            commands::delete_tag,
+            commands::list_tags,
+            commands::generate_password,
+            commands::import_authenticator,
+            commands::import_bitwarden,
+            commands::import_keepass,
+            commands::export_kdbx,
+            commands::preview_csv,
+            commands::import_csv,
+            commands::export_csv,
+        ])
         .run(tauri::generate_context!())
         .expect("error while running tauri application");
 }
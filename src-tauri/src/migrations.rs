@@ -0,0 +1,393 @@
+// schema migrations for data.db.
+// the schema version a database is at lives in `PRAGMA user_version`, which is 0 for a new database
+// (and for every database created before migrations existed).
+// migration `n` upgrades a database from version `n` to version `n + 1`.
+// never edit a migration that has shipped, add a new one to the end of `MIGRATIONS` instead.
+
+use std::path::Path;
+
+use rusqlite::{Connection, OptionalExtension};
+
+use crate::error::BackendError;
+
+/// One step of the schema, applied inside a transaction together with the `user_version` bump.
+pub struct Migration {
+    pub description: &'static str,
+    pub up: fn(&Connection) -> Result<(), rusqlite::Error>,
+}
+
+pub const MIGRATIONS: &[Migration] = &[
+    Migration {
+        description: "create the PasswordInfo and VaultMetadata tables",
+        up: initial_schema,
+    },
+    Migration {
+        description: "add a blind index for encrypted entry names",
+        up: name_index,
+    },
+    Migration {
+        description: "add the CustomField table",
+        up: custom_fields,
+    },
+    Migration {
+        description: "add folders and tags",
+        up: folders_and_tags,
+    },
+    Migration {
+        description: "add the FieldHistory table",
+        up: field_history,
+    },
+    Migration {
+        description: "add timestamps to PasswordInfo",
+        up: timestamps,
+    },
+    Migration {
+        description: "add a trash to PasswordInfo",
+        up: trash,
+    },
+    Migration {
+        description: "add an OTP field to PasswordInfo",
+        up: otp,
+    },
+    Migration {
+        description: "add a custom fields timestamp to PasswordInfo",
+        up: custom_fields_timestamp,
+    },
+];
+
+/// The schema version this build of the app expects.
+pub fn latest_version() -> u32 {
+    MIGRATIONS.len() as u32
+}
+
+// databases from before migrations already have these tables (with TEXT instead of BLOB columns),
+// hence the `IF NOT EXISTS`. SQLite never converts blobs, so the old column types are fine.
+fn initial_schema(connection: &Connection) -> Result<(), rusqlite::Error> {
+    connection.execute_batch(
+        "CREATE TABLE IF NOT EXISTS PasswordInfo (
+        id INTEGER NOT NULL PRIMARY KEY,
+        name TEXT NOT NULL UNIQUE,
+        username BLOB DEFAULT NULL,
+        email BLOB DEFAULT NULL,
+        password BLOB DEFAULT NULL,
+        notes BLOB DEFAULT NULL
+      );
+      CREATE TABLE IF NOT EXISTS VaultMetadata (
+        key TEXT NOT NULL PRIMARY KEY,
+        value TEXT NOT NULL
+      );",
+    )
+}
+
+// names are encrypted from now on, so the `UNIQUE` on `name` no longer means anything, the index takes over.
+// it's NULL for the master record and for names that haven't been encrypted yet (see `db_ops::migrate_names`),
+// and SQLite doesn't count NULLs as duplicates
+fn name_index(connection: &Connection) -> Result<(), rusqlite::Error> {
+    connection.execute_batch(
+        "ALTER TABLE PasswordInfo ADD COLUMN name_index BLOB DEFAULT NULL;
+        CREATE UNIQUE INDEX PasswordInfoNameIndex ON PasswordInfo(name_index);",
+    )
+}
+
+// the label and value are encrypted like any other field, the type isn't
+fn custom_fields(connection: &Connection) -> Result<(), rusqlite::Error> {
+    connection.execute_batch(
+        "CREATE TABLE CustomField (
+        id INTEGER NOT NULL PRIMARY KEY,
+        password_id INTEGER NOT NULL REFERENCES PasswordInfo(id),
+        label BLOB NOT NULL,
+        field_type TEXT NOT NULL,
+        value BLOB NOT NULL
+      );
+      CREATE INDEX CustomFieldPasswordId ON CustomField(password_id);",
+    )
+}
+
+// folder and tag names are encrypted too. tags are unique, so they get a blind index like entry names;
+// folders only have to be unique among their siblings, which is checked when they're created
+fn folders_and_tags(connection: &Connection) -> Result<(), rusqlite::Error> {
+    connection.execute_batch(
+        "CREATE TABLE Folder (
+        id INTEGER NOT NULL PRIMARY KEY,
+        parent_id INTEGER DEFAULT NULL REFERENCES Folder(id),
+        name BLOB NOT NULL
+      );
+      CREATE TABLE Tag (
+        id INTEGER NOT NULL PRIMARY KEY,
+        name_index BLOB NOT NULL UNIQUE,
+        name BLOB NOT NULL
+      );
+      CREATE TABLE PasswordTag (
+        password_id INTEGER NOT NULL REFERENCES PasswordInfo(id),
+        tag_id INTEGER NOT NULL REFERENCES Tag(id),
+        PRIMARY KEY (password_id, tag_id)
+      );
+      CREATE INDEX PasswordTagTagId ON PasswordTag(tag_id);
+      ALTER TABLE PasswordInfo ADD COLUMN folder_id INTEGER DEFAULT NULL REFERENCES Folder(id);",
+    )
+}
+
+// previous values of the fixed fields, see `history`
+fn field_history(connection: &Connection) -> Result<(), rusqlite::Error> {
+    connection.execute_batch(
+        "CREATE TABLE FieldHistory (
+        id INTEGER NOT NULL PRIMARY KEY,
+        password_id INTEGER NOT NULL REFERENCES PasswordInfo(id),
+        field TEXT NOT NULL,
+        value BLOB NOT NULL,
+        replaced_at INTEGER NOT NULL
+      );
+      CREATE INDEX FieldHistoryPasswordId ON FieldHistory(password_id, field);",
+    )
+}
+
+// seconds since the Unix epoch. entries from before this stay NULL, we can't know when they were made
+fn timestamps(connection: &Connection) -> Result<(), rusqlite::Error> {
+    connection.execute_batch(
+        "ALTER TABLE PasswordInfo ADD COLUMN created_at INTEGER DEFAULT NULL;
+        ALTER TABLE PasswordInfo ADD COLUMN email_updated_at INTEGER DEFAULT NULL;
+        ALTER TABLE PasswordInfo ADD COLUMN username_updated_at INTEGER DEFAULT NULL;
+        ALTER TABLE PasswordInfo ADD COLUMN password_updated_at INTEGER DEFAULT NULL;
+        ALTER TABLE PasswordInfo ADD COLUMN notes_updated_at INTEGER DEFAULT NULL;
+        ALTER TABLE PasswordInfo ADD COLUMN last_accessed_at INTEGER DEFAULT NULL;",
+    )
+}
+
+// a trashed entry gets a placeholder name index (see `crypto::trash_index`), so its name can be used again
+fn trash(connection: &Connection) -> Result<(), rusqlite::Error> {
+    connection.execute_batch("ALTER TABLE PasswordInfo ADD COLUMN trashed_at INTEGER DEFAULT NULL;")
+}
+// an `otpauth://` URI, encrypted like the other fields (see `otp`)
+fn otp(connection: &Connection) -> Result<(), rusqlite::Error> {
+    connection.execute_batch(
+        "ALTER TABLE PasswordInfo ADD COLUMN otp BLOB DEFAULT NULL;
+        ALTER TABLE PasswordInfo ADD COLUMN otp_updated_at INTEGER DEFAULT NULL;",
+    )
+}
+// when any custom field of the entry last changed; they're in their own table, so they share one
+fn custom_fields_timestamp(connection: &Connection) -> Result<(), rusqlite::Error> {
+    connection.execute_batch(
+        "ALTER TABLE PasswordInfo ADD COLUMN custom_fields_updated_at INTEGER DEFAULT NULL;",
+    )
+}
+
+/// Reads the schema version of the database.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+///
+pub fn user_version(connection: &Connection) -> Result<u32, rusqlite::Error> {
+    connection.query_row("PRAGMA user_version", (), |row| row.get(0))
+}
+
+/// Copies the whole database to `path`, which must not exist yet.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `path` - where to write the copy.
+///
+pub fn backup(connection: &Connection, path: &Path) -> Result<(), rusqlite::Error> {
+    connection.execute("VACUUM INTO ?", [path.to_string_lossy()])?;
+    Ok(())
+}
+
+/// Upgrades the database to `latest_version()`, returning how many migrations were applied.
+/// Each migration is applied in its own transaction, so a failed upgrade leaves the database at the last version that succeeded.
+/// Fails with `BackendError::UnsupportedSchemaVersion` if the database was created by a newer version of the app.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `backup_path` - if set, and the database already holds data, a copy is written here before anything is upgraded.
+///
+pub fn migrate(connection: &Connection, backup_path: Option<&Path>) -> Result<usize, BackendError> {
+    apply(connection, MIGRATIONS, backup_path)
+}
+
+fn apply(
+    connection: &Connection,
+    migrations: &[Migration],
+    backup_path: Option<&Path>,
+) -> Result<usize, BackendError> {
+    let version = user_version(connection)?;
+    let latest = migrations.len() as u32;
+    if version > latest {
+        return Err(BackendError::UnsupportedSchemaVersion(version));
+    }
+    if version == latest {
+        return Ok(0);
+    }
+
+    // a brand new database has nothing worth backing up
+    if let Some(path) = backup_path {
+        if has_tables(connection)? {
+            backup(connection, path)?;
+        }
+    }
+
+    for (index, migration) in migrations.iter().enumerate().skip(version as usize) {
+        let version = index as u32 + 1;
+        let upgrade = || {
+            let tx = connection.unchecked_transaction()?;
+            (migration.up)(&tx)?;
+            // pragmas can't take parameters
+            tx.execute_batch(&format!("PRAGMA user_version = {}", version))?;
+            tx.commit()
+        };
+        upgrade().map_err(|source| BackendError::MigrationFailed {
+            version,
+            description: migration.description,
+            source,
+        })?;
+    }
+    Ok((latest - version) as usize)
+}
+
+fn has_tables(connection: &Connection) -> Result<bool, rusqlite::Error> {
+    Ok(connection
+        .query_row(
+            "select 1 from sqlite_master where type = 'table'",
+            (),
+            |_| Ok(()),
+        )
+        .optional()?
+        .is_some())
+}
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use super::{apply, latest_version, migrate, user_version, Migration, MIGRATIONS};
+    use crate::error::BackendError;
+
+    #[test]
+    fn fresh_database() {
+        let connection = Connection::open_in_memory().unwrap();
+        assert_eq!(user_version(&connection).unwrap(), 0);
+
+        let applied = migrate(&connection, None).unwrap();
+        assert_eq!(applied, latest_version() as usize);
+        assert_eq!(user_version(&connection).unwrap(), latest_version());
+
+        // already up to date
+        assert_eq!(migrate(&connection, None).unwrap(), 0);
+    }
+
+    #[test]
+    fn unversioned_database() {
+        // the schema from before migrations existed
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE PasswordInfo (
+                id INTEGER NOT NULL PRIMARY KEY,
+                name TEXT NOT NULL UNIQUE,
+                username TEXT DEFAULT NULL,
+                email TEXT DEFAULT NULL,
+                password TEXT DEFAULT NULL,
+                notes TEXT DEFAULT NULL
+              );
+              insert into PasswordInfo (name, password) values ('test_name', 'abcd');",
+            )
+            .unwrap();
+
+        migrate(&connection, None).unwrap();
+        assert_eq!(user_version(&connection).unwrap(), latest_version());
+        let password: String = connection
+            .query_row(
+                "select password from PasswordInfo where name = 'test_name'",
+                (),
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(password, "abcd");
+    }
+
+    #[test]
+    fn newer_database() {
+        let connection = Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(&format!("PRAGMA user_version = {}", latest_version() + 1))
+            .unwrap();
+        assert!(matches!(
+            migrate(&connection, None),
+            Err(BackendError::UnsupportedSchemaVersion(_))
+        ));
+    }
+
+    #[test]
+    fn failed_migration() {
+        let migrations = [
+            Migration {
+                description: "create a table",
+                up: |connection| connection.execute_batch("CREATE TABLE A (id INTEGER);"),
+            },
+            Migration {
+                description: "create a table, then fail",
+                up: |connection| {
+                    connection.execute_batch("CREATE TABLE B (id INTEGER);")?;
+                    connection.execute_batch("this isn't sql")
+                },
+            },
+        ];
+        let connection = Connection::open_in_memory().unwrap();
+        assert!(matches!(
+            apply(&connection, &migrations, None),
+            Err(BackendError::MigrationFailed { version: 2, .. })
+        ));
+
+        // the first migration stuck, the second was rolled back entirely
+        assert_eq!(user_version(&connection).unwrap(), 1);
+        let table_exists = |name: &str| {
+            connection
+                .query_row(
+                    "select count(*) from sqlite_master where name = ?",
+                    [name],
+                    |row| row.get::<_, u32>(0),
+                )
+                .unwrap()
+                == 1
+        };
+        assert!(table_exists("A"));
+        assert!(!table_exists("B"));
+    }
+
+    #[test]
+    fn backup() {
+        let dir = std::env::temp_dir().join(format!("passman-migrations-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let db_path = dir.join("data.db");
+        let backup_path = dir.join("data.db.bak");
+        let _ = std::fs::remove_file(&db_path);
+        let _ = std::fs::remove_file(&backup_path);
+
+        // a fresh database isn't backed up
+        let connection = Connection::open(&db_path).unwrap();
+        let previous = &MIGRATIONS[..MIGRATIONS.len() - 1];
+        apply(&connection, previous, Some(&backup_path)).unwrap();
+        assert!(!backup_path.exists());
+
+        // the database is now a version behind
+        connection
+            .execute(
+                "insert into PasswordInfo (name, password) values ('test_name', 'abcd')",
+                (),
+            )
+            .unwrap();
+        migrate(&connection, Some(&backup_path)).unwrap();
+        assert_eq!(user_version(&connection).unwrap(), latest_version());
+
+        let backup = Connection::open(&backup_path).unwrap();
+        assert_eq!(user_version(&backup).unwrap(), latest_version() - 1);
+        let password: String = backup
+            .query_row(
+                "select password from PasswordInfo where name = 'test_name'",
+                (),
+                |row| row.get(0),
+            )
+            .unwrap();
+        assert_eq!(password, "abcd");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,635 @@
+// folders and tags, for organizing passwords.
+// folders form a tree, and a password is in at most one of them; tags are flat, and a password can have any number.
+// their names are encrypted with the same key as entry names (see `crypto::name_cipher`), bound to their row.
+
+use std::collections::HashSet;
+
+use rusqlite::{Connection, OptionalExtension};
+
+use crate::{
+    crypto::*,
+    error::BackendError,
+    password::{Folder, Tag},
+};
+
+fn folder_associated_data(folder_id: i32) -> Vec<u8> {
+    [b"passman-folder".as_ref(), &folder_id.to_be_bytes()].concat()
+}
+
+fn tag_associated_data(index: &[u8]) -> Vec<u8> {
+    [b"passman-tag".as_ref(), index].concat()
+}
+
+fn password_id(
+    connection: &Connection,
+    password_name: &str,
+    key: &[u8],
+) -> Result<Option<i32>, rusqlite::Error> {
+    connection
+        .query_row(
+            "select id from PasswordInfo where name_index = ?",
+            [name_index(key, password_name)],
+            |row| row.get(0),
+        )
+        .optional()
+}
+
+fn check_folder_exists(connection: &Connection, folder_id: i32) -> Result<(), BackendError> {
+    connection
+        .query_row("select 1 from Folder where id = ?", [folder_id], |_| Ok(()))
+        .optional()?
+        .ok_or(BackendError::FolderNotFound(folder_id))
+}
+
+// folder names only have to be unique among siblings, and like entry names, case and surrounding whitespace don't count
+fn check_sibling_names(
+    connection: &Connection,
+    key: &[u8],
+    parent_id: Option<i32>,
+    name: &str,
+    except: Option<i32>,
+) -> Result<(), BackendError> {
+    let mut stmt = connection.prepare("select id, name from Folder where parent_id is ?")?;
+    let siblings = stmt.query_map([parent_id], |row| {
+        Ok((row.get::<_, i32>(0)?, row.get::<_, EncryptedField>(1)?))
+    })?;
+    let cipher = name_cipher(key);
+    for sibling in siblings {
+        let (id, sibling_name) = sibling?;
+        if Some(id) == except {
+            continue;
+        }
+        let sibling_name =
+            decrypt_password_field(&sibling_name, &folder_associated_data(id), false, &cipher)?;
+        if normalize_name(&sibling_name) == normalize_name(name) {
+            return Err(BackendError::NameAlreadyExists(name.to_string()));
+        }
+    }
+    Ok(())
+}
+
+/// Creates a folder, returning its id. Fails if the parent doesn't exist or already has a folder with that name.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+/// - `name` - a string slice that holds the name of the folder.
+/// - `parent_id` - the folder to create it in, or `None` for a top-level folder.
+///
+pub fn create_folder(
+    connection: &Connection,
+    key: &[u8],
+    name: &str,
+    parent_id: Option<i32>,
+) -> Result<i32, BackendError> {
+    let transaction = connection.unchecked_transaction()?;
+    let id = insert_folder(&transaction, key, name, parent_id)?;
+    transaction.commit()?;
+    Ok(id)
+}
+
+// `create_folder` without its own transaction, for callers that are already in one
+pub(crate) fn insert_folder(
+    connection: &Connection,
+    key: &[u8],
+    name: &str,
+    parent_id: Option<i32>,
+) -> Result<i32, BackendError> {
+    if let Some(parent_id) = parent_id {
+        check_folder_exists(connection, parent_id)?;
+    }
+    check_sibling_names(connection, key, parent_id, name, None)?;
+
+    // the name is bound to the id, which we only know once the row exists
+    connection.execute(
+        "insert into Folder(parent_id, name) values (?1, x'')",
+        [parent_id],
+    )?;
+    let id = connection.last_insert_rowid() as i32;
+    let encrypted = encrypt_password_field(name, &folder_associated_data(id), &name_cipher(key));
+    connection.execute("update Folder set name = ?1 where id = ?2", (encrypted, id))?;
+    Ok(id)
+}
+
+/// Renames a folder. Returns how many folders were renamed: 0 if it doesn't exist, otherwise 1.
+/// Fails with `BackendError::NameAlreadyExists` if a sibling already has that name.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+/// - `folder_id` - the id of the folder to rename.
+/// - `name` - a string slice that holds the new name.
+///
+pub fn rename_folder(
+    connection: &Connection,
+    key: &[u8],
+    folder_id: i32,
+    name: &str,
+) -> Result<usize, BackendError> {
+    let parent_id: Option<Option<i32>> = connection
+        .query_row(
+            "select parent_id from Folder where id = ?",
+            [folder_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let parent_id = match parent_id {
+        Some(parent_id) => parent_id,
+        None => return Ok(0),
+    };
+    check_sibling_names(connection, key, parent_id, name, Some(folder_id))?;
+    let encrypted =
+        encrypt_password_field(name, &folder_associated_data(folder_id), &name_cipher(key));
+    Ok(connection.execute(
+        "update Folder set name = ?1 where id = ?2",
+        (encrypted, folder_id),
+    )?)
+}
+
+/// Returns the ids of a folder and every folder below it.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `folder_id` - the id of the folder at the top of the subtree.
+///
+pub fn subtree(connection: &Connection, folder_id: i32) -> Result<HashSet<i32>, rusqlite::Error> {
+    let mut stmt = connection.prepare(
+        "with recursive subtree(id) as (
+            select ?1
+            union select Folder.id from Folder join subtree on Folder.parent_id = subtree.id
+        ) select id from subtree",
+    )?;
+    let ids = stmt.query_map([folder_id], |row| row.get(0))?;
+    ids.collect()
+}
+
+/// Moves a folder (and everything in it) into another folder. Returns how many folders were moved: 0 or 1.
+/// Fails if the new parent doesn't exist, is inside the folder, or already has a folder with that name.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+/// - `folder_id` - the id of the folder to move.
+/// - `parent_id` - the folder to move it into, or `None` to make it a top-level folder.
+///
+pub fn move_folder(
+    connection: &Connection,
+    key: &[u8],
+    folder_id: i32,
+    parent_id: Option<i32>,
+) -> Result<usize, BackendError> {
+    let name: Option<EncryptedField> = connection
+        .query_row("select name from Folder where id = ?", [folder_id], |row| {
+            row.get(0)
+        })
+        .optional()?;
+    let name = match name {
+        Some(name) => decrypt_password_field(
+            &name,
+            &folder_associated_data(folder_id),
+            false,
+            &name_cipher(key),
+        )?,
+        None => return Ok(0),
+    };
+    if let Some(parent_id) = parent_id {
+        check_folder_exists(connection, parent_id)?;
+        if subtree(connection, folder_id)?.contains(&parent_id) {
+            return Err(BackendError::FolderCycle);
+        }
+    }
+    check_sibling_names(connection, key, parent_id, &name, Some(folder_id))?;
+    Ok(connection.execute(
+        "update Folder set parent_id = ?1 where id = ?2",
+        (parent_id, folder_id),
+    )?)
+}
+
+/// Deletes a folder. Nothing in it is deleted: its passwords and subfolders move up into its parent.
+/// Runs inside a transaction. Returns how many folders were deleted: 0 if it doesn't exist, otherwise 1.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `folder_id` - the id of the folder to delete.
+///
+pub fn delete_folder(connection: &Connection, folder_id: i32) -> Result<usize, BackendError> {
+    let transaction = connection.unchecked_transaction()?;
+    let parent_id: Option<Option<i32>> = transaction
+        .query_row(
+            "select parent_id from Folder where id = ?",
+            [folder_id],
+            |row| row.get(0),
+        )
+        .optional()?;
+    let parent_id = match parent_id {
+        Some(parent_id) => parent_id,
+        None => return Ok(0),
+    };
+    // the names of the folders moving up could clash with their new siblings, but that's better than losing them
+    transaction.execute(
+        "update Folder set parent_id = ?1 where parent_id = ?2",
+        (parent_id, folder_id),
+    )?;
+    transaction.execute(
+        "update PasswordInfo set folder_id = ?1 where folder_id = ?2",
+        (parent_id, folder_id),
+    )?;
+    let deleted = transaction.execute("delete from Folder where id = ?", [folder_id])?;
+    transaction.commit()?;
+    Ok(deleted)
+}
+
+/// Lists every folder, with how many passwords are directly in each (not counting the trash), sorted by name.
+/// The frontend can build the tree from `parent_id`.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+///
+pub fn list_folders(connection: &Connection, key: &[u8]) -> Result<Vec<Folder>, BackendError> {
+    let mut stmt = connection.prepare(
+        "select id, parent_id, name, (select count(*) from PasswordInfo where folder_id = Folder.id and trashed_at is null) from Folder",
+    )?;
+    let cipher = name_cipher(key);
+    let mut folders = stmt
+        .query_map((), |row| {
+            Ok((
+                row.get::<_, i32>(0)?,
+                row.get::<_, Option<i32>>(1)?,
+                row.get::<_, EncryptedField>(2)?,
+                row.get::<_, usize>(3)?,
+            ))
+        })?
+        .map(|row| {
+            let (id, parent_id, name, password_count) = row?;
+            Ok(Folder {
+                id,
+                parent_id,
+                name: decrypt_password_field(&name, &folder_associated_data(id), false, &cipher)?,
+                password_count,
+            })
+        })
+        .collect::<Result<Vec<_>, BackendError>>()?;
+    folders.sort_by_key(|folder| (folder.name.to_lowercase(), folder.id));
+    Ok(folders)
+}
+
+/// Moves a password into a folder. Returns how many passwords were moved: 0 if it doesn't exist, otherwise 1.
+/// Fails with `BackendError::FolderNotFound` if the folder doesn't exist.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+/// - `password_name` - a string slice that holds the name of the password to move.
+/// - `folder_id` - the folder to move it into, or `None` to take it out of any folder.
+///
+pub fn move_password(
+    connection: &Connection,
+    key: &[u8],
+    password_name: &str,
+    folder_id: Option<i32>,
+) -> Result<usize, BackendError> {
+    if let Some(folder_id) = folder_id {
+        check_folder_exists(connection, folder_id)?;
+    }
+    Ok(connection.execute(
+        "update PasswordInfo set folder_id = ?1 where name_index = ?2",
+        (folder_id, name_index(key, password_name)),
+    )?)
+}
+
+/// Tags a password, creating the tag if needed. Runs inside a transaction.
+/// Returns how many passwords were tagged: 0 if the password doesn't exist or already has the tag, otherwise 1.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+/// - `password_name` - a string slice that holds the name of the password to tag.
+/// - `tag` - a string slice that holds the tag.
+///
+pub fn tag_password(
+    connection: &Connection,
+    key: &[u8],
+    password_name: &str,
+    tag: &str,
+) -> Result<usize, BackendError> {
+    let transaction = connection.unchecked_transaction()?;
+    let password_id = match password_id(&transaction, password_name, key)? {
+        Some(id) => id,
+        None => return Ok(0),
+    };
+    let index = tag_index(key, tag);
+    let existing: Option<i32> = transaction
+        .query_row("select id from Tag where name_index = ?", [&index], |row| {
+            row.get(0)
+        })
+        .optional()?;
+    let tag_id = match existing {
+        Some(id) => id,
+        None => {
+            let name = encrypt_password_field(tag, &tag_associated_data(&index), &name_cipher(key));
+            transaction.execute(
+                "insert into Tag(name_index, name) values (?1, ?2)",
+                (&index, name),
+            )?;
+            transaction.last_insert_rowid() as i32
+        }
+    };
+    let tagged = transaction.execute(
+        "insert or ignore into PasswordTag(password_id, tag_id) values (?1, ?2)",
+        (password_id, tag_id),
+    )?;
+    transaction.commit()?;
+    Ok(tagged)
+}
+
+/// Removes a tag from a password. The tag itself stays, even if nothing has it anymore; see `delete_tag`.
+/// Returns how many passwords were untagged: 0 if the password doesn't exist or doesn't have the tag, otherwise 1.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+/// - `password_name` - a string slice that holds the name of the password to untag.
+/// - `tag` - a string slice that holds the tag.
+///
+pub fn untag_password(
+    connection: &Connection,
+    key: &[u8],
+    password_name: &str,
+    tag: &str,
+) -> Result<usize, rusqlite::Error> {
+    connection.execute(
+        "delete from PasswordTag
+        where password_id = (select id from PasswordInfo where name_index = ?1)
+        and tag_id = (select id from Tag where name_index = ?2)",
+        (name_index(key, password_name), tag_index(key, tag)),
+    )
+}
+
+/// Renames a tag. Returns how many tags were renamed: 0 if it doesn't exist, otherwise 1.
+/// Fails with `BackendError::NameAlreadyExists` if another tag already has that name.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+/// - `tag_id` - the id of the tag to rename.
+/// - `name` - a string slice that holds the new name.
+///
+pub fn rename_tag(
+    connection: &Connection,
+    key: &[u8],
+    tag_id: i32,
+    name: &str,
+) -> Result<usize, BackendError> {
+    let index = tag_index(key, name);
+    let existing: Option<i32> = connection
+        .query_row("select id from Tag where name_index = ?", [&index], |row| {
+            row.get(0)
+        })
+        .optional()?;
+    if existing.is_some_and(|id| id != tag_id) {
+        return Err(BackendError::NameAlreadyExists(name.to_string()));
+    }
+    let encrypted = encrypt_password_field(name, &tag_associated_data(&index), &name_cipher(key));
+    Ok(connection.execute(
+        "update Tag set name_index = ?1, name = ?2 where id = ?3",
+        (&index, encrypted, tag_id),
+    )?)
+}
+
+/// Deletes a tag, removing it from every password that has it. Runs inside a transaction.
+/// Returns how many tags were deleted: 0 if it doesn't exist, otherwise 1.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `tag_id` - the id of the tag to delete.
+///
+pub fn delete_tag(connection: &Connection, tag_id: i32) -> Result<usize, rusqlite::Error> {
+    let transaction = connection.unchecked_transaction()?;
+    transaction.execute("delete from PasswordTag where tag_id = ?", [tag_id])?;
+    let deleted = transaction.execute("delete from Tag where id = ?", [tag_id])?;
+    transaction.commit()?;
+    Ok(deleted)
+}
+
+/// Lists every tag, with how many passwords have each (not counting the trash), sorted by name.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+///
+pub fn list_tags(connection: &Connection, key: &[u8]) -> Result<Vec<Tag>, BackendError> {
+    let mut stmt = connection.prepare(
+        "select id, name_index, name, (select count(*) from PasswordTag join PasswordInfo on PasswordInfo.id = password_id where tag_id = Tag.id and trashed_at is null) from Tag",
+    )?;
+    let cipher = name_cipher(key);
+    let mut tags = stmt
+        .query_map((), |row| {
+            Ok((
+                row.get::<_, i32>(0)?,
+                row.get::<_, Vec<u8>>(1)?,
+                row.get::<_, EncryptedField>(2)?,
+                row.get::<_, usize>(3)?,
+            ))
+        })?
+        .map(|row| {
+            let (id, index, name, password_count) = row?;
+            Ok(Tag {
+                id,
+                name: decrypt_password_field(&name, &tag_associated_data(&index), false, &cipher)?,
+                password_count,
+            })
+        })
+        .collect::<Result<Vec<_>, BackendError>>()?;
+    tags.sort_by_key(|tag| (tag.name.to_lowercase(), tag.id));
+    Ok(tags)
+}
+
+/// Encrypts every folder and tag name again under `new_key`, for when the vault key changes.
+/// Stops at the first error; run this inside a transaction so a failure leaves nothing half-done.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `old_key` - the vault key every name is currently encrypted under.
+/// - `new_key` - the vault key to encrypt under instead.
+///
+pub fn reencrypt(
+    connection: &Connection,
+    old_key: &[u8],
+    new_key: &[u8],
+) -> Result<(), BackendError> {
+    let cipher = name_cipher(new_key);
+    for folder in list_folders(connection, old_key)? {
+        let name =
+            encrypt_password_field(&folder.name, &folder_associated_data(folder.id), &cipher);
+        connection.execute(
+            "update Folder set name = ?1 where id = ?2",
+            (name, folder.id),
+        )?;
+    }
+    for tag in list_tags(connection, old_key)? {
+        let index = tag_index(new_key, &tag.name);
+        let name = encrypt_password_field(&tag.name, &tag_associated_data(&index), &cipher);
+        connection.execute(
+            "update Tag set name_index = ?1, name = ?2 where id = ?3",
+            (&index, name, tag.id),
+        )?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{db_ops::crud_operations, error::BackendError, test_utils};
+
+    #[test]
+    fn folders() {
+        let (connection, key) = test_utils::vault_with(&["GitHub", "gitlab", "bank"]);
+        let work = super::create_folder(&connection, &key, "Work", None).unwrap();
+        let code = super::create_folder(&connection, &key, "Code", Some(work)).unwrap();
+        // the same name is fine somewhere else, but not next to itself
+        super::create_folder(&connection, &key, "Code", None).unwrap();
+        assert!(matches!(
+            super::create_folder(&connection, &key, " code", Some(work)),
+            Err(BackendError::NameAlreadyExists(_))
+        ));
+        assert!(matches!(
+            super::create_folder(&connection, &key, "Nowhere", Some(1000)),
+            Err(BackendError::FolderNotFound(1000))
+        ));
+
+        super::move_password(&connection, &key, "github", Some(code)).unwrap();
+        super::move_password(&connection, &key, "gitlab", Some(code)).unwrap();
+        super::move_password(&connection, &key, "bank", Some(work)).unwrap();
+        let password = crud_operations::read_password_info(&connection, "GitHub", &key)
+            .unwrap()
+            .unwrap();
+        assert_eq!(password.folder_id, Some(code));
+
+        let folders = super::list_folders(&connection, &key).unwrap();
+        let counts: Vec<_> = folders
+            .iter()
+            .map(|f| (f.name.as_str(), f.parent_id, f.password_count))
+            .collect();
+        assert_eq!(
+            counts,
+            vec![
+                ("Code", Some(work), 2),
+                ("Code", None, 0),
+                ("Work", None, 1)
+            ]
+        );
+        // no plaintext names in the table
+        let stored: Vec<u8> = connection
+            .query_row("select name from Folder where id = ?", [work], |row| {
+                row.get(0)
+            })
+            .unwrap();
+        assert!(!stored.windows(4).any(|w| w == b"Work"));
+
+        // no cycles
+        assert!(matches!(
+            super::move_folder(&connection, &key, work, Some(code)),
+            Err(BackendError::FolderCycle)
+        ));
+        assert!(matches!(
+            super::move_folder(&connection, &key, work, Some(work)),
+            Err(BackendError::FolderCycle)
+        ));
+        // and no clashes
+        assert!(matches!(
+            super::move_folder(&connection, &key, code, None),
+            Err(BackendError::NameAlreadyExists(_))
+        ));
+        super::rename_folder(&connection, &key, code, "Repos").unwrap();
+        super::move_folder(&connection, &key, code, None).unwrap();
+        assert_eq!(
+            super::subtree(&connection, work).unwrap(),
+            [work].into_iter().collect()
+        );
+
+        // deleting a folder moves what's in it up
+        let personal = super::create_folder(&connection, &key, "Personal", Some(code)).unwrap();
+        super::delete_folder(&connection, code).unwrap();
+        let password = crud_operations::read_password_info(&connection, "GitHub", &key)
+            .unwrap()
+            .unwrap();
+        assert_eq!(password.folder_id, None);
+        let folders = super::list_folders(&connection, &key).unwrap();
+        assert!(folders
+            .iter()
+            .any(|f| f.id == personal && f.parent_id.is_none()));
+        assert_eq!(super::delete_folder(&connection, code).unwrap(), 0);
+    }
+
+    #[test]
+    fn tags() {
+        let (connection, key) = test_utils::vault_with(&["GitHub", "gitlab", "bank"]);
+        assert_eq!(
+            super::tag_password(&connection, &key, "GitHub", "dev").unwrap(),
+            1
+        );
+        super::tag_password(&connection, &key, "GitHub", "2FA").unwrap();
+        super::tag_password(&connection, &key, "gitlab", " DEV").unwrap();
+        // tagging twice, or tagging something that doesn't exist, does nothing
+        assert_eq!(
+            super::tag_password(&connection, &key, "github", "dev").unwrap(),
+            0
+        );
+        assert_eq!(
+            super::tag_password(&connection, &key, "nothing", "dev").unwrap(),
+            0
+        );
+
+        let tags = super::list_tags(&connection, &key).unwrap();
+        let counts: Vec<_> = tags
+            .iter()
+            .map(|t| (t.name.as_str(), t.password_count))
+            .collect();
+        assert_eq!(counts, vec![("2FA", 1), ("dev", 2)]);
+        let dev = tags[1].id;
+        let password = crud_operations::read_password_info(&connection, "GitHub", &key)
+            .unwrap()
+            .unwrap();
+        assert_eq!(password.tag_ids.len(), 2);
+        assert!(password.tag_ids.contains(&dev));
+
+        assert!(matches!(
+            super::rename_tag(&connection, &key, dev, "2fa"),
+            Err(BackendError::NameAlreadyExists(_))
+        ));
+        super::rename_tag(&connection, &key, dev, "Development").unwrap();
+        assert_eq!(
+            super::untag_password(&connection, &key, "gitlab", "development").unwrap(),
+            1
+        );
+        let tags = super::list_tags(&connection, &key).unwrap();
+        assert_eq!(tags[1].name, "Development");
+        assert_eq!(tags[1].password_count, 1);
+
+        super::delete_tag(&connection, dev).unwrap();
+        let password = crud_operations::read_password_info(&connection, "GitHub", &key)
+            .unwrap()
+            .unwrap();
+        assert_eq!(password.tag_ids.len(), 1);
+    }
+
+    #[test]
+    fn reencrypt() {
+        let (connection, key) = test_utils::vault_with(&["GitHub", "gitlab", "bank"]);
+        super::create_folder(&connection, &key, "Work", None).unwrap();
+        super::tag_password(&connection, &key, "bank", "money").unwrap();
+
+        let new_key = [7u8; 32];
+        super::reencrypt(&connection, &key, &new_key).unwrap();
+        assert_eq!(
+            super::list_folders(&connection, &new_key).unwrap()[0].name,
+            "Work"
+        );
+        assert_eq!(
+            super::list_tags(&connection, &new_key).unwrap()[0].name,
+            "money"
+        );
+        assert!(super::list_tags(&connection, &key).is_err());
+    }
+}
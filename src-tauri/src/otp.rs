@@ -0,0 +1,519 @@
+// two-factor codes, so the vault can stand in for an authenticator app.
+// a password's OTP field holds an `otpauth://` URI (the format of the QR codes sites show when you set up 2FA),
+// encrypted like any other field. codes are HOTP (RFC 4226), or TOTP (RFC 6238), which is HOTP with the time as the counter.
+
+use std::str::FromStr;
+
+use data_encoding::BASE32_NOPAD;
+use hmac::{Hmac, Mac};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, NON_ALPHANUMERIC};
+use rusqlite::Connection;
+use sha1::Sha1;
+use sha2::{Sha256, Sha512};
+
+use crate::{
+    clock,
+    crypto::*,
+    db_ops::crud_operations,
+    error::BackendError,
+    password::{PasswordField, PasswordInfo},
+};
+
+/// The hash function codes are computed with. Almost everything uses SHA1.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OtpAlgorithm {
+    #[default]
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+/// Whether codes are counted by time or by use.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum OtpKind {
+    /// A new code every `period` seconds.
+    Totp { period: u64 },
+    /// A new code every time one is generated. `counter` is the one the next code uses.
+    Hotp { counter: u64 },
+}
+
+/// An OTP secret and how to compute codes from it, as described by an `otpauth://` URI.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Otp {
+    pub kind: OtpKind,
+    pub secret: Vec<u8>,
+    pub algorithm: OtpAlgorithm,
+    pub digits: u32,
+    pub issuer: Option<String>,
+    pub account: Option<String>,
+}
+
+/// A generated code.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct OtpCode {
+    pub code: String,
+    /// For TOTP, how many more seconds the code is valid for.
+    pub remaining: Option<u64>,
+    /// For TOTP, how many seconds each code is valid for in total.
+    pub period: Option<u64>,
+    /// For HOTP, the counter the code was generated with.
+    pub counter: Option<u64>,
+    pub issuer: Option<String>,
+    pub account: Option<String>,
+}
+
+fn invalid(message: &str) -> BackendError {
+    BackendError::InvalidOtp(message.to_string())
+}
+
+// parses a numeric parameter of a URI, which has to fit `T` as it is
+fn number<T: FromStr>(name: &str, value: &str) -> Result<T, BackendError> {
+    value
+        .parse()
+        .map_err(|_| invalid(&format!("`{}` isn't a number, or is too large", name)))
+}
+
+fn decode(text: &str) -> Result<String, BackendError> {
+    percent_decode_str(text)
+        .decode_utf8()
+        .map(|text| text.into_owned())
+        .map_err(|_| invalid("not valid UTF-8"))
+}
+
+fn encode(text: &str) -> String {
+    utf8_percent_encode(text, NON_ALPHANUMERIC).to_string()
+}
+
+/// Decodes a base32 secret the way people paste them: any case, with spaces, with or without padding.
+pub fn decode_secret(secret: &str) -> Result<Vec<u8>, BackendError> {
+    let cleaned: String = secret
+        .chars()
+        .filter(|c| !c.is_whitespace() && *c != '=' && *c != '-')
+        .map(|c| c.to_ascii_uppercase())
+        .collect();
+    let secret = BASE32_NOPAD
+        .decode(cleaned.as_bytes())
+        .map_err(|_| invalid("the secret isn't valid base32"))?;
+    if secret.is_empty() {
+        return Err(invalid("the secret is empty"));
+    }
+    Ok(secret)
+}
+
+impl Otp {
+    /// Parses an `otpauth://totp/...` or `otpauth://hotp/...` URI.
+    /// Fails with `BackendError::InvalidOtp` if it's malformed, or uses anything we can't compute codes for.
+    ///
+    /// # Arguments
+    ///
+    /// - `uri` - a string slice that holds the URI, e.g. `otpauth://totp/GitHub:me?secret=JBSWY3DPEHPK3PXP&issuer=GitHub`.
+    pub fn parse_uri(uri: &str) -> Result<Self, BackendError> {
+        let rest = uri
+            .trim()
+            .strip_prefix("otpauth://")
+            .ok_or_else(|| invalid("not an otpauth:// URI"))?;
+        let (kind, rest) = rest
+            .split_once('/')
+            .ok_or_else(|| invalid("missing the label"))?;
+        let (label, query) = rest.split_once('?').unwrap_or((rest, ""));
+
+        // the label is `issuer:account`, or just `account`
+        let label = decode(label)?;
+        let (mut issuer, account) = match label.split_once(':') {
+            Some((issuer, account)) => (Some(issuer.trim().to_string()), account.trim()),
+            None => (None, label.trim()),
+        };
+        let account = (!account.is_empty()).then(|| account.to_string());
+
+        let mut secret = None;
+        let mut algorithm = OtpAlgorithm::Sha1;
+        let mut digits = 6;
+        let mut period = 30;
+        let mut counter = None;
+        for pair in query.split('&').filter(|pair| !pair.is_empty()) {
+            let (name, value) = pair.split_once('=').unwrap_or((pair, ""));
+            let value = decode(value)?;
+            match name.to_ascii_lowercase().as_str() {
+                "secret" => secret = Some(decode_secret(&value)?),
+                // the parameter wins over the label, it's what the spec recommends
+                "issuer" if !value.is_empty() => issuer = Some(value),
+                "algorithm" => {
+                    algorithm = match value.to_ascii_uppercase().as_str() {
+                        "SHA1" => OtpAlgorithm::Sha1,
+                        "SHA256" => OtpAlgorithm::Sha256,
+                        "SHA512" => OtpAlgorithm::Sha512,
+                        _ => return Err(invalid(&format!("unsupported algorithm `{}`", value))),
+                    }
+                }
+                "digits" => digits = number(name, &value)?,
+                "period" => period = number(name, &value)?,
+                "counter" => counter = Some(number(name, &value)?),
+                // e.g. `image`, which we have no use for
+                _ => {}
+            }
+        }
+
+        let kind = match kind.to_ascii_lowercase().as_str() {
+            "totp" => OtpKind::Totp { period },
+            // the spec says the counter is required, but 0 is what everyone means by leaving it out
+            "hotp" => OtpKind::Hotp {
+                counter: counter.unwrap_or(0),
+            },
+            _ => return Err(invalid(&format!("unsupported type `{}`", kind))),
+        };
+        let otp = Otp {
+            kind,
+            secret: secret.ok_or_else(|| invalid("missing the secret"))?,
+            algorithm,
+            digits,
+            issuer: issuer.filter(|issuer| !issuer.is_empty()),
+            account,
+        };
+        otp.validate()?;
+        Ok(otp)
+    }
+
+    /// Checks that codes can be computed: 6 to 10 digits, and a period of at least a second.
+    pub fn validate(&self) -> Result<(), BackendError> {
+        if !(6..=10).contains(&self.digits) {
+            return Err(invalid("codes must have 6 to 10 digits"));
+        }
+        if self.kind == (OtpKind::Totp { period: 0 }) {
+            return Err(invalid("the period must be at least a second"));
+        }
+        if self.secret.is_empty() {
+            return Err(invalid("the secret is empty"));
+        }
+        Ok(())
+    }
+
+    /// Writes the `otpauth://` URI back out. Every parameter is written, even the defaults.
+    pub fn to_uri(&self) -> String {
+        let label = match (&self.issuer, &self.account) {
+            (Some(issuer), Some(account)) => format!("{}:{}", encode(issuer), encode(account)),
+            (Some(issuer), None) => format!("{}:", encode(issuer)),
+            (None, Some(account)) => encode(account),
+            (None, None) => String::new(),
+        };
+        let algorithm = match self.algorithm {
+            OtpAlgorithm::Sha1 => "SHA1",
+            OtpAlgorithm::Sha256 => "SHA256",
+            OtpAlgorithm::Sha512 => "SHA512",
+        };
+        let (kind, counter) = match self.kind {
+            OtpKind::Totp { period } => ("totp", format!("period={}", period)),
+            OtpKind::Hotp { counter } => ("hotp", format!("counter={}", counter)),
+        };
+        let mut uri = format!(
+            "otpauth://{}/{}?secret={}",
+            kind,
+            label,
+            BASE32_NOPAD.encode(&self.secret)
+        );
+        if let Some(issuer) = &self.issuer {
+            uri.push_str(&format!("&issuer={}", encode(issuer)));
+        }
+        uri.push_str(&format!(
+            "&algorithm={}&digits={}&{}",
+            algorithm, self.digits, counter
+        ));
+        uri
+    }
+
+    /// Generates the code for the time `now`, in seconds since the Unix epoch (for TOTP),
+    /// or for the current counter (for HOTP, which the caller has to advance, see `next_code`).
+    pub fn code_at(&self, now: i64) -> OtpCode {
+        let now = now.max(0) as u64;
+        let (counter, remaining, period) = match self.kind {
+            OtpKind::Totp { period } => (now / period, Some(period - now % period), Some(period)),
+            OtpKind::Hotp { counter } => (counter, None, None),
+        };
+        OtpCode {
+            code: hotp(&self.secret, counter, self.digits, self.algorithm),
+            remaining,
+            period,
+            counter: matches!(self.kind, OtpKind::Hotp { .. }).then_some(counter),
+            issuer: self.issuer.clone(),
+            account: self.account.clone(),
+        }
+    }
+}
+
+fn mac(algorithm: OtpAlgorithm, secret: &[u8], message: &[u8]) -> Vec<u8> {
+    // HMAC takes keys of any length
+    match algorithm {
+        OtpAlgorithm::Sha1 => {
+            let mut mac = <Hmac<Sha1> as Mac>::new_from_slice(secret).unwrap();
+            mac.update(message);
+            mac.finalize().into_bytes().to_vec()
+        }
+        OtpAlgorithm::Sha256 => {
+            let mut mac = <Hmac<Sha256> as Mac>::new_from_slice(secret).unwrap();
+            mac.update(message);
+            mac.finalize().into_bytes().to_vec()
+        }
+        OtpAlgorithm::Sha512 => {
+            let mut mac = <Hmac<Sha512> as Mac>::new_from_slice(secret).unwrap();
+            mac.update(message);
+            mac.finalize().into_bytes().to_vec()
+        }
+    }
+}
+
+/// Computes an HOTP code (RFC 4226).
+///
+/// # Arguments
+///
+/// - `secret` - the shared secret, decoded.
+/// - `counter` - the moving factor: a counter for HOTP, or the number of periods since the epoch for TOTP.
+/// - `digits` - how long the code is.
+/// - `algorithm` - the hash function to use.
+pub fn hotp(secret: &[u8], counter: u64, digits: u32, algorithm: OtpAlgorithm) -> String {
+    let hash = mac(algorithm, secret, &counter.to_be_bytes());
+    // "dynamic truncation": the last nibble picks which 4 bytes to use
+    let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+    let binary = u32::from_be_bytes([
+        hash[offset] & 0x7f,
+        hash[offset + 1],
+        hash[offset + 2],
+        hash[offset + 3],
+    ]);
+    let code = binary as u64 % 10u64.pow(digits);
+    format!("{:0width$}", code, width = digits as usize)
+}
+
+/// Computes a TOTP code (RFC 6238).
+///
+/// # Arguments
+///
+/// - `secret` - the shared secret, decoded.
+/// - `now` - the time, in seconds since the Unix epoch.
+/// - `period` - how many seconds each code is valid for.
+/// - `digits` - how long the code is.
+/// - `algorithm` - the hash function to use.
+pub fn totp(secret: &[u8], now: u64, period: u64, digits: u32, algorithm: OtpAlgorithm) -> String {
+    hotp(secret, now / period, digits, algorithm)
+}
+
+/// Generates the current code of a password's OTP field, advancing and saving the HOTP counter.
+/// Runs inside a transaction. Returns `None` if the password doesn't exist or has no OTP field.
+/// # Arguments
+///
+/// - `connection` - a reference to a `rusqlite::Connection`, which may be to a file or in memory.
+/// - `key` - the vault key of the unlocked session, see `db_ops::unlock_vault_key`.
+/// - `password_name` - a string slice that holds the name of the password.
+///
+pub fn next_code(
+    connection: &Connection,
+    key: &[u8],
+    password_name: &str,
+) -> Result<Option<OtpCode>, BackendError> {
+    let transaction = connection.unchecked_transaction()?;
+    let password = match crud_operations::read_password_info(&transaction, password_name, key)? {
+        Some(password) => password,
+        None => return Ok(None),
+    };
+    let mut otp = match &password.otp {
+        Some(uri) => Otp::parse_uri(uri)?,
+        None => return Ok(None),
+    };
+    let code = otp.code_at(clock::now());
+    if let OtpKind::Hotp { counter } = otp.kind {
+        otp.kind = OtpKind::Hotp {
+            counter: counter
+                .checked_add(1)
+                .ok_or_else(|| invalid("the counter can't go any higher"))?,
+        };
+        store_uri(&transaction, key, &password, &otp.to_uri())?;
+    }
+    transaction.commit()?;
+    Ok(Some(code))
+}
+
+// writes the OTP field directly, leaving its history and timestamp alone
+fn store_uri(
+    connection: &Connection,
+    key: &[u8],
+    password: &PasswordInfo,
+    uri: &str,
+) -> Result<usize, BackendError> {
+    let aad = associated_data(&password.name, &PasswordField::Otp);
+    let encrypted = encrypt_password_field(uri, &aad, &gen_cipher(key, &password.name));
+    Ok(connection.execute(
+        "update PasswordInfo set otp = ?1 where id = ?2",
+        (encrypted, password.id),
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{hotp, totp, Otp, OtpAlgorithm, OtpKind};
+    use crate::{
+        clock, db_ops::crud_operations, error::BackendError, password::PasswordField, test_utils,
+    };
+
+    #[test]
+    fn rfc4226() {
+        let secret = b"12345678901234567890";
+        let expected = [
+            "755224", "287082", "359152", "969429", "338314", "254676", "287922", "162583",
+            "399871", "520489",
+        ];
+        for (counter, code) in expected.iter().enumerate() {
+            assert_eq!(hotp(secret, counter as u64, 6, OtpAlgorithm::Sha1), *code);
+        }
+    }
+
+    #[test]
+    fn rfc6238() {
+        let sha1 = b"12345678901234567890".as_ref();
+        let sha256 = b"12345678901234567890123456789012".as_ref();
+        let sha512 = b"1234567890123456789012345678901234567890123456789012345678901234".as_ref();
+        let expected = [
+            (59, "94287082", "46119246", "90693936"),
+            (1111111109, "07081804", "68084774", "25091201"),
+            (1111111111, "14050471", "67062674", "99943326"),
+            (1234567890, "89005924", "91819424", "93441116"),
+            (2000000000, "69279037", "90698825", "38618901"),
+            (20000000000, "65353130", "77737706", "47863826"),
+        ];
+        for (time, code1, code256, code512) in expected {
+            assert_eq!(totp(sha1, time, 30, 8, OtpAlgorithm::Sha1), code1);
+            assert_eq!(totp(sha256, time, 30, 8, OtpAlgorithm::Sha256), code256);
+            assert_eq!(totp(sha512, time, 30, 8, OtpAlgorithm::Sha512), code512);
+        }
+    }
+
+    #[test]
+    fn parse_uri() {
+        let otp = Otp::parse_uri(
+            "otpauth://totp/ACME%20Co:john.doe@email.com?secret=HXDMVJECJJWSRB3HWIZR4IFUGFTMXBOZ&issuer=ACME%20Co&algorithm=SHA256&digits=8&period=60",
+        )
+        .unwrap();
+        assert_eq!(otp.kind, OtpKind::Totp { period: 60 });
+        assert_eq!(otp.algorithm, OtpAlgorithm::Sha256);
+        assert_eq!(otp.digits, 8);
+        assert_eq!(otp.issuer.as_deref(), Some("ACME Co"));
+        assert_eq!(otp.account.as_deref(), Some("john.doe@email.com"));
+        assert_eq!(otp.secret.len(), 20);
+        // and back
+        assert_eq!(Otp::parse_uri(&otp.to_uri()).unwrap(), otp);
+
+        // the defaults, and a lowercase secret with padding
+        let otp = Otp::parse_uri("otpauth://hotp/me?secret=gezdgnbvgy3tqojq&counter=5").unwrap();
+        assert_eq!(otp.kind, OtpKind::Hotp { counter: 5 });
+        assert_eq!(otp.algorithm, OtpAlgorithm::Sha1);
+        assert_eq!(otp.digits, 6);
+        assert_eq!(otp.issuer, None);
+        assert_eq!(otp.secret, b"1234567890");
+
+        for uri in [
+            "https://example.com",
+            "otpauth://totp/me",
+            "otpauth://totp/me?secret=not*base32",
+            "otpauth://totp/me?secret=GEZDGNBV&digits=4",
+            // 2^32 + 6, which would be 6 if it were cut down to 32 bits
+            "otpauth://totp/me?secret=GEZDGNBV&digits=4294967302",
+            "otpauth://hotp/me?secret=GEZDGNBV&counter=18446744073709551616",
+            "otpauth://totp/me?secret=GEZDGNBV&period=0",
+            "otpauth://totp/me?secret=GEZDGNBV&algorithm=MD5",
+            "otpauth://motp/me?secret=GEZDGNBV",
+        ] {
+            assert!(
+                matches!(Otp::parse_uri(uri), Err(BackendError::InvalidOtp(_))),
+                "{}",
+                uri
+            );
+        }
+    }
+
+    #[test]
+    fn remaining() {
+        let otp =
+            Otp::parse_uri("otpauth://totp/me?secret=GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ").unwrap();
+        let code = otp.code_at(59);
+        assert_eq!(code.code, "287082");
+        assert_eq!(code.remaining, Some(1));
+        assert_eq!(otp.code_at(60).remaining, Some(30));
+    }
+
+    #[test]
+    fn next_code() {
+        let (connection, key) = test_utils::vault();
+        // "12345678901234567890" in base32
+        let secret = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+        crud_operations::insert_data(
+            &connection,
+            "GitHub",
+            &key,
+            PasswordField::Otp,
+            &format!("otpauth://totp/GitHub:me?secret={}&digits=8", secret),
+        )
+        .unwrap();
+        crud_operations::insert_data(
+            &connection,
+            "bank",
+            &key,
+            PasswordField::Otp,
+            &format!("otpauth://hotp/bank?secret={}&counter=0", secret),
+        )
+        .unwrap();
+        // only valid URIs are stored
+        assert!(crud_operations::insert_data(
+            &connection,
+            "bank",
+            &key,
+            PasswordField::Otp,
+            "not a uri",
+        )
+        .is_err());
+
+        clock::set(Some(1111111109));
+        let code = super::next_code(&connection, &key, "github")
+            .unwrap()
+            .unwrap();
+        assert_eq!(code.code, "07081804");
+        assert_eq!(code.issuer.as_deref(), Some("GitHub"));
+
+        // the counter moves on every time, and is kept
+        for expected in ["755224", "287082", "359152"] {
+            let code = super::next_code(&connection, &key, "bank")
+                .unwrap()
+                .unwrap();
+            assert_eq!(code.code, expected);
+        }
+        let password = crud_operations::read_password_info(&connection, "bank", &key)
+            .unwrap()
+            .unwrap();
+        assert!(password.otp.unwrap().contains("counter=3"));
+        // without going into the history
+        assert!(
+            crate::history::list_history(&connection, &key, "bank", None)
+                .unwrap()
+                .is_empty()
+        );
+
+        // at the last counter there's nothing to move on to, so no code is handed out
+        crud_operations::insert_data(
+            &connection,
+            "bank",
+            &key,
+            PasswordField::Otp,
+            &format!("otpauth://hotp/bank?secret={}&counter={}", secret, u64::MAX),
+        )
+        .unwrap();
+        assert!(matches!(
+            super::next_code(&connection, &key, "bank"),
+            Err(BackendError::InvalidOtp(_))
+        ));
+
+        crud_operations::insert_data(&connection, "plain", &key, PasswordField::Email, "me")
+            .unwrap();
+        assert_eq!(super::next_code(&connection, &key, "plain").unwrap(), None);
+        assert_eq!(
+            super::next_code(&connection, &key, "nothing").unwrap(),
+            None
+        );
+        clock::set(None);
+    }
+}
@@ -1,12 +1,16 @@
 use core::fmt;
-use std::fmt::Display;
+use std::{fmt::Display, str::FromStr};
 
-#[derive(Debug)]
+use data_encoding::BASE64;
+
+use crate::{crypto::EncryptedField, error::BackendError};
+
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
 /// A struct to represent a password
 pub struct PasswordInfo {
     /// Password ID, auto-incremented by SQLite database. do not set this yourself!
     pub id: i32,
-    /// The password name. Must be unique or will fail SQLite constraints.
+    /// The password name. Must be unique (ignoring case and surrounding whitespace) or will fail SQLite constraints.
     pub name: String,
     /// Optional email field.
     pub email: Option<String>,
@@ -16,6 +20,314 @@ pub struct PasswordInfo {
     pub notes: Option<String>,
     /// Optional password field.
     pub password: Option<String>,
+    /// Optional two-factor secret, as an `otpauth://` URI. See `otp`.
+    #[serde(default)]
+    pub otp: Option<String>,
+    /// Any number of custom fields, in the order they were added.
+    #[serde(default)]
+    pub custom_fields: Vec<CustomField>,
+    /// The folder the password is in, or `None` if it isn't in one. See `organize`.
+    #[serde(default)]
+    pub folder_id: Option<i32>,
+    /// The ids of the password's tags, see `organize::list_tags`.
+    #[serde(default)]
+    pub tag_ids: Vec<i32>,
+    /// When the password was created, in seconds since the Unix epoch. `None` for passwords from before timestamps were kept.
+    #[serde(default)]
+    pub created_at: Option<i64>,
+    /// When each field was last changed.
+    #[serde(default)]
+    pub updated_at: FieldTimestamps,
+    /// When the password was last read or marked as used, see `crud_operations::mark_used`.
+    #[serde(default)]
+    pub last_accessed_at: Option<i64>,
+    /// When the password was moved to the trash, or `None` if it isn't in the trash.
+    #[serde(default)]
+    pub trashed_at: Option<i64>,
+}
+/// When each field of a password was last changed, in seconds since the Unix epoch.
+/// `None` if the field was never set, or hasn't changed since before timestamps were kept.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub struct FieldTimestamps {
+    pub email: Option<i64>,
+    pub username: Option<i64>,
+    pub notes: Option<i64>,
+    pub password: Option<i64>,
+    #[serde(default)]
+    pub otp: Option<i64>,
+    /// When a custom field was last added, changed or deleted.
+    #[serde(default)]
+    pub custom_fields: Option<i64>,
+}
+
+impl FieldTimestamps {
+    /// When any of the fields was last changed.
+    pub fn latest(&self) -> Option<i64> {
+        [
+            self.email,
+            self.username,
+            self.notes,
+            self.password,
+            self.otp,
+            self.custom_fields,
+        ]
+        .into_iter()
+        .flatten()
+        .max()
+    }
+}
+/// A `PasswordInfo` as it is stored in the database, with its fields still encrypted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncryptedPasswordInfo {
+    pub id: i32,
+    /// The blind index of the name, see `crypto::name_index`.
+    pub name_index: Vec<u8>,
+    pub name: EncryptedField,
+    pub email: Option<EncryptedField>,
+    pub username: Option<EncryptedField>,
+    pub notes: Option<EncryptedField>,
+    pub password: Option<EncryptedField>,
+    pub otp: Option<EncryptedField>,
+    pub custom_fields: Vec<EncryptedCustomField>,
+    pub folder_id: Option<i32>,
+    pub tag_ids: Vec<i32>,
+    pub created_at: Option<i64>,
+    pub updated_at: FieldTimestamps,
+    pub last_accessed_at: Option<i64>,
+    pub trashed_at: Option<i64>,
+}
+/// The kind of value a custom field holds. It decides how the frontend shows and edits the value,
+/// which is always stored as a string.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum CustomFieldType {
+    Text,
+    /// Shown masked, like a password, e.g. a PIN or an API key.
+    Hidden,
+    Url,
+    /// `YYYY-MM-DD`.
+    Date,
+    /// `true` or `false`.
+    Boolean,
+    /// A file, base64 encoded. The label is the file name.
+    Attachment,
+}
+
+impl Display for CustomFieldType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let str = match self {
+            CustomFieldType::Text => "text",
+            CustomFieldType::Hidden => "hidden",
+            CustomFieldType::Url => "url",
+            CustomFieldType::Date => "date",
+            CustomFieldType::Boolean => "boolean",
+            CustomFieldType::Attachment => "attachment",
+        };
+        write!(f, "{}", str)
+    }
+}
+
+impl FromStr for CustomFieldType {
+    type Err = BackendError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "text" => Ok(CustomFieldType::Text),
+            "hidden" => Ok(CustomFieldType::Hidden),
+            "url" => Ok(CustomFieldType::Url),
+            "date" => Ok(CustomFieldType::Date),
+            "boolean" => Ok(CustomFieldType::Boolean),
+            "attachment" => Ok(CustomFieldType::Attachment),
+            _ => Err(BackendError::InvalidCustomField(format!(
+                "unknown type `{}`",
+                s
+            ))),
+        }
+    }
+}
+
+/// A custom field of a `PasswordInfo`, for anything that doesn't fit the fixed fields,
+/// e.g. an API key, a security question or an account number.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct CustomField {
+    /// Custom field ID, auto-incremented by SQLite database. Ignored when adding a field.
+    #[serde(default)]
+    pub id: i32,
+    pub label: String,
+    pub field_type: CustomFieldType,
+    pub value: String,
+}
+
+impl CustomField {
+    /// Checks that the value fits the field's type. Only dates, booleans and attachments have a format.
+    pub fn validate(&self) -> Result<(), BackendError> {
+        let valid = match self.field_type {
+            CustomFieldType::Boolean => matches!(self.value.as_str(), "true" | "false"),
+            CustomFieldType::Date => {
+                let bytes = self.value.as_bytes();
+                bytes.len() == 10
+                    && bytes.iter().enumerate().all(|(i, b)| match i {
+                        4 | 7 => *b == b'-',
+                        _ => b.is_ascii_digit(),
+                    })
+            }
+            CustomFieldType::Attachment => BASE64.decode(self.value.as_bytes()).is_ok(),
+            _ => true,
+        };
+        if !valid {
+            return Err(BackendError::InvalidCustomField(format!(
+                "`{}` is not a valid {} value",
+                self.value, self.field_type
+            )));
+        }
+        Ok(())
+    }
+}
+/// A `CustomField` as it is stored in the database, with its label and value still encrypted.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct EncryptedCustomField {
+    pub id: i32,
+    pub label: EncryptedField,
+    pub field_type: String,
+    pub value: EncryptedField,
+}
+/// A `PasswordInfo` as it is listed, e.g. in the sidebar: its name and which fields are set, but none of its secrets.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct PasswordSummary {
+    pub id: i32,
+    pub name: String,
+    /// The fields that have a value, in the order email, username, password, notes.
+    pub fields: Vec<PasswordField>,
+    pub folder_id: Option<i32>,
+    pub tag_ids: Vec<i32>,
+    pub created_at: Option<i64>,
+    /// When any of the fields was last changed, see `FieldTimestamps::latest`.
+    pub updated_at: Option<i64>,
+    pub last_accessed_at: Option<i64>,
+    pub trashed_at: Option<i64>,
+}
+/// Whether to list passwords that are in the trash.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TrashFilter {
+    #[default]
+    Exclude,
+    Include,
+    /// Only list the trash.
+    Only,
+}
+/// Which folder to list passwords from.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq)]
+pub enum FolderFilter {
+    /// Passwords that aren't in any folder.
+    Unfiled,
+    /// Passwords directly in the folder with this id.
+    Folder(i32),
+    /// Passwords in the folder with this id, or in any of its subfolders.
+    Subtree(i32),
+}
+/// A folder. Folders form a tree through `parent_id`.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct Folder {
+    pub id: i32,
+    /// The folder this one is in, or `None` for a top-level folder.
+    pub parent_id: Option<i32>,
+    pub name: String,
+    /// How many passwords are directly in this folder.
+    pub password_count: usize,
+}
+/// A tag. A password can have any number of tags.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct Tag {
+    pub id: i32,
+    pub name: String,
+    /// How many passwords have this tag.
+    pub password_count: usize,
+}
+/// How to match names when listing passwords. Matching ignores case.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub enum NameFilter {
+    Prefix(String),
+    Contains(String),
+}
+/// The order to list passwords in.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, Copy, PartialEq, Eq, Default)]
+pub enum SortOrder {
+    /// Alphabetically, ignoring case.
+    #[default]
+    NameAscending,
+    NameDescending,
+    /// Most recently created first.
+    Newest,
+    Oldest,
+    /// Most recently read or marked as used first. Passwords that never were come last.
+    RecentlyUsed,
+    /// Most recently changed first. Passwords that never were come last.
+    RecentlyUpdated,
+}
+/// Options for listing passwords. Every field may be left out, which lists everything alphabetically.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct ListOptions {
+    pub filter: Option<NameFilter>,
+    pub folder: Option<FolderFilter>,
+    /// Only list passwords with the tag with this id.
+    pub tag_id: Option<i32>,
+    /// Only list passwords that haven't changed since this time (or ever), to find stale ones.
+    pub updated_before: Option<i64>,
+    pub trash: TrashFilter,
+    pub sort: SortOrder,
+    /// How many matching passwords to skip.
+    pub offset: usize,
+    /// The most passwords to return, or all of them if `None`.
+    pub limit: Option<usize>,
+}
+/// One page of a listing.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct PasswordPage {
+    pub items: Vec<PasswordSummary>,
+    /// How many passwords matched the filter in total, across every page.
+    pub total: usize,
+}
+/// Options for searching decrypted passwords.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq, Default)]
+#[serde(default)]
+pub struct SearchOptions {
+    /// Whether to search (and return) the password field too. Off unless asked for.
+    pub include_password: bool,
+    /// The most results to return, or all of them if `None`.
+    pub limit: Option<usize>,
+}
+/// Where in a password a search matched.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub enum SearchField {
+    Name,
+    Field(PasswordField),
+}
+/// One field of a password that matched a search.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct SearchHighlight {
+    pub field: SearchField,
+    /// The decrypted value of the field.
+    pub text: String,
+    /// The positions (in characters) of `text` that matched, for highlighting.
+    pub indices: Vec<usize>,
+    pub score: i64,
+}
+/// A password that matched a search, with every field that matched.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct SearchResult {
+    pub id: i32,
+    pub name: String,
+    /// The best score of any of the highlights. Results are sorted by it, best first.
+    pub score: i64,
+    pub highlights: Vec<SearchHighlight>,
+}
+/// A previous value of one of a password's fields, see `history`.
+#[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub id: i32,
+    pub field: PasswordField,
+    pub value: String,
+    /// When the value was replaced, in seconds since the Unix epoch.
+    pub replaced_at: i64,
 }
 /// Enum containing different fields on `PasswordInfo`. Primarily used in inserting or updating data.
 #[derive(Debug, serde::Serialize, serde::Deserialize, Clone, PartialEq, Eq)]
@@ -24,6 +336,8 @@ pub enum PasswordField {
     Username,
     Notes,
     Password,
+    /// An `otpauth://` URI, see `otp`.
+    Otp,
 }
 
 impl Display for PasswordField {
@@ -33,16 +347,74 @@ impl Display for PasswordField {
             PasswordField::Username => "username",
             PasswordField::Password => "password",
             PasswordField::Notes => "notes",
+            PasswordField::Otp => "otp",
         };
         write!(f, "{}", str)
     }
 }
 
+impl FromStr for PasswordField {
+    type Err = BackendError;
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "email" => Ok(PasswordField::Email),
+            "username" => Ok(PasswordField::Username),
+            "password" => Ok(PasswordField::Password),
+            "notes" => Ok(PasswordField::Notes),
+            "otp" => Ok(PasswordField::Otp),
+            _ => Err(BackendError::UnknownField(s.to_string())),
+        }
+    }
+}
+
 // is this even necessary?
 #[cfg(test)]
 mod tests {
 
-    use crate::password::PasswordField;
+    use crate::password::{CustomField, CustomFieldType, PasswordField};
+
+    #[test]
+    fn custom_field_type() {
+        for field_type in [
+            CustomFieldType::Text,
+            CustomFieldType::Hidden,
+            CustomFieldType::Url,
+            CustomFieldType::Date,
+            CustomFieldType::Boolean,
+            CustomFieldType::Attachment,
+        ] {
+            assert_eq!(
+                field_type.to_string().parse::<CustomFieldType>().unwrap(),
+                field_type
+            );
+        }
+        assert!("color".parse::<CustomFieldType>().is_err());
+    }
+
+    #[test]
+    fn validate_custom_field() {
+        let field = |field_type, value: &str| CustomField {
+            id: 0,
+            label: "label".to_string(),
+            field_type,
+            value: value.to_string(),
+        };
+        assert!(field(CustomFieldType::Boolean, "true").validate().is_ok());
+        assert!(field(CustomFieldType::Boolean, "yes").validate().is_err());
+        assert!(field(CustomFieldType::Date, "2023-11-05")
+            .validate()
+            .is_ok());
+        assert!(field(CustomFieldType::Date, "5/11/2023")
+            .validate()
+            .is_err());
+        assert!(field(CustomFieldType::Hidden, "1234").validate().is_ok());
+        assert!(field(CustomFieldType::Attachment, "MTIzNA==")
+            .validate()
+            .is_ok());
+        assert!(field(CustomFieldType::Attachment, "not base64")
+            .validate()
+            .is_err());
+    }
 
     #[test]
     fn test_display() {
@@ -50,5 +422,10 @@ mod tests {
         assert_eq!(PasswordField::Username.to_string(), "username");
         assert_eq!(PasswordField::Password.to_string(), "password");
         assert_eq!(PasswordField::Notes.to_string(), "notes");
+        assert_eq!(
+            "notes".parse::<PasswordField>().unwrap(),
+            PasswordField::Notes
+        );
+        assert!("name".parse::<PasswordField>().is_err());
     }
 }
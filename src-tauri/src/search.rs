@@ -0,0 +1,135 @@
+// fuzzy matching for searching decrypted passwords, see `crud_operations::search_password_infos`.
+// this is a small fzf-style scorer: every character of the pattern has to appear in the text, in order,
+// and matches that are consecutive or start a word score higher.
+
+/// The result of matching a pattern against some text.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FuzzyMatch {
+    /// Higher is better.
+    pub score: i64,
+    /// The positions (in `char`s, not bytes) of the matched characters in the text.
+    pub indices: Vec<usize>,
+}
+
+const MATCH: i64 = 16;
+const CONSECUTIVE: i64 = 16;
+const WORD_START: i64 = 10;
+const FIRST_CHAR: i64 = 8;
+const GAP: i64 = 1;
+
+/// Matches `pattern` against `text`, ignoring case. Returns `None` if some character of the pattern can't be found
+/// in order, or if the pattern is empty.
+///
+/// # Arguments
+///
+/// - `pattern` - what the user typed.
+/// - `text` - the text to search in.
+pub fn fuzzy_match(pattern: &str, text: &str) -> Option<FuzzyMatch> {
+    let pattern: Vec<char> = pattern.chars().flat_map(char::to_lowercase).collect();
+    let original: Vec<char> = text.chars().collect();
+    // lowercasing one char can give several (e.g. `İ`), so the text is lowercased the same way as the pattern
+    // and `positions` maps every lowercase char back to the char of `original` it came from
+    let (text, positions): (Vec<char>, Vec<usize>) = original
+        .iter()
+        .enumerate()
+        .flat_map(|(i, c)| c.to_lowercase().map(move |lower| (lower, i)))
+        .unzip();
+    let first = *pattern.first()?;
+
+    // a greedy match can miss a better alignment (e.g. "ab" in "a-long-way-to-ab"),
+    // so try one starting from every occurrence of the first character and keep the best
+    let best = (0..text.len())
+        .filter(|&start| text[start] == first)
+        .filter_map(|start| match_from(&pattern, &text, &original, &positions, start))
+        .max_by_key(|m| (m.score, std::cmp::Reverse(m.indices[0])))?;
+    let mut indices: Vec<usize> = best.indices.iter().map(|&i| positions[i]).collect();
+    indices.dedup();
+    Some(FuzzyMatch { indices, ..best })
+}
+
+// the indices of the match are into the lowercase `text`
+fn match_from(
+    pattern: &[char],
+    text: &[char],
+    original: &[char],
+    positions: &[usize],
+    start: usize,
+) -> Option<FuzzyMatch> {
+    let mut indices = Vec::with_capacity(pattern.len());
+    let mut position = start;
+    for c in pattern {
+        let found = (position..text.len()).find(|&i| text[i] == *c)?;
+        indices.push(found);
+        position = found + 1;
+    }
+
+    let mut score = 0;
+    for (n, &i) in indices.iter().enumerate() {
+        score += MATCH;
+        if i == 0 {
+            score += FIRST_CHAR;
+        }
+        // only the first of the chars one char lowercases to can start a word
+        if (i == 0 || positions[i - 1] != positions[i]) && is_word_start(original, positions[i]) {
+            score += WORD_START;
+        }
+        if n > 0 {
+            let previous = indices[n - 1];
+            if i == previous + 1 {
+                score += CONSECUTIVE;
+            } else {
+                score -= GAP * (i - previous - 1) as i64;
+            }
+        }
+    }
+    // matching further into the text counts for a little less
+    score -= GAP * indices[0].min(8) as i64;
+    Some(FuzzyMatch { score, indices })
+}
+
+// the start of the text, after a separator, or an upper case letter after a lower case one ("camelCase")
+fn is_word_start(text: &[char], i: usize) -> bool {
+    if i == 0 {
+        return true;
+    }
+    let (previous, current) = (text[i - 1], text[i]);
+    !previous.is_alphanumeric() || (previous.is_lowercase() && current.is_uppercase())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fuzzy_match;
+
+    #[test]
+    fn matches() {
+        let m = fuzzy_match("gh", "GitHub").unwrap();
+        assert_eq!(m.indices, vec![0, 3]);
+        // in order, and every character has to be there
+        assert!(fuzzy_match("hg", "GitHub").is_none());
+        assert!(fuzzy_match("ghx", "GitHub").is_none());
+        assert!(fuzzy_match("", "GitHub").is_none());
+        // indices are in chars
+        assert_eq!(fuzzy_match("ü", "grüße").unwrap().indices, vec![2]);
+    }
+
+    #[test]
+    fn multi_char_lowercase() {
+        // `İ` lowercases to `i` and a combining dot, on both sides
+        assert_eq!(fuzzy_match("İ", "İzmir").unwrap().indices, vec![0]);
+        assert_eq!(fuzzy_match("izm", "İzmir").unwrap().indices, vec![0, 1, 2]);
+        // and the indices still point into the original text
+        assert_eq!(fuzzy_match("mir", "İzmir").unwrap().indices, vec![2, 3, 4]);
+    }
+
+    #[test]
+    fn ranking() {
+        let score = |pattern, text| fuzzy_match(pattern, text).unwrap().score;
+        // consecutive beats scattered
+        assert!(score("user", "cool_user1") > score("user", "u_s_e_r"));
+        // word starts beat the middle of a word
+        assert!(score("mail", "usermail.com") < score("mail", "user@mail.com"));
+        // the best alignment is found, not just the first one
+        let m = fuzzy_match("ab", "a-long-way-to-ab").unwrap();
+        assert_eq!(m.indices, vec![14, 15]);
+    }
+}
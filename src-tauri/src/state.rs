@@ -1,14 +1,208 @@
 // source: https://github.com/RandomEngy/tauri-sqlite
 // i tried to comment this code to help me better understand everything that's happening. so excuse the messy comments.
 
+use std::time::{Duration, Instant};
+
 use rusqlite::Connection;
 use tauri::{AppHandle, Manager, State};
+use zeroize::Zeroize;
+
+use crate::{
+    db_ops::{authenticate, migrate_associated_data, migrate_names, purge_trash, unlock_vault_key},
+    error::BackendError,
+    password::PasswordField,
+};
 
 // i'm not very good at rust, so this helped me understand mutex's (is that the right plural?)
 // https://fongyoong.github.io/easy_rust/Chapter_43.html
 pub struct AppState {
     // Question: why is the connection optional?
     pub connection: std::sync::Mutex<Option<Connection>>,
+    /// The unlocked session, `None` while the vault is locked.
+    pub session: std::sync::Mutex<Option<Session>>,
+    /// How long a session may live, see `SessionConfig`.
+    pub session_config: std::sync::Mutex<SessionConfig>,
+}
+
+/// The name of the event emitted to the frontend whenever the vault locks. The payload is a `LockReason`.
+pub const VAULT_LOCKED_EVENT: &str = "vault-locked";
+
+/// Limits on how long a session may stay unlocked.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SessionConfig {
+    /// The session locks after this long without being used.
+    pub idle_timeout: Duration,
+    /// The session locks this long after it was unlocked, used or not.
+    pub max_age: Duration,
+}
+
+/// The shortest timeout `SessionConfig::new` accepts, so a typo can't lock the vault every second.
+pub const MIN_SESSION_TIMEOUT: Duration = Duration::from_secs(30);
+
+impl SessionConfig {
+    /// Checks that both timeouts are at least `MIN_SESSION_TIMEOUT`, and that the session can't hit
+    /// `max_age` before it could go idle.
+    pub fn new(idle_timeout: Duration, max_age: Duration) -> Result<Self, BackendError> {
+        if idle_timeout < MIN_SESSION_TIMEOUT || max_age < MIN_SESSION_TIMEOUT {
+            return Err(BackendError::InvalidSessionTimeouts(format!(
+                "timeouts must be at least {} seconds",
+                MIN_SESSION_TIMEOUT.as_secs()
+            )));
+        }
+        if max_age < idle_timeout {
+            return Err(BackendError::InvalidSessionTimeouts(
+                "the maximum age can't be shorter than the idle timeout".to_string(),
+            ));
+        }
+        Ok(SessionConfig {
+            idle_timeout,
+            max_age,
+        })
+    }
+}
+
+impl Default for SessionConfig {
+    fn default() -> Self {
+        SessionConfig {
+            idle_timeout: Duration::from_secs(5 * 60),
+            max_age: Duration::from_secs(8 * 60 * 60),
+        }
+    }
+}
+
+/// Why the vault was locked. Sent as the payload of `VAULT_LOCKED_EVENT`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize)]
+pub enum LockReason {
+    /// The user (or the frontend) asked for it.
+    Manual,
+    /// The session went unused for longer than `SessionConfig::idle_timeout`.
+    Idle,
+    /// The session is older than `SessionConfig::max_age`.
+    MaxAge,
+}
+
+/// An unlocked vault. This holds the vault key (see `db_ops::unlock_vault_key`), never the master password itself.
+/// The key is zeroed when the session is dropped.
+pub struct Session {
+    key: [u8; 32],
+    unlocked_at: Instant,
+    last_used: Instant,
+}
+
+impl Session {
+    pub fn new(key: [u8; 32]) -> Self {
+        let now = Instant::now();
+        Session {
+            key,
+            unlocked_at: now,
+            last_used: now,
+        }
+    }
+    /// Authenticates `master` and unlocks the vault key with it.
+    /// Vaults that still contain plaintext names or fields without associated data are migrated on the way
+    /// (see `db_ops::migrate_names` and `db_ops::migrate_associated_data`), and the trash is purged (see `db_ops::purge_trash`).
+    /// Returns `None` if the password is wrong.
+    pub fn open(connection: &Connection, master: &str) -> Result<Option<Self>, BackendError> {
+        if !authenticate(connection, master, PasswordField::Password)? {
+            return Ok(None);
+        }
+        let session = Session::new(unlock_vault_key(connection, master)?);
+        migrate_names(connection, session.key())?;
+        migrate_associated_data(connection, session.key())?;
+        purge_trash(connection)?;
+        Ok(Some(session))
+    }
+    /// The vault key, see `db_ops::unlock_vault_key`.
+    pub fn key(&self) -> &[u8; 32] {
+        &self.key
+    }
+    /// Checks whether the session has outlived `config` at the instant `now`, and if so, why.
+    pub fn expiry(&self, config: &SessionConfig, now: Instant) -> Option<LockReason> {
+        if now.saturating_duration_since(self.unlocked_at) >= config.max_age {
+            Some(LockReason::MaxAge)
+        } else if now.saturating_duration_since(self.last_used) >= config.idle_timeout {
+            Some(LockReason::Idle)
+        } else {
+            None
+        }
+    }
+    /// Marks the session as used at the instant `now`, resetting the idle timer.
+    pub fn touch(&mut self, now: Instant) {
+        self.last_used = now;
+    }
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        self.key.zeroize();
+    }
+}
+
+impl AppState {
+    pub fn new() -> Self {
+        AppState {
+            connection: Default::default(),
+            session: Default::default(),
+            session_config: Default::default(),
+        }
+    }
+
+    /// Starts a new session, replacing the current one if there is one.
+    pub fn unlock(&self, session: Session) {
+        *self.session.lock().unwrap() = Some(session);
+    }
+
+    /// Ends the current session, if there is one, wiping its key.
+    /// Returns `true` if the vault was unlocked.
+    pub fn lock(&self) -> bool {
+        self.session.lock().unwrap().take().is_some()
+    }
+
+    /// Locks the vault if the current session has expired at the instant `now`.
+    /// Returns the reason if the vault was locked by this call.
+    pub fn expire(&self, now: Instant) -> Option<LockReason> {
+        let config = *self.session_config.lock().unwrap();
+        let mut session_guard = self.session.lock().unwrap();
+        let reason = session_guard.as_ref()?.expiry(&config, now)?;
+        *session_guard = None;
+        Some(reason)
+    }
+
+    /// Runs `operation` with the key of the current session, failing with `BackendError::Locked` if the vault is locked
+    /// or the session has expired. Using the key resets the idle timer.
+    pub fn with_key<F, TResult>(&self, operation: F) -> Result<TResult, BackendError>
+    where
+        F: FnOnce(&[u8; 32]) -> Result<TResult, BackendError>,
+    {
+        let config = *self.session_config.lock().unwrap();
+        let now = Instant::now();
+        let mut session_guard = self.session.lock().unwrap();
+        let session = session_guard.as_mut().ok_or(BackendError::Locked)?;
+        if session.expiry(&config, now).is_some() {
+            *session_guard = None;
+            return Err(BackendError::Locked);
+        }
+        session.touch(now);
+        operation(session.key())
+    }
+}
+
+impl Default for AppState {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Spawns a thread that locks the vault once the session expires, emitting `VAULT_LOCKED_EVENT` when it does.
+pub fn watch_session(app_handle: AppHandle) {
+    std::thread::spawn(move || loop {
+        std::thread::sleep(Duration::from_secs(1));
+        let app_state: State<AppState> = app_handle.state();
+        if let Some(reason) = app_state.expire(Instant::now()) {
+            // if the window is gone there's nobody to tell
+            let _ = app_handle.emit_all(VAULT_LOCKED_EVENT, reason);
+        }
+    });
 }
 
 // my big question is why we need the mutable functions?
@@ -52,3 +246,133 @@ impl ServiceAccess for AppHandle {
         operation(db)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use rusqlite::Connection;
+
+    use std::time::{Duration, Instant};
+
+    use super::{AppState, LockReason, Session, SessionConfig};
+    use crate::{db_ops, error::BackendError};
+
+    // a vault with a master password, still locked
+    fn locked_state() -> (AppState, Connection) {
+        let mut connection = Connection::open_in_memory().unwrap();
+        db_ops::create_table(&connection).unwrap();
+        db_ops::register_master(&mut connection, "mymasterpassword", "abcd").unwrap();
+        (AppState::new(), connection)
+    }
+
+    fn unlocked_state() -> (AppState, Connection) {
+        let (state, connection) = locked_state();
+        state.unlock(
+            Session::open(&connection, "mymasterpassword")
+                .unwrap()
+                .unwrap(),
+        );
+        (state, connection)
+    }
+
+    #[test]
+    fn unlock_and_lock() {
+        let (state, connection) = locked_state();
+        // locked to begin with
+        assert!(matches!(
+            state.with_key(|_| Ok(())),
+            Err(BackendError::Locked)
+        ));
+
+        assert!(Session::open(&connection, "random_guess")
+            .unwrap()
+            .is_none());
+
+        let session = Session::open(&connection, "mymasterpassword")
+            .unwrap()
+            .unwrap();
+        state.unlock(session);
+        let key = state.with_key(|key| Ok(*key)).unwrap();
+        assert_eq!(
+            key,
+            db_ops::unlock_vault_key(&connection, "mymasterpassword").unwrap()
+        );
+
+        assert!(state.lock());
+        assert!(matches!(
+            state.with_key(|_| Ok(())),
+            Err(BackendError::Locked)
+        ));
+        // locking twice is harmless
+        assert!(!state.lock());
+    }
+
+    #[test]
+    fn expiry() {
+        let config = SessionConfig {
+            idle_timeout: Duration::from_secs(60),
+            max_age: Duration::from_secs(600),
+        };
+        let mut session = Session::new([0; 32]);
+        let start = Instant::now();
+
+        assert_eq!(session.expiry(&config, start), None);
+        assert_eq!(
+            session.expiry(&config, start + Duration::from_secs(61)),
+            Some(LockReason::Idle)
+        );
+
+        // using the session keeps it alive...
+        for minutes in 1..10 {
+            let now = start + Duration::from_secs(minutes * 59);
+            assert_eq!(session.expiry(&config, now), None);
+            session.touch(now);
+        }
+        // ...but only up to the maximum age
+        assert_eq!(
+            session.expiry(&config, start + Duration::from_secs(600)),
+            Some(LockReason::MaxAge)
+        );
+    }
+
+    #[test]
+    fn session_config() {
+        let minutes = |n: u64| Duration::from_secs(n * 60);
+        assert_eq!(
+            SessionConfig::new(minutes(5), minutes(60)).unwrap(),
+            SessionConfig {
+                idle_timeout: minutes(5),
+                max_age: minutes(60),
+            }
+        );
+        assert!(SessionConfig::new(minutes(5), minutes(5)).is_ok());
+        for (idle_timeout, max_age) in [
+            (Duration::ZERO, minutes(60)),
+            (Duration::from_secs(29), minutes(60)),
+            (minutes(5), Duration::from_secs(1)),
+            (minutes(60), minutes(5)),
+        ] {
+            assert!(matches!(
+                SessionConfig::new(idle_timeout, max_age),
+                Err(BackendError::InvalidSessionTimeouts(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn expire() {
+        let (state, _connection) = unlocked_state();
+
+        // nothing happens before the timeout
+        assert_eq!(state.expire(Instant::now()), None);
+        assert!(state.with_key(|_| Ok(())).is_ok());
+
+        let later = Instant::now() + SessionConfig::default().idle_timeout;
+        assert_eq!(state.expire(later), Some(LockReason::Idle));
+        assert!(matches!(
+            state.with_key(|_| Ok(())),
+            Err(BackendError::Locked)
+        ));
+        // already locked, so there's nothing left to expire
+        assert_eq!(state.expire(later), None);
+    }
+}
@@ -0,0 +1,31 @@
+//! Fixtures shared by the unit tests.
+use rusqlite::Connection;
+
+use crate::{
+    crypto::generate_vault_key,
+    db_ops::{self, crud_operations},
+    password::PasswordField,
+};
+
+/// Opens an empty vault in memory, along with a vault key to use on it.
+pub fn vault() -> (Connection, [u8; 32]) {
+    let connection = Connection::open_in_memory().unwrap();
+    db_ops::create_table(&connection).unwrap();
+    (connection, generate_vault_key())
+}
+
+/// Like `vault`, with a password called each of `names`.
+pub fn vault_with(names: &[&str]) -> (Connection, [u8; 32]) {
+    let (connection, key) = vault();
+    for name in names {
+        crud_operations::insert_data(
+            &connection,
+            name,
+            &key,
+            PasswordField::Password,
+            "supersecret",
+        )
+        .unwrap();
+    }
+    (connection, key)
+}